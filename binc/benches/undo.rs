@@ -0,0 +1,95 @@
+use binc::attributes::AttributeValue;
+use binc::document::Document;
+use binc::journal::Journal;
+use binc::node_id::NodeId;
+use binc::operation::Operation;
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use std::hint::black_box;
+
+/// A journal with `operations` operations, like a tree of notes that is edited a lot
+fn edited_journal(operations: usize) -> Journal {
+    let mut journal = Journal::new();
+    let mut nodes = 0;
+    while journal.operations.len() < operations {
+        nodes += 1;
+        let id = NodeId::new(nodes);
+        journal.add_operation(Operation::AddNode {
+            id,
+            node_type: 0,
+            parent: NodeId::ROOT_NODE,
+            index_in_parent: 0,
+        });
+        for i in 0..8 {
+            journal.add_operation(Operation::SetName {
+                node: id,
+                name: format!("Note {} edit {}", nodes, i),
+            });
+            journal.add_operation(Operation::SetAttribute {
+                node: id,
+                attribute: 0,
+                value: AttributeValue::U32(i),
+            });
+        }
+    }
+    journal.operations.truncate(operations);
+    journal
+}
+
+/// Undo and redo one operation. This should take the same time however long the history is.
+fn undo_redo(c: &mut Criterion) {
+    let mut group = c.benchmark_group("undo_redo");
+    for operations in [1_000, 10_000, 100_000] {
+        let mut document = Document::new(edited_journal(operations));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(operations),
+            &operations,
+            |b, _| {
+                b.iter(|| {
+                    document.undo();
+                    document.redo();
+                    black_box(document.node_count())
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Undo 100 operations and redo them again, for comparison with a single step
+fn undo_redo_100(c: &mut Criterion) {
+    let mut document = Document::new(edited_journal(100_000));
+    c.bench_function("undo_redo_100/100000", |b| {
+        b.iter(|| {
+            for _ in 0..100 {
+                document.undo();
+            }
+            for _ in 0..100 {
+                document.redo();
+            }
+            black_box(document.node_count())
+        })
+    });
+}
+
+/// Replaying the whole journal, which is what undo used to cost
+fn replay(c: &mut Criterion) {
+    let mut group = c.benchmark_group("replay");
+    for operations in [1_000, 10_000, 100_000] {
+        let journal = edited_journal(operations);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(operations),
+            &journal,
+            |b, journal| {
+                b.iter(|| {
+                    let mut copy = Journal::new();
+                    copy.operations = journal.operations.clone();
+                    black_box(Document::new(copy).node_count())
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, undo_redo, undo_redo_100, replay);
+criterion_main!(benches);
@@ -1,4 +1,7 @@
-#[derive(Debug, Clone)]
+use crate::node_id::NodeId;
+use chrono::{DateTime, NaiveDate, NaiveTime, SecondsFormat, SubsecRound, TimeDelta, Utc};
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum AttributeValue {
     String(String),
     Bool(bool),
@@ -13,22 +16,133 @@ pub enum AttributeValue {
     I24(I24),
     I32(i32),
     I64(i64),
+    F16(F16),
     F32(f32),
     F64(f64),
+    /// Raw binary data, like thumbnails, small files or serialized payloads
+    Bytes(Vec<u8>),
+    /// Point in time, stored with millisecond precision
+    Timestamp(DateTime<Utc>),
+    /// Calendar date without time of day, like a due date
+    Date(NaiveDate),
+    /// Length of time, stored with millisecond precision
+    Duration(TimeDelta),
+    /// Link to another node, like "duplicate of"
+    NodeReference(NodeId),
+    /// Links to other nodes, like "blocks" or "see also"
+    NodeReferenceArray(Vec<NodeId>),
+    BoolArray(Vec<bool>),
+    StringArray(Vec<String>),
+    UuidArray(Vec<uuid::Uuid>),
+    U8Array(Vec<u8>),
+    U16Array(Vec<u16>),
+    U24Array(Vec<U24>),
+    U32Array(Vec<u32>),
+    U64Array(Vec<u64>),
+    I8Array(Vec<i8>),
+    I16Array(Vec<i16>),
+    I24Array(Vec<I24>),
+    I32Array(Vec<i32>),
+    I64Array(Vec<i64>),
+    F16Array(Vec<F16>),
+    F32Array(Vec<f32>),
+    F64Array(Vec<f64>),
 }
 
-type I24 = [u8; 3];
-type U24 = [u8; 3];
+pub use crate::numeric::{F16, I24, U24};
 
 impl AttributeValue {
+    /// Arrays longer than this are abbreviated when displayed
+    pub const MAX_DISPLAYED_ELEMENTS: usize = 16;
+    /// Binary data longer than this only shows a preview of the first bytes when displayed
+    pub const MAX_DISPLAYED_BYTES: usize = 16;
+
     pub(crate) fn too_long_for_display(&self) -> bool {
         match self {
             AttributeValue::String(s) => s.len() > 100,
-            _ => false,
+            AttributeValue::Bytes(b) => b.len() > Self::MAX_DISPLAYED_BYTES,
+            AttributeValue::StringArray(a) => a.iter().map(|s| s.len()).sum::<usize>() > 100,
+            _ => self.array_len().unwrap_or(0) > Self::MAX_DISPLAYED_ELEMENTS,
+        }
+    }
+
+    /// The nodes this value links to, empty if it isn't a node reference
+    pub fn node_references(&self) -> &[NodeId] {
+        match self {
+            AttributeValue::NodeReference(id) => std::slice::from_ref(id),
+            AttributeValue::NodeReferenceArray(ids) => ids,
+            _ => &[],
+        }
+    }
+
+    /// Timestamps as they are, and dates as midnight UTC, so both can be sorted together
+    pub fn as_timestamp(&self) -> Option<DateTime<Utc>> {
+        match self {
+            AttributeValue::Timestamp(t) => Some(*t),
+            AttributeValue::Date(d) => Some(d.and_time(NaiveTime::MIN).and_utc()),
+            _ => None,
+        }
+    }
+
+    pub fn is_array(&self) -> bool {
+        self.array_len().is_some()
+    }
+
+    /// Number of elements, if this is an array value
+    pub fn array_len(&self) -> Option<usize> {
+        match self {
+            AttributeValue::BoolArray(a) => Some(a.len()),
+            AttributeValue::StringArray(a) => Some(a.len()),
+            AttributeValue::UuidArray(a) => Some(a.len()),
+            AttributeValue::U8Array(a) => Some(a.len()),
+            AttributeValue::U16Array(a) => Some(a.len()),
+            AttributeValue::U24Array(a) => Some(a.len()),
+            AttributeValue::U32Array(a) => Some(a.len()),
+            AttributeValue::U64Array(a) => Some(a.len()),
+            AttributeValue::I8Array(a) => Some(a.len()),
+            AttributeValue::I16Array(a) => Some(a.len()),
+            AttributeValue::I24Array(a) => Some(a.len()),
+            AttributeValue::I32Array(a) => Some(a.len()),
+            AttributeValue::I64Array(a) => Some(a.len()),
+            AttributeValue::F16Array(a) => Some(a.len()),
+            AttributeValue::F32Array(a) => Some(a.len()),
+            AttributeValue::F64Array(a) => Some(a.len()),
+            AttributeValue::NodeReferenceArray(a) => Some(a.len()),
+            _ => None,
+        }
+    }
+
+    /// The elements formatted one by one, if this is an array value
+    pub fn array_elements(&self) -> Option<Vec<String>> {
+        match self {
+            AttributeValue::BoolArray(a) => Some(to_strings(a)),
+            AttributeValue::StringArray(a) => Some(a.clone()),
+            AttributeValue::UuidArray(a) => Some(to_strings(a)),
+            AttributeValue::U8Array(a) => Some(to_strings(a)),
+            AttributeValue::U16Array(a) => Some(to_strings(a)),
+            AttributeValue::U24Array(a) => Some(to_strings(a)),
+            AttributeValue::U32Array(a) => Some(to_strings(a)),
+            AttributeValue::U64Array(a) => Some(to_strings(a)),
+            AttributeValue::I8Array(a) => Some(to_strings(a)),
+            AttributeValue::I16Array(a) => Some(to_strings(a)),
+            AttributeValue::I24Array(a) => Some(to_strings(a)),
+            AttributeValue::I32Array(a) => Some(to_strings(a)),
+            AttributeValue::I64Array(a) => Some(to_strings(a)),
+            AttributeValue::F16Array(a) => Some(to_strings(a)),
+            AttributeValue::F32Array(a) => Some(to_strings(a)),
+            AttributeValue::F64Array(a) => Some(to_strings(a)),
+            AttributeValue::NodeReferenceArray(a) => {
+                Some(a.iter().map(|id| format!("#{}", id)).collect())
+            }
+            _ => None,
         }
     }
 }
 
+fn to_strings<T: std::fmt::Display>(values: &[T]) -> Vec<String> {
+    values.iter().map(|v| v.to_string()).collect()
+}
+
 impl std::fmt::Display for AttributeValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -37,17 +151,221 @@ impl std::fmt::Display for AttributeValue {
             AttributeValue::Uuid(u) => write!(f, "{}", u),
             AttributeValue::U8(u) => write!(f, "{}", u),
             AttributeValue::U16(u) => write!(f, "{}", u),
-            AttributeValue::U24(u) => write!(f, "U24"),
+            AttributeValue::U24(u) => write!(f, "{}", u),
             AttributeValue::U32(u) => write!(f, "{}", u),
             AttributeValue::U64(u) => write!(f, "{}", u),
             AttributeValue::I8(u) => write!(f, "{}", u),
             AttributeValue::I16(u) => write!(f, "{}", u),
-            AttributeValue::I24(u) => write!(f, "I24"),
+            AttributeValue::I24(u) => write!(f, "{}", u),
             AttributeValue::I32(u) => write!(f, "{}", u),
             AttributeValue::I64(u) => write!(f, "{}", u),
+            AttributeValue::F16(u) => write!(f, "{}", u),
             AttributeValue::F32(u) => write!(f, "{}", u),
             AttributeValue::F64(u) => write!(f, "{}", u),
+            AttributeValue::Bytes(b) => write_bytes(f, b),
+            AttributeValue::Timestamp(t) => {
+                write!(f, "{}", t.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+            }
+            AttributeValue::Date(d) => write!(f, "{}", d.format("%Y-%m-%d")),
+            AttributeValue::Duration(d) => write_duration(f, d),
+            AttributeValue::NodeReference(id) => write!(f, "#{}", id),
+            AttributeValue::NodeReferenceArray(_) => {
+                write_array(f, &self.array_elements().unwrap_or_default())
+            }
+            AttributeValue::BoolArray(a) => write_array(f, a),
+            AttributeValue::StringArray(a) => write_array(f, a),
+            AttributeValue::UuidArray(a) => write_array(f, a),
+            AttributeValue::U8Array(a) => write_array(f, a),
+            AttributeValue::U16Array(a) => write_array(f, a),
+            AttributeValue::U24Array(a) => write_array(f, a),
+            AttributeValue::U32Array(a) => write_array(f, a),
+            AttributeValue::U64Array(a) => write_array(f, a),
+            AttributeValue::I8Array(a) => write_array(f, a),
+            AttributeValue::I16Array(a) => write_array(f, a),
+            AttributeValue::I24Array(a) => write_array(f, a),
+            AttributeValue::I32Array(a) => write_array(f, a),
+            AttributeValue::I64Array(a) => write_array(f, a),
+            AttributeValue::F16Array(a) => write_array(f, a),
+            AttributeValue::F32Array(a) => write_array(f, a),
+            AttributeValue::F64Array(a) => write_array(f, a),
+        }
+    }
+}
+
+fn write_array<T: std::fmt::Display>(
+    f: &mut std::fmt::Formatter<'_>,
+    values: &[T],
+) -> std::fmt::Result {
+    write!(f, "[")?;
+    for (i, v) in values
+        .iter()
+        .take(AttributeValue::MAX_DISPLAYED_ELEMENTS)
+        .enumerate()
+    {
+        if i > 0 {
+            write!(f, ", ")?;
         }
+        write!(f, "{}", v)?;
+    }
+    if values.len() > AttributeValue::MAX_DISPLAYED_ELEMENTS {
+        write!(
+            f,
+            ", … {} more",
+            values.len() - AttributeValue::MAX_DISPLAYED_ELEMENTS
+        )?;
+    }
+    write!(f, "]")
+}
+
+fn write_bytes(f: &mut std::fmt::Formatter<'_>, bytes: &[u8]) -> std::fmt::Result {
+    write!(f, "{} [", format_size(bytes.len()))?;
+    for (i, b) in bytes
+        .iter()
+        .take(AttributeValue::MAX_DISPLAYED_BYTES)
+        .enumerate()
+    {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        write!(f, "{:02x}", b)?;
+    }
+    if bytes.len() > AttributeValue::MAX_DISPLAYED_BYTES {
+        write!(f, " …")?;
+    }
+    write!(f, "]")
+}
+
+/// Formats like "2d 3h 15m 4.5s", leaving out parts that are zero
+fn write_duration(f: &mut std::fmt::Formatter<'_>, duration: &TimeDelta) -> std::fmt::Result {
+    if duration.is_zero() {
+        return write!(f, "0s");
+    }
+    if *duration < TimeDelta::zero() {
+        write!(f, "-")?;
+    }
+    let millis = duration.num_milliseconds().unsigned_abs();
+    let parts = [
+        (millis / 86_400_000, "d"),
+        (millis / 3_600_000 % 24, "h"),
+        (millis / 60_000 % 60, "m"),
+    ];
+    let mut separator = "";
+    for (value, unit) in parts {
+        if value > 0 {
+            write!(f, "{}{}{}", separator, value, unit)?;
+            separator = " ";
+        }
+    }
+    let seconds = millis / 1000 % 60;
+    let fraction = millis % 1000;
+    if fraction > 0 {
+        let fraction = format!("{:03}", fraction);
+        write!(
+            f,
+            "{}{}.{}s",
+            separator,
+            seconds,
+            fraction.trim_end_matches('0')
+        )
+    } else if seconds > 0 {
+        write!(f, "{}{}s", separator, seconds)
+    } else {
+        Ok(())
+    }
+}
+
+/// Human readable size, like "12 bytes" or "3.5 KiB"
+pub fn format_size(size: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{} bytes", size);
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+macro_rules! impl_from_value {
+    ($($t:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$t> for AttributeValue {
+                fn from(value: $t) -> Self {
+                    AttributeValue::$variant(value)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_value!(
+    String => String,
+    bool => Bool,
+    uuid::Uuid => Uuid,
+    u8 => U8,
+    u16 => U16,
+    U24 => U24,
+    u32 => U32,
+    u64 => U64,
+    i8 => I8,
+    i16 => I16,
+    I24 => I24,
+    i32 => I32,
+    i64 => I64,
+    F16 => F16,
+    f32 => F32,
+    f64 => F64,
+    Vec<bool> => BoolArray,
+    Vec<String> => StringArray,
+    Vec<uuid::Uuid> => UuidArray,
+    Vec<u8> => U8Array,
+    Vec<u16> => U16Array,
+    Vec<U24> => U24Array,
+    Vec<u32> => U32Array,
+    Vec<u64> => U64Array,
+    Vec<i8> => I8Array,
+    Vec<i16> => I16Array,
+    Vec<I24> => I24Array,
+    Vec<i32> => I32Array,
+    Vec<i64> => I64Array,
+    Vec<F16> => F16Array,
+    Vec<f32> => F32Array,
+    Vec<f64> => F64Array,
+    Vec<NodeId> => NodeReferenceArray,
+);
+
+impl From<DateTime<Utc>> for AttributeValue {
+    /// Drops sub-millisecond precision, so the value is the same after saving and loading
+    fn from(value: DateTime<Utc>) -> Self {
+        AttributeValue::Timestamp(value.trunc_subsecs(3))
+    }
+}
+
+impl From<NaiveDate> for AttributeValue {
+    fn from(value: NaiveDate) -> Self {
+        AttributeValue::Date(value)
+    }
+}
+
+impl From<TimeDelta> for AttributeValue {
+    /// Drops sub-millisecond precision, so the value is the same after saving and loading
+    fn from(value: TimeDelta) -> Self {
+        AttributeValue::Duration(TimeDelta::milliseconds(value.num_milliseconds()))
+    }
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        AttributeValue::String(value.to_string())
+    }
+}
+
+impl From<Vec<&str>> for AttributeValue {
+    fn from(value: Vec<&str>) -> Self {
+        AttributeValue::StringArray(value.into_iter().map(|s| s.to_string()).collect())
     }
 }
 
@@ -66,8 +384,31 @@ pub fn attribute_type(value: &AttributeValue) -> &str {
         AttributeValue::I24(_) => "I24",
         AttributeValue::I32(_) => "I32",
         AttributeValue::I64(_) => "I64",
+        AttributeValue::F16(_) => "F16",
         AttributeValue::F32(_) => "F32",
         AttributeValue::F64(_) => "F64",
+        AttributeValue::Bytes(_) => "Bytes",
+        AttributeValue::Timestamp(_) => "Timestamp",
+        AttributeValue::Date(_) => "Date",
+        AttributeValue::Duration(_) => "Duration",
+        AttributeValue::NodeReference(_) => "NodeReference",
+        AttributeValue::NodeReferenceArray(_) => "NodeReferenceArray",
+        AttributeValue::BoolArray(_) => "BoolArray",
+        AttributeValue::StringArray(_) => "StringArray",
+        AttributeValue::UuidArray(_) => "UuidArray",
+        AttributeValue::U8Array(_) => "U8Array",
+        AttributeValue::U16Array(_) => "U16Array",
+        AttributeValue::U24Array(_) => "U24Array",
+        AttributeValue::U32Array(_) => "U32Array",
+        AttributeValue::U64Array(_) => "U64Array",
+        AttributeValue::I8Array(_) => "I8Array",
+        AttributeValue::I16Array(_) => "I16Array",
+        AttributeValue::I24Array(_) => "I24Array",
+        AttributeValue::I32Array(_) => "I32Array",
+        AttributeValue::I64Array(_) => "I64Array",
+        AttributeValue::F16Array(_) => "F16Array",
+        AttributeValue::F32Array(_) => "F32Array",
+        AttributeValue::F64Array(_) => "F64Array",
     }
 }
 
@@ -94,6 +435,12 @@ impl AttributeStore {
         self.attributes.push(AttributeEntry { key, value });
     }
 
+    /// Returns the removed value, if the attribute was set
+    pub fn remove(&mut self, key: usize) -> Option<AttributeValue> {
+        let index = self.attributes.iter().position(|x| x.key == key)?;
+        Some(self.attributes.remove(index).value)
+    }
+
     pub fn get(&self, key: usize) -> Option<&AttributeValue> {
         self.attributes
             .iter()
@@ -108,6 +455,14 @@ impl AttributeStore {
             .map(|x| &mut x.value)
     }
 
+    pub(crate) fn position(&self, key: usize) -> Option<usize> {
+        self.attributes.iter().position(|x| x.key == key)
+    }
+
+    pub(crate) fn insert_at(&mut self, index: usize, key: usize, value: AttributeValue) {
+        self.attributes.insert(index, AttributeEntry { key, value });
+    }
+
     pub fn iter(&self) -> std::slice::Iter<AttributeEntry> {
         self.attributes.iter()
     }
@@ -116,3 +471,110 @@ impl AttributeStore {
         self.attributes.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_array() {
+        let value = AttributeValue::from(vec![1u16, 2, 3]);
+        assert_eq!(value.to_string(), "[1, 2, 3]");
+        assert!(!value.too_long_for_display());
+
+        let labels = AttributeValue::from(vec!["a", "b"]);
+        assert_eq!(labels.to_string(), "[a, b]");
+        assert_eq!(attribute_type(&labels), "StringArray");
+    }
+
+    #[test]
+    fn test_display_long_array() {
+        let value = AttributeValue::from((0..100).collect::<Vec<i32>>());
+        assert!(value.too_long_for_display());
+        assert_eq!(value.array_len(), Some(100));
+        assert_eq!(
+            value.to_string(),
+            "[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, … 84 more]"
+        );
+    }
+
+    #[test]
+    fn test_array_elements() {
+        let value = AttributeValue::from(vec![true, false]);
+        assert_eq!(
+            value.array_elements(),
+            Some(vec!["true".to_string(), "false".to_string()])
+        );
+        assert_eq!(AttributeValue::from(3u8).array_elements(), None);
+    }
+
+    #[test]
+    fn test_display_bytes() {
+        let value = AttributeValue::Bytes(vec![0x89, b'P', b'N', b'G']);
+        assert_eq!(value.to_string(), "4 bytes [89 50 4e 47]");
+        assert!(!value.too_long_for_display());
+
+        let value = AttributeValue::Bytes(vec![0xAB; 3000]);
+        assert!(value.too_long_for_display());
+        assert_eq!(
+            value.to_string(),
+            "2.9 KiB [ab ab ab ab ab ab ab ab ab ab ab ab ab ab ab ab …]"
+        );
+        assert_eq!(attribute_type(&value), "Bytes");
+    }
+
+    #[test]
+    fn test_display_dates() {
+        let timestamp = DateTime::from_timestamp(1_700_000_000, 250_000_000).unwrap();
+        assert_eq!(
+            AttributeValue::from(timestamp).to_string(),
+            "2023-11-14T22:13:20.250Z"
+        );
+        let date = NaiveDate::from_ymd_opt(2025, 3, 14).unwrap();
+        assert_eq!(AttributeValue::from(date).to_string(), "2025-03-14");
+
+        let duration = |d: TimeDelta| AttributeValue::from(d).to_string();
+        assert_eq!(duration(TimeDelta::zero()), "0s");
+        assert_eq!(duration(TimeDelta::minutes(90)), "1h 30m");
+        assert_eq!(
+            duration(TimeDelta::days(2) + TimeDelta::milliseconds(4500)),
+            "2d 4.5s"
+        );
+        assert_eq!(duration(-TimeDelta::seconds(61)), "-1m 1s");
+    }
+
+    #[test]
+    fn test_dates_keep_millisecond_precision() {
+        let timestamp = DateTime::from_timestamp(1_700_000_000, 123_456_789).unwrap();
+        assert_eq!(
+            AttributeValue::from(timestamp),
+            AttributeValue::Timestamp(
+                DateTime::from_timestamp(1_700_000_000, 123_000_000).unwrap()
+            )
+        );
+        assert_eq!(
+            AttributeValue::from(TimeDelta::microseconds(2500)),
+            AttributeValue::Duration(TimeDelta::milliseconds(2))
+        );
+    }
+
+    #[test]
+    fn test_display_small_numbers() {
+        assert_eq!(AttributeValue::from(U24::MAX).to_string(), "16777215");
+        assert_eq!(AttributeValue::from(I24::MIN).to_string(), "-8388608");
+        assert_eq!(AttributeValue::from(F16::from_f32(0.5)).to_string(), "0.5");
+        let values = vec![I24::try_from(-3).unwrap(), I24::from(7u8)];
+        assert_eq!(AttributeValue::from(values).to_string(), "[-3, 7]");
+    }
+
+    #[test]
+    fn test_remove_attribute() {
+        let mut store = AttributeStore::default();
+        store.set(1, AttributeValue::Bool(true));
+        store.set(2, AttributeValue::from("x"));
+        assert_eq!(store.remove(1), Some(AttributeValue::Bool(true)));
+        assert_eq!(store.remove(1), None);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(2), Some(&AttributeValue::from("x")));
+    }
+}
@@ -2,6 +2,7 @@ use crate::attributes::AttributeValue;
 use crate::document::Document;
 use crate::node_id::NodeId;
 use crate::operation::Operation;
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
 
 pub trait NodeBuilder {
     fn add_node(&mut self, type_name: &str, parent: NodeId) -> NodeId;
@@ -10,117 +11,132 @@ pub trait NodeBuilder {
     fn set_node_name(&mut self, node_id: NodeId, name: &str);
     fn set_node_type(&mut self, node_id: NodeId, type_name: &str);
     fn set_node_attribute_s(&mut self, node_id: NodeId, attribute: &str, name: &str);
+    fn set_node_attribute(&mut self, node_id: NodeId, attribute: &str, value: AttributeValue);
     fn set_node_tag(&mut self, node_id: NodeId, tag: &str);
+    fn set_node_timestamp(&mut self, node_id: NodeId, attribute: &str, value: DateTime<Utc>);
+    fn set_node_date(&mut self, node_id: NodeId, attribute: &str, value: NaiveDate);
+    fn set_node_duration(&mut self, node_id: NodeId, attribute: &str, value: TimeDelta);
 }
 
 impl NodeBuilder for Document {
     fn add_node(&mut self, type_name: &str, parent: NodeId) -> NodeId {
-        let id = self.node_id_generator.next_id();
         let index_in_parent = self
             .nodes
             .get(parent)
             .expect("Parent must exist")
             .children
             .len();
-        let (node_type, exists) = self.nodes.type_names.get_or_create_index(type_name);
-        if !exists {
-            self.add_and_apply(Operation::DefineTypeName {
-                id: node_type,
-                name: type_name.to_string(),
-            });
-        }
-        self.add_and_apply(Operation::AddNode {
-            id,
-            node_type,
-            parent,
-            index_in_parent,
-        });
-        id
+        self.insert_node(type_name, parent, index_in_parent)
     }
 
     fn insert_node(&mut self, type_name: &str, parent: NodeId, index: usize) -> NodeId {
         let id = self.node_id_generator.next_id();
-        let (node_type, exists) = self.nodes.type_names.get_or_create_index(type_name);
-        if !exists {
-            self.add_and_apply(Operation::DefineTypeName {
-                id: node_type,
-                name: type_name.to_string(),
+        // Defining the type is part of adding the node, so both are undone together
+        self.transaction(|document| {
+            let (node_type, exists) = document.nodes.type_names.get_or_create_index(type_name);
+            if !exists {
+                document.add_and_apply_valid(Operation::DefineTypeName {
+                    id: node_type,
+                    name: type_name.to_string(),
+                });
+            }
+            document.add_and_apply_valid(Operation::AddNode {
+                id,
+                node_type,
+                parent,
+                index_in_parent: index,
             });
-        }
-        self.add_and_apply(Operation::AddNode {
-            id,
-            node_type,
-            parent,
-            index_in_parent: index,
         });
         id
     }
 
     fn set_node_name(&mut self, node_id: NodeId, name: &str) {
-        self.add_and_apply(Operation::SetName {
+        self.add_and_apply_valid(Operation::SetName {
             node: node_id,
             name: name.to_string(),
         });
     }
 
     fn set_node_type(&mut self, node_id: NodeId, type_name: &str) {
-        let t = self.nodes.type_names.get_index(type_name);
-
-        let t = if t.is_none() {
-            let new_id = self.nodes.type_names.len();
-            self.add_and_apply(Operation::DefineTypeName {
-                id: new_id,
-                name: type_name.to_string(),
+        self.transaction(|document| {
+            let t = document.nodes.type_names.get_index(type_name);
+
+            let t = if t.is_none() {
+                let new_id = document.nodes.type_names.len();
+                document.add_and_apply_valid(Operation::DefineTypeName {
+                    id: new_id,
+                    name: type_name.to_string(),
+                });
+                new_id
+            } else {
+                t.unwrap()
+            };
+
+            document.add_and_apply_valid(Operation::SetType {
+                node: node_id,
+                type_id: t,
             });
-            new_id
-        } else {
-            t.unwrap()
-        };
-
-        self.add_and_apply(Operation::SetType {
-            node: node_id,
-            type_id: t,
         });
     }
 
     fn set_node_attribute_s(&mut self, node_id: NodeId, attribute: &str, name: &str) {
-        let attr = self.nodes.attribute_names.get_index(attribute);
+        self.set_node_attribute(node_id, attribute, AttributeValue::String(name.to_string()));
+    }
 
-        let attr = if attr.is_none() {
-            let new_id = self.nodes.attribute_names.len();
-            self.add_and_apply(Operation::DefineAttributeName {
-                id: new_id,
-                name: attribute.to_string(),
+    fn set_node_attribute(&mut self, node_id: NodeId, attribute: &str, value: AttributeValue) {
+        self.transaction(|document| {
+            let attr = document.nodes.attribute_names.get_index(attribute);
+
+            let attr = if attr.is_none() {
+                let new_id = document.nodes.attribute_names.len();
+                document.add_and_apply_valid(Operation::DefineAttributeName {
+                    id: new_id,
+                    name: attribute.to_string(),
+                });
+                new_id
+            } else {
+                attr.unwrap()
+            };
+
+            document.add_and_apply_valid(Operation::SetAttribute {
+                node: node_id,
+                attribute: attr,
+                value,
             });
-            new_id
-        } else {
-            attr.unwrap()
-        };
-
-        self.add_and_apply(Operation::SetAttribute {
-            node: node_id,
-            attribute: attr,
-            value: AttributeValue::String(name.to_string()),
         });
     }
 
-    fn set_node_tag(&mut self, node_id: NodeId, tag: &str) {
-        let t = self.nodes.tag_names.get_index(tag);
+    fn set_node_timestamp(&mut self, node_id: NodeId, attribute: &str, value: DateTime<Utc>) {
+        self.set_node_attribute(node_id, attribute, value.into());
+    }
 
-        let t = if t.is_none() {
-            let new_id = self.nodes.tag_names.len();
-            self.add_and_apply(Operation::DefineTagName {
-                id: new_id,
-                name: tag.to_string(),
-            });
-            new_id
-        } else {
-            t.unwrap()
-        };
+    fn set_node_date(&mut self, node_id: NodeId, attribute: &str, value: NaiveDate) {
+        self.set_node_attribute(node_id, attribute, value.into());
+    }
 
-        self.add_and_apply(Operation::SetTag {
-            node: node_id,
-            tag: t,
+    fn set_node_duration(&mut self, node_id: NodeId, attribute: &str, value: TimeDelta) {
+        self.set_node_attribute(node_id, attribute, value.into());
+    }
+
+    fn set_node_tag(&mut self, node_id: NodeId, tag: &str) {
+        self.transaction(|document| {
+            let t = document.nodes.tag_names.get_index(tag);
+
+            let t = if t.is_none() {
+                let new_id = document.nodes.tag_names.len();
+                document.add_and_apply_valid(Operation::DefineTagName {
+                    id: new_id,
+                    name: tag.to_string(),
+                });
+                new_id
+            } else {
+                t.unwrap()
+            };
+
+            document.add_and_apply_valid(Operation::SetTag {
+                node: node_id,
+                tag: t,
+            });
         });
     }
 }
@@ -139,4 +155,45 @@ mod tests {
         document.set_node_attribute_s(b, "speed", "high");
         assert_eq!(document.find_roots().len(), 2)
     }
+
+    #[test]
+    fn test_set_array_attribute() {
+        let mut document = Document::default();
+        let a = document.add_node("test", NodeId::ROOT_NODE);
+        document.set_node_attribute(a, "samples", vec![0.5f32, 0.25].into());
+        document.set_node_attribute(a, "labels", vec!["x", "y"].into());
+
+        let samples = document.nodes.attribute_names.get_index("samples").unwrap();
+        let node = document.nodes.get(a).unwrap();
+        assert_eq!(
+            node.get_attribute(samples),
+            Some(&AttributeValue::F32Array(vec![0.5, 0.25]))
+        );
+    }
+
+    #[test]
+    fn test_set_date_attributes() {
+        let mut document = Document::default();
+        let a = document.add_node("issue", NodeId::ROOT_NODE);
+        let due = NaiveDate::from_ymd_opt(2025, 3, 14).unwrap();
+        let created = DateTime::from_timestamp(1_700_000_000, 123_456_789).unwrap();
+        document.set_node_date(a, "due", due);
+        document.set_node_timestamp(a, "created", created);
+        document.set_node_duration(a, "estimate", TimeDelta::hours(3));
+
+        let node = document.nodes.get(a).unwrap();
+        let names = &document.nodes.attribute_names;
+        assert_eq!(
+            node.get_date_attribute(names.get_index("due").unwrap()),
+            Some(due)
+        );
+        assert_eq!(
+            node.get_timestamp_attribute(names.get_index("created").unwrap()),
+            Some(DateTime::from_timestamp(1_700_000_000, 123_000_000).unwrap())
+        );
+        assert_eq!(
+            node.get_duration_attribute(names.get_index("estimate").unwrap()),
+            Some(TimeDelta::hours(3))
+        );
+    }
 }
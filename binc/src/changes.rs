@@ -1,6 +1,8 @@
 use crate::attributes::AttributeValue;
 use crate::node_id::NodeId;
 use crate::operation::Operation;
+use crate::position::Position;
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
 
 #[derive(Debug, Clone, Default)]
 pub struct Changes {
@@ -47,6 +49,30 @@ impl Changes {
         self
     }
 
+    pub fn add_node_at(&mut self, id: NodeId, parent: NodeId, position: Position) -> &mut Self {
+        self.operations.push(Operation::AddNodeAt {
+            id,
+            node_type: 0,
+            parent,
+            position,
+        });
+        self
+    }
+
+    pub fn move_node_to(
+        &mut self,
+        id: NodeId,
+        new_parent: NodeId,
+        position: Position,
+    ) -> &mut Self {
+        self.operations.push(Operation::MoveNodeTo {
+            id,
+            new_parent,
+            position,
+        });
+        self
+    }
+
     pub fn set_type_s(&mut self, node: NodeId, type_name: &str) -> &mut Self {
         let id = self.get_or_add_type_id(type_name);
         self.set_type(node, id)
@@ -91,6 +117,93 @@ impl Changes {
         self
     }
 
+    pub fn set_bytes(&mut self, node: NodeId, attribute: usize, value: &[u8]) -> &mut Self {
+        self.operations.push(Operation::SetAttribute {
+            node,
+            attribute,
+            value: AttributeValue::Bytes(value.to_vec()),
+        });
+        self
+    }
+
+    pub fn set_timestamp(
+        &mut self,
+        node: NodeId,
+        attribute: usize,
+        value: DateTime<Utc>,
+    ) -> &mut Self {
+        self.set_attribute(node, attribute, value)
+    }
+
+    pub fn set_date(&mut self, node: NodeId, attribute: usize, value: NaiveDate) -> &mut Self {
+        self.set_attribute(node, attribute, value)
+    }
+
+    pub fn set_duration(&mut self, node: NodeId, attribute: usize, value: TimeDelta) -> &mut Self {
+        self.set_attribute(node, attribute, value)
+    }
+
+    pub fn set_attribute_s(
+        &mut self,
+        node: NodeId,
+        attribute: &str,
+        value: impl Into<AttributeValue>,
+    ) -> &mut Self {
+        let id = self.get_or_add_attribute_id(attribute);
+        self.set_attribute(node, id, value)
+    }
+
+    pub fn set_attribute(
+        &mut self,
+        node: NodeId,
+        attribute: usize,
+        value: impl Into<AttributeValue>,
+    ) -> &mut Self {
+        self.operations.push(Operation::SetAttribute {
+            node,
+            attribute,
+            value: value.into(),
+        });
+        self
+    }
+
+    pub fn remove_attribute(&mut self, node: NodeId, attribute: usize) -> &mut Self {
+        self.operations
+            .push(Operation::RemoveAttribute { node, attribute });
+        self
+    }
+
+    pub fn add_comment(
+        &mut self,
+        node: NodeId,
+        comment: &str,
+        author: &str,
+        response_to: usize,
+    ) -> &mut Self {
+        self.operations.push(Operation::AddComment {
+            node,
+            comment: comment.to_string(),
+            author: author.to_string(),
+            response_to,
+        });
+        self
+    }
+
+    pub fn edit_comment(&mut self, node: NodeId, comment: usize, text: &str) -> &mut Self {
+        self.operations.push(Operation::EditComment {
+            node,
+            comment,
+            text: text.to_string(),
+        });
+        self
+    }
+
+    pub fn retract_comment(&mut self, node: NodeId, comment: usize) -> &mut Self {
+        self.operations
+            .push(Operation::RetractComment { node, comment });
+        self
+    }
+
     fn get_or_add_attribute_id(&mut self, attribute_name: &str) -> usize {
         let mut next_id = 0;
         for c in &self.operations {
@@ -1,3 +1,4 @@
+use crate::error::BincError;
 use crate::network_protocol::{NetworkRequest, NetworkResponse};
 use std::io;
 use std::net::TcpStream;
@@ -12,7 +13,7 @@ impl Client {
         Ok(Client { stream })
     }
 
-    pub fn request(&mut self, request: NetworkRequest) -> io::Result<NetworkResponse> {
+    pub fn request(&mut self, request: NetworkRequest) -> Result<NetworkResponse, BincError> {
         request.write(&mut self.stream)?;
         NetworkResponse::read(&mut self.stream)
     }
@@ -1,22 +1,164 @@
-#[derive(Debug, Default)]
+/// Comments on a node. Comments are identified by their position in the list, starting at 1.
+/// They are never removed, only retracted, so an id stays valid for edits and replies.
+#[derive(Debug, Clone, Default)]
 pub struct Comments {
     pub comments: Vec<Comment>,
 }
 
 impl Comments {
-    pub(crate) fn add_comment(&mut self, comment: &str, author: &str, response_to: usize) {
+    /// Id used in `response_to` for comments that don't reply to another comment
+    pub const NO_COMMENT: usize = 0;
+
+    pub(crate) fn add_comment(&mut self, comment: &str, author: &str, response_to: usize) -> usize {
+        let id = self.comments.len() + 1;
         let comment = Comment {
+            id,
             author: author.to_string(),
             text: comment.to_string(),
-            response_to: Some(response_to),
+            response_to: if response_to == Self::NO_COMMENT {
+                None
+            } else {
+                Some(response_to)
+            },
+            edited: false,
+            retracted: false,
         };
         self.comments.push(comment);
+        id
+    }
+
+    /// Returns false if there is no comment with that id
+    pub(crate) fn edit_comment(&mut self, id: usize, text: &str) -> bool {
+        let Some(comment) = self.get_mut(id) else {
+            return false;
+        };
+        comment.text = text.to_string();
+        comment.edited = true;
+        true
+    }
+
+    /// Returns false if there is no comment with that id
+    pub(crate) fn retract_comment(&mut self, id: usize) -> bool {
+        let Some(comment) = self.get_mut(id) else {
+            return false;
+        };
+        comment.text.clear();
+        comment.retracted = true;
+        true
+    }
+
+    /// Take back the last `add_comment`
+    pub(crate) fn remove_last(&mut self) {
+        self.comments.pop();
+    }
+
+    /// Put a comment back the way it was before an edit or retraction
+    pub(crate) fn restore(&mut self, comment: Comment) {
+        if let Some(existing) = self.get_mut(comment.id) {
+            *existing = comment;
+        }
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Comment> {
+        self.comments.get(id.checked_sub(1)?)
+    }
+
+    fn get_mut(&mut self, id: usize) -> Option<&mut Comment> {
+        self.comments.get_mut(id.checked_sub(1)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.comments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.comments.is_empty()
+    }
+
+    /// Direct replies to a comment, in the order they were added
+    pub fn replies(&self, id: usize) -> impl Iterator<Item = &Comment> {
+        self.comments
+            .iter()
+            .filter(move |c| c.response_to == Some(id))
+    }
+
+    /// All comments grouped into threads. Replies to a comment that doesn't exist start a new thread.
+    pub fn threads(&self) -> Vec<CommentThread<'_>> {
+        self.comments
+            .iter()
+            .filter(|c| match c.response_to {
+                Some(parent) => parent >= c.id || self.get(parent).is_none(),
+                None => true,
+            })
+            .map(|c| self.thread(c))
+            .collect()
+    }
+
+    fn thread<'a>(&'a self, comment: &'a Comment) -> CommentThread<'a> {
+        CommentThread {
+            comment,
+            // A reply always comes after its parent, which also rules out cycles
+            replies: self
+                .replies(comment.id)
+                .filter(|reply| reply.id > comment.id)
+                .map(|reply| self.thread(reply))
+                .collect(),
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Comment {
+    pub id: usize,
     pub author: String,
     pub text: String,
     pub response_to: Option<usize>,
+    pub edited: bool,
+    /// The author took the comment back. The text is gone, but replies still belong to it.
+    pub retracted: bool,
+}
+
+/// A comment with the replies to it, and the replies to those
+#[derive(Debug)]
+pub struct CommentThread<'a> {
+    pub comment: &'a Comment,
+    pub replies: Vec<CommentThread<'a>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threads() {
+        let mut comments = Comments::default();
+        let first = comments.add_comment("Is this a bug?", "ann", Comments::NO_COMMENT);
+        let second = comments.add_comment("Unrelated", "bob", Comments::NO_COMMENT);
+        let reply = comments.add_comment("Yes", "bob", first);
+        comments.add_comment("Thanks", "ann", reply);
+        comments.add_comment("Lost", "cid", 99);
+
+        let threads = comments.threads();
+        let ids: Vec<usize> = threads.iter().map(|t| t.comment.id).collect();
+        assert_eq!(ids, vec![first, second, 5]);
+        assert_eq!(threads[0].replies.len(), 1);
+        assert_eq!(threads[0].replies[0].comment.text, "Yes");
+        assert_eq!(threads[0].replies[0].replies[0].comment.author, "ann");
+    }
+
+    #[test]
+    fn test_edit_and_retract() {
+        let mut comments = Comments::default();
+        let first = comments.add_comment("Typo", "ann", Comments::NO_COMMENT);
+        let reply = comments.add_comment("Where?", "bob", first);
+        assert!(comments.edit_comment(first, "Fixed"));
+        assert!(comments.retract_comment(reply));
+        assert!(!comments.retract_comment(3));
+
+        assert_eq!(comments.get(first).unwrap().text, "Fixed");
+        assert!(comments.get(first).unwrap().edited);
+        assert!(comments.get(reply).unwrap().retracted);
+        assert_eq!(comments.threads()[0].replies.len(), 1);
+        assert!(comments.get(0).is_none());
+    }
 }
@@ -0,0 +1,608 @@
+use crate::comments::Comments;
+use crate::error::InvalidOperation;
+use crate::journal::Journal;
+use crate::name_dictionary::NameDictionary;
+use crate::node_id::NodeId;
+use crate::node_store::{Node, NodeStore};
+use crate::operation::Operation;
+use crate::position::Position;
+use uuid::Uuid;
+
+/// What `compact` keeps besides the operations needed to rebuild the document. The default
+/// flattens everything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactOptions {
+    /// Keep the snapshots, so the document can still be seen as it was at each of them. Only the
+    /// operations between two snapshots are compacted.
+    pub keep_snapshots: bool,
+    /// Keep every comment operation, so edits and retractions stay in the history. Otherwise
+    /// only the comments as they are in the end are written.
+    pub keep_comments: bool,
+}
+
+/// A journal with as few operations as possible that rebuilds the same nodes. Operations that
+/// were rejected or had their effect undone by later ones are left out, and a node that was
+/// edited many times is written as one node with its final name, type, attributes and tags.
+///
+/// The version and header are kept, except for the document id. The operations are numbered
+/// differently, so a new id tells clients that synced by revision to start over. Checksums would no longer match, so they are left out, and
+/// if there were any, one new checksum is added at the end. Unknown operations can't be
+/// compacted, and are kept as they are, and so are node id reservations.
+pub fn compact(journal: &Journal, options: CompactOptions) -> Journal {
+    let mut result = Journal::new();
+    result.version = journal.version;
+    result.header = journal.header.clone();
+    result.header.document_id = Uuid::new_v4();
+
+    let mut nodes = NodeStore::new();
+    let mut part = vec![];
+    for operation in &journal.operations {
+        match operation {
+            Operation::Snapshot { .. } if options.keep_snapshots => {
+                nodes = compact_part(&nodes, &part, options, &mut result);
+                result.add_operation(operation.clone());
+                part.clear();
+            }
+            Operation::Snapshot { .. } | Operation::Checksum { .. } => {}
+            _ => part.push(operation),
+        }
+    }
+    compact_part(&nodes, &part, options, &mut result);
+
+    if journal
+        .operations
+        .iter()
+        .any(|o| matches!(o, Operation::Checksum { .. }))
+    {
+        result
+            .add_checksum()
+            .expect("Hashing in memory doesn't fail");
+    }
+    result
+}
+
+/// True if both have the same nodes, names and sources. The order of attributes and tags on a
+/// node doesn't matter, and neither do the slots left by removed nodes.
+pub fn same_nodes(a: &NodeStore, b: &NodeStore) -> bool {
+    let live = |nodes: &NodeStore| nodes.nodes().iter().filter(|n| n.id.exists()).count();
+
+    same_names(&a.type_names, &b.type_names)
+        && same_names(&a.attribute_names, &b.attribute_names)
+        && same_names(&a.tag_names, &b.tag_names)
+        && a.sources == b.sources
+        && live(a) == live(b)
+        && a.nodes().iter().filter(|n| n.id.exists()).all(|node| {
+            b.resolve(node.id)
+                .is_some_and(|other| same_node(node, other))
+        })
+}
+
+fn same_names(a: &NameDictionary, b: &NameDictionary) -> bool {
+    (0..a.len().max(b.len())).all(|id| a.get(id) == b.get(id))
+}
+
+fn same_node(a: &Node, b: &Node) -> bool {
+    let mut a_tags = a.tags.clone();
+    let mut b_tags = b.tags.clone();
+    a_tags.sort();
+    b_tags.sort();
+
+    a.name == b.name
+        && a.type_id == b.type_id
+        && a.parent == b.parent
+        && a.position == b.position
+        && a.children == b.children
+        && a.attributes.len() == b.attributes.len()
+        && a.attributes
+            .iter()
+            .all(|entry| b.get_attribute(entry.key) == Some(&entry.value))
+        && a_tags == b_tags
+        && a.comments.comments == b.comments.comments
+}
+
+/// Write the operations that take `before` to where `part` takes it, and return the result.
+/// If the shorter operations don't get there, as with a node that was removed and added again
+/// with fewer comments, the part is written as it was.
+fn compact_part(
+    before: &NodeStore,
+    part: &[&Operation],
+    options: CompactOptions,
+    result: &mut Journal,
+) -> NodeStore {
+    let mut after = before.clone();
+    for operation in part {
+        let _ = operation.apply(&mut after);
+    }
+
+    let mut diff = Diff {
+        current: before.clone(),
+        target: &after,
+        part,
+        operations: vec![],
+    };
+    let compacted = diff.write_all(options).is_ok() && same_nodes(&diff.current, &after);
+
+    if compacted {
+        for operation in diff.operations {
+            result.add_operation(operation);
+        }
+    } else {
+        for operation in part {
+            result.add_operation((*operation).clone());
+        }
+    }
+    after
+}
+
+/// Move `id` to `index` in `parent`, or to `position` if the node has one there
+fn move_operation(
+    id: NodeId,
+    parent: NodeId,
+    index: usize,
+    position: Option<Position>,
+) -> Operation {
+    match position {
+        Some(position) => Operation::MoveNodeTo {
+            id,
+            new_parent: parent,
+            position,
+        },
+        None => Operation::MoveNode {
+            id,
+            new_parent: parent,
+            index_in_new_parent: index,
+        },
+    }
+}
+
+/// Operations that take `current` to `target`, applied to `current` as they are written
+struct Diff<'a> {
+    current: NodeStore,
+    target: &'a NodeStore,
+    part: &'a [&'a Operation],
+    operations: Vec<Operation>,
+}
+
+impl Diff<'_> {
+    fn emit(&mut self, operation: Operation) -> Result<(), InvalidOperation> {
+        operation.apply(&mut self.current)?;
+        self.operations.push(operation);
+        Ok(())
+    }
+
+    fn write_all(&mut self, options: CompactOptions) -> Result<(), InvalidOperation> {
+        self.define_names(
+            |n| &n.type_names,
+            |id, name| Operation::DefineTypeName { id, name },
+        )?;
+        self.define_names(
+            |n| &n.attribute_names,
+            |id, name| Operation::DefineAttributeName { id, name },
+        )?;
+        self.define_names(
+            |n| &n.tag_names,
+            |id, name| Operation::DefineTagName { id, name },
+        )?;
+        self.sources()?;
+
+        self.properties(NodeId::ROOT_NODE, !options.keep_comments)?;
+        self.place_children(NodeId::ROOT_NODE, !options.keep_comments)?;
+        self.remove_extra_nodes()?;
+
+        let part = self.part;
+        for operation in part {
+            let keep = match operation {
+                // A client may still be adding nodes with the reserved ids
+                Operation::UnknownOperation { .. } | Operation::ReserveNodeIds { .. } => true,
+                Operation::AddComment { node, .. }
+                | Operation::EditComment { node, .. }
+                | Operation::RetractComment { node, .. } => {
+                    options.keep_comments && self.target.resolve(*node).is_some()
+                }
+                _ => false,
+            };
+            if keep {
+                self.emit((*operation).clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn define_names(
+        &mut self,
+        names: fn(&NodeStore) -> &NameDictionary,
+        define: fn(usize, String) -> Operation,
+    ) -> Result<(), InvalidOperation> {
+        let target = names(self.target);
+        for id in 0..target.len() {
+            let Some(name) = target.get(id) else {
+                continue;
+            };
+            if names(&self.current).get(id) != Some(name) {
+                self.emit(define(id, name.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn sources(&mut self) -> Result<(), InvalidOperation> {
+        let target = &self.target.sources;
+        for id in 0..target.next_id() {
+            let operation = match (self.current.sources.get(id), target.get(id)) {
+                (current, Some(source)) if current == Some(source) => continue,
+                (Some(current), Some(source))
+                    if current.importer == source.importer && current.path == source.path =>
+                {
+                    Operation::UpdateSource {
+                        id,
+                        hash: source.hash,
+                        imported: source.imported,
+                    }
+                }
+                (_, Some(source)) => Operation::AddSource {
+                    id,
+                    importer: source.importer.clone(),
+                    path: source.path.clone(),
+                    hash: source.hash,
+                    imported: source.imported,
+                },
+                (Some(_), None) => Operation::RemoveSource { id },
+                (None, None) => continue,
+            };
+            self.emit(operation)?;
+        }
+
+        // Ids of removed sources are not reused, so the last one has to be added and removed
+        // again for the next id to stay the same
+        let last = target.next_id();
+        if self.current.sources.next_id() < last {
+            let added = self
+                .part
+                .iter()
+                .rev()
+                .find(|o| matches!(o, Operation::AddSource { id, .. } if *id + 1 == last));
+            if let Some(added) = added {
+                self.emit((*added).clone())?;
+                self.emit(Operation::RemoveSource { id: last - 1 })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add or move the children of `parent` in order, so the first ones are always in place
+    /// and nothing is moved into itself. Children with a position are added or moved by
+    /// position, so they keep it.
+    fn place_children(&mut self, parent: NodeId, comments: bool) -> Result<(), InvalidOperation> {
+        let children = self.target.existing(parent)?.children.clone();
+        for (index, child) in children.into_iter().enumerate() {
+            let target = self.target.existing(child)?;
+            let position = target.position.clone();
+            let node_type = target.type_id.unwrap_or(0);
+            match self.current.resolve(child) {
+                Some(node) if node.parent == parent && node.position == position => {
+                    let current_index = self.current.existing(parent)?.get_child_index(child);
+                    if current_index != Some(index) {
+                        self.emit(move_operation(child, parent, index, position))?;
+                    }
+                }
+                Some(_) => self.emit(move_operation(child, parent, index, position))?,
+                None => self.emit(match position {
+                    Some(position) => Operation::AddNodeAt {
+                        id: child,
+                        node_type,
+                        parent,
+                        position,
+                    },
+                    None => Operation::AddNode {
+                        id: child,
+                        node_type,
+                        parent,
+                        index_in_parent: index,
+                    },
+                })?,
+            }
+            self.properties(child, comments)?;
+            self.place_children(child, comments)?;
+        }
+        Ok(())
+    }
+
+    fn properties(&mut self, id: NodeId, comments: bool) -> Result<(), InvalidOperation> {
+        let target = self.target.existing(id)?;
+        let current = self.current.existing(id)?.clone();
+
+        if current.type_id != target.type_id {
+            self.emit(Operation::SetType {
+                node: id,
+                type_id: target.type_id.unwrap_or(0),
+            })?;
+        }
+        if current.name != target.name {
+            self.emit(Operation::SetName {
+                node: id,
+                name: target.name.clone().unwrap_or_default(),
+            })?;
+        }
+
+        for entry in target.attributes.iter() {
+            if current.get_attribute(entry.key) != Some(&entry.value) {
+                self.emit(Operation::SetAttribute {
+                    node: id,
+                    attribute: entry.key,
+                    value: entry.value.clone(),
+                })?;
+            }
+        }
+        for entry in current.attributes.iter() {
+            if target.get_attribute(entry.key).is_none() {
+                self.emit(Operation::RemoveAttribute {
+                    node: id,
+                    attribute: entry.key,
+                })?;
+            }
+        }
+
+        for tag in &target.tags {
+            if !current.tags.contains(tag) {
+                self.emit(Operation::SetTag {
+                    node: id,
+                    tag: *tag,
+                })?;
+            }
+        }
+        for tag in &current.tags {
+            if !target.tags.contains(tag) {
+                self.emit(Operation::RemoveTag {
+                    node: id,
+                    tag: *tag,
+                })?;
+            }
+        }
+
+        if comments {
+            self.comments(id, &current.comments, &target.comments)?;
+        }
+        Ok(())
+    }
+
+    fn comments(
+        &mut self,
+        node: NodeId,
+        current: &Comments,
+        target: &Comments,
+    ) -> Result<(), InvalidOperation> {
+        for comment in &target.comments {
+            let existing = current.get(comment.id);
+            if existing.is_none() {
+                self.emit(Operation::AddComment {
+                    node,
+                    comment: comment.text.clone(),
+                    author: comment.author.clone(),
+                    response_to: comment.response_to.unwrap_or(Comments::NO_COMMENT),
+                })?;
+            }
+            let was_retracted = existing.is_some_and(|c| c.retracted);
+            let same_edit = existing.is_some_and(|c| c.edited && c.text == comment.text);
+            if comment.retracted && !was_retracted {
+                self.emit(Operation::RetractComment {
+                    node,
+                    comment: comment.id,
+                })?;
+            } else if comment.edited && !comment.retracted && !same_edit {
+                self.emit(Operation::EditComment {
+                    node,
+                    comment: comment.id,
+                    text: comment.text.clone(),
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Every node in the target is in place now, so nodes that are left are under one that
+    /// stays, and removing the top ones removes the rest
+    fn remove_extra_nodes(&mut self) -> Result<(), InvalidOperation> {
+        let extra: Vec<NodeId> = self
+            .current
+            .nodes()
+            .iter()
+            .filter(|n| n.id.exists() && self.target.resolve(n.id).is_none())
+            .filter(|n| self.target.resolve(n.parent).is_some())
+            .map(|n| n.id)
+            .collect();
+        for id in extra {
+            self.emit(Operation::RemoveNode { id })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes::AttributeValue;
+    use crate::changes::Changes;
+    use crate::sources::Source;
+    use crate::validation::check_journal;
+    use std::io::Cursor;
+
+    fn replay(operations: &[Operation]) -> NodeStore {
+        let mut nodes = NodeStore::new();
+        for operation in operations {
+            let _ = operation.apply(&mut nodes);
+        }
+        nodes
+    }
+
+    fn count(journal: &Journal, filter: fn(&Operation) -> bool) -> usize {
+        journal.operations.iter().filter(|o| filter(o)).count()
+    }
+
+    /// A document edited the way the explorer does it, one keystroke at a time
+    fn edited_journal() -> Journal {
+        let a = NodeId::new(1);
+        let b = NodeId::new(2);
+        let c = NodeId::new(3);
+        let mut changes = Changes::new();
+        changes
+            .add_node(a, NodeId::ROOT_NODE, 0)
+            .add_node(b, NodeId::ROOT_NODE, 1)
+            .add_node(c, b, 0);
+        for i in 1..=10 {
+            changes.set_name(a, &"first node"[..i]);
+        }
+        changes
+            .set_attribute_s(a, "count", AttributeValue::U32(1))
+            .set_attribute_s(a, "count", AttributeValue::U32(2))
+            .set_attribute_s(b, "done", AttributeValue::Bool(true))
+            .remove_attribute(b, 1)
+            .move_node(c, NodeId::ROOT_NODE, 0)
+            .remove_node(b)
+            .add_comment(a, "Frist", "ann", Comments::NO_COMMENT)
+            .edit_comment(a, 1, "First")
+            .add_comment(a, "Oops", "ann", Comments::NO_COMMENT)
+            .retract_comment(a, 2);
+        changes.operations.extend([
+            Operation::Snapshot {
+                author: "ann".to_string(),
+                message: "Renamed".to_string(),
+            },
+            Operation::DefineTagName {
+                id: 0,
+                name: "urgent".to_string(),
+            },
+            Operation::SetTag { node: c, tag: 0 },
+        ]);
+        changes.add_node(b, c, 0).set_name(b, "again");
+
+        let mut journal = Journal::from(changes);
+        journal.add_operation(Operation::AddSource {
+            id: 0,
+            importer: "csv".to_string(),
+            path: "old.csv".to_string(),
+            hash: Source::hash_data(b"old"),
+            imported: Source::now(),
+        });
+        journal.add_operation(Operation::RemoveSource { id: 0 });
+        journal.add_checksum().unwrap();
+        journal
+    }
+
+    #[test]
+    fn test_flatten() {
+        let journal = edited_journal();
+        let compacted = compact(&journal, CompactOptions::default());
+
+        assert!(same_nodes(
+            &replay(&journal.operations),
+            &replay(&compacted.operations)
+        ));
+        assert_eq!(check_journal(&compacted), vec![]);
+        assert_eq!(
+            count(&compacted, |o| matches!(o, Operation::SetName { .. })),
+            2
+        );
+        assert_eq!(
+            count(&compacted, |o| matches!(o, Operation::Snapshot { .. })),
+            0
+        );
+        assert_eq!(compacted.operations.len(), 17);
+        assert_eq!(compacted.header.schema, journal.header.schema);
+        assert_ne!(compacted.header.document_id, journal.header.document_id);
+
+        let mut data = vec![];
+        compacted.write(&mut data).unwrap();
+        let read = Journal::read(&mut Cursor::new(data)).unwrap();
+        assert!(matches!(
+            read.operations.last(),
+            Some(Operation::Checksum { .. })
+        ));
+    }
+
+    #[test]
+    fn test_keep_snapshots_and_comments() {
+        let journal = edited_journal();
+        let options = CompactOptions {
+            keep_snapshots: true,
+            keep_comments: true,
+        };
+        let compacted = compact(&journal, options);
+        assert_eq!(check_journal(&compacted), vec![]);
+
+        let snapshot = |j: &Journal| {
+            j.operations
+                .iter()
+                .position(|o| matches!(o, Operation::Snapshot { .. }))
+                .unwrap()
+        };
+        assert!(same_nodes(
+            &replay(&journal.operations[..snapshot(&journal)]),
+            &replay(&compacted.operations[..snapshot(&compacted)])
+        ));
+        assert!(same_nodes(
+            &replay(&journal.operations),
+            &replay(&compacted.operations)
+        ));
+
+        let is_comment = |o: &Operation| {
+            matches!(
+                o,
+                Operation::AddComment { .. }
+                    | Operation::EditComment { .. }
+                    | Operation::RetractComment { .. }
+            )
+        };
+        assert_eq!(count(&compacted, is_comment), count(&journal, is_comment));
+    }
+
+    #[test]
+    fn test_node_added_again() {
+        let a = NodeId::new(1);
+        let mut changes = Changes::new();
+        changes
+            .add_node(a, NodeId::ROOT_NODE, 0)
+            .add_comment(a, "Old", "ann", Comments::NO_COMMENT)
+            .remove_node(a)
+            .add_node(a, NodeId::ROOT_NODE, 0)
+            .set_name(a, "new");
+        let journal = Journal::from(changes);
+
+        let compacted = compact(&journal, CompactOptions::default());
+        assert!(same_nodes(
+            &replay(&journal.operations),
+            &replay(&compacted.operations)
+        ));
+    }
+
+    #[test]
+    fn test_positions_are_kept() {
+        let (a, b, c, d) = (
+            NodeId::new(1),
+            NodeId::new(2),
+            NodeId::new(3),
+            NodeId::new(4),
+        );
+        let first = Position::between(None, None);
+        let last = Position::between(Some(&first), None);
+        let mut changes = Changes::new();
+        changes
+            .add_node_at(a, NodeId::ROOT_NODE, first.clone())
+            .add_node_at(b, NodeId::ROOT_NODE, last.clone())
+            .add_node_at(c, NodeId::ROOT_NODE, Position::between(None, Some(&first)))
+            .add_node(d, NodeId::ROOT_NODE, 1)
+            .move_node_to(c, b, first.clone())
+            .move_node_to(c, NodeId::ROOT_NODE, Position::between(Some(&last), None));
+        let journal = Journal::from(changes);
+
+        let compacted = compact(&journal, CompactOptions::default());
+        assert!(same_nodes(
+            &replay(&journal.operations),
+            &replay(&compacted.operations)
+        ));
+        assert_eq!(
+            count(&compacted, |o| matches!(o, Operation::AddNodeAt { .. })),
+            3
+        );
+        assert_eq!(compacted.operations.len(), 4);
+    }
+}
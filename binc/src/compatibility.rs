@@ -0,0 +1,252 @@
+use crate::attributes::AttributeValue;
+use crate::error::BincError;
+use crate::journal::Journal;
+use crate::node_id::NodeId;
+use crate::node_store::NodeStore;
+use crate::operation::Operation;
+use crate::validation::{Problem, ProblemKind};
+
+/// How `to_v1` handles operations that are not in version 1 of the spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum V1Conversion {
+    /// Any operation outside version 1 is an error
+    Strict,
+    /// Attributes of other types become strings, and snapshots, checksums and node id
+    /// reservations are left out, as they don't change the document. Nodes added or moved by
+    /// position are added or moved to the index they end up at. Anything else has no version 1
+    /// equivalent, and is still an error.
+    Downgrade,
+}
+
+/// True for the operations in version 1 of the spec, 0x01 to 0x09
+pub fn is_v1(operation: &Operation) -> bool {
+    operation.feature().is_none() && !matches!(operation, Operation::UnknownOperation { .. })
+}
+
+/// What an operation outside version 1 is, for error messages and reports
+pub fn extension_name(operation: &Operation) -> String {
+    match operation.feature() {
+        Some(feature) => feature.to_string(),
+        None => format!("unknown operation {:#x}", operation.operation_id()),
+    }
+}
+
+/// Every operation in the journal that is not in version 1 of the spec
+pub fn check_v1(journal: &Journal) -> Vec<Problem> {
+    journal
+        .operations
+        .iter()
+        .enumerate()
+        .filter(|(_, operation)| !is_v1(operation))
+        .map(|(index, operation)| Problem {
+            index,
+            kind: ProblemKind::NotInV1 {
+                feature: extension_name(operation),
+            },
+        })
+        .collect()
+}
+
+/// A copy of the journal with only version 1 operations, for readers like the Java
+/// implementation that support nothing else. Its version is 1, so it is written without a
+/// header.
+pub fn to_v1(journal: &Journal, conversion: V1Conversion) -> Result<Journal, BincError> {
+    let mut result = Journal::new();
+    result.version = 1;
+    result.header = journal.header.clone();
+
+    // The nodes so far, for the index a position puts a node at
+    let mut nodes = NodeStore::new();
+    for (index, operation) in journal.operations.iter().enumerate() {
+        let moved_from = match operation {
+            Operation::MoveNodeTo { id, .. } => nodes
+                .resolve(*id)
+                .map(|node| (node.parent, child_index(&nodes, node.parent, *id))),
+            _ => None,
+        };
+        let applied = operation.apply(&mut nodes).is_ok();
+        if is_v1(operation) {
+            result.add_operation(operation.clone());
+            continue;
+        }
+        let downgraded = match (conversion, operation) {
+            (
+                V1Conversion::Downgrade,
+                Operation::Snapshot { .. }
+                | Operation::Checksum { .. }
+                | Operation::ReserveNodeIds { .. },
+            ) => {
+                continue;
+            }
+            // Rejected operations change nothing either
+            (
+                V1Conversion::Downgrade,
+                Operation::AddNodeAt { .. } | Operation::MoveNodeTo { .. },
+            ) if !applied => {
+                continue;
+            }
+            (
+                V1Conversion::Downgrade,
+                Operation::AddNodeAt {
+                    id,
+                    node_type,
+                    parent,
+                    ..
+                },
+            ) => Operation::AddNode {
+                id: *id,
+                node_type: *node_type,
+                parent: *parent,
+                index_in_parent: child_index(&nodes, *parent, *id),
+            },
+            (V1Conversion::Downgrade, Operation::MoveNodeTo { id, new_parent, .. }) => {
+                // A move within the same parent counts the node at its old index
+                let index = child_index(&nodes, *new_parent, *id);
+                let index_in_new_parent = match moved_from {
+                    Some((parent, old_index)) if parent == *new_parent && old_index <= index => {
+                        index + 1
+                    }
+                    _ => index,
+                };
+                Operation::MoveNode {
+                    id: *id,
+                    new_parent: *new_parent,
+                    index_in_new_parent,
+                }
+            }
+            (
+                V1Conversion::Downgrade,
+                Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value,
+                },
+            ) => Operation::SetAttribute {
+                node: *node,
+                attribute: *attribute,
+                value: AttributeValue::String(v1_string(value)),
+            },
+            _ => {
+                return Err(BincError::NotInV1 {
+                    index,
+                    feature: extension_name(operation),
+                });
+            }
+        };
+        result.add_operation(downgraded);
+    }
+    Ok(result)
+}
+
+fn child_index(nodes: &NodeStore, parent: NodeId, id: NodeId) -> usize {
+    nodes
+        .existing(parent)
+        .ok()
+        .and_then(|p| p.get_child_index(id))
+        .unwrap_or(0)
+}
+
+/// The whole value as a string. Unlike `Display`, nothing is left out.
+fn v1_string(value: &AttributeValue) -> String {
+    match value {
+        AttributeValue::Bytes(bytes) => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+        _ => match value.array_elements() {
+            Some(elements) => format!("[{}]", elements.join(", ")),
+            None => value.to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::Changes;
+    use crate::position::Position;
+    use std::io::Cursor;
+
+    fn journal() -> Journal {
+        let a = NodeId::new(1);
+        let mut changes = Changes::new();
+        changes
+            .add_node(a, NodeId::ROOT_NODE, 0)
+            .set_name(a, "task")
+            .set_attribute(a, 0, AttributeValue::U32(42))
+            .set_attribute(a, 1, AttributeValue::U8Array(vec![1, 2, 3]))
+            .set_attribute(a, 2, AttributeValue::Bytes(vec![0xCA, 0xFE]));
+        let mut journal = Journal::from(changes);
+        journal.add_checksum().unwrap();
+        journal
+    }
+
+    #[test]
+    fn test_strict_rejects_extensions() {
+        let journal = journal();
+        let problems = check_v1(&journal);
+        let indexes: Vec<usize> = problems.iter().map(|p| p.index).collect();
+        assert_eq!(indexes, vec![2, 3, 4, 5]);
+
+        match to_v1(&journal, V1Conversion::Strict) {
+            Err(BincError::NotInV1 { index, feature }) => {
+                assert_eq!((index, feature.as_str()), (2, "typed-attributes"));
+            }
+            other => panic!("Expected an error, got {:?}", other.map(|j| j.operations)),
+        }
+    }
+
+    #[test]
+    fn test_downgrade_to_strings() {
+        let converted = to_v1(&journal(), V1Conversion::Downgrade).unwrap();
+        assert!(check_v1(&converted).is_empty());
+
+        let mut data = vec![];
+        converted.write(&mut data).unwrap();
+        let read = Journal::read(&mut Cursor::new(data)).unwrap();
+        assert_eq!(read.version, 1);
+        let values: Vec<String> = read.operations[2..]
+            .iter()
+            .map(|o| match o {
+                Operation::SetAttribute {
+                    value: AttributeValue::String(s),
+                    ..
+                } => s.clone(),
+                other => panic!("Unexpected operation {}", other),
+            })
+            .collect();
+        assert_eq!(values, vec!["42", "[1, 2, 3]", "cafe"]);
+    }
+
+    #[test]
+    fn test_downgrade_positions() {
+        let (a, b, c) = (NodeId::new(1), NodeId::new(2), NodeId::new(3));
+        let first = Position::between(None, None);
+        let last = Position::between(Some(&first), None);
+        let mut changes = Changes::new();
+        changes
+            .add_node_at(a, NodeId::ROOT_NODE, first.clone())
+            .add_node_at(b, NodeId::ROOT_NODE, last.clone())
+            .add_node(c, NodeId::ROOT_NODE, 2)
+            .move_node_to(a, NodeId::ROOT_NODE, Position::between(Some(&last), None))
+            .move_node_to(c, a, first)
+            .move_node_to(a, c, last);
+        let journal = Journal::from(changes);
+
+        let converted = to_v1(&journal, V1Conversion::Downgrade).unwrap();
+        assert!(check_v1(&converted).is_empty());
+        // The last move is rejected, and left out
+        assert_eq!(converted.operations.len(), 5);
+        let replay = |journal: &Journal| {
+            let mut nodes = NodeStore::new();
+            for operation in &journal.operations {
+                let _ = operation.apply(&mut nodes);
+            }
+            nodes
+        };
+        let (before, after) = (replay(&journal), replay(&converted));
+        for parent in [NodeId::ROOT_NODE, a, b, c] {
+            assert_eq!(
+                before.get(parent).unwrap().children,
+                after.get(parent).unwrap().children
+            );
+        }
+    }
+}
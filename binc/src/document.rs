@@ -1,10 +1,31 @@
 use crate::changes::Changes;
+use crate::error::{BincError, InvalidOperation};
 use crate::journal::Journal;
+use crate::merge::{merge, Conflict};
 use crate::node_id::{NodeId, NodeIdGenerator};
 use crate::node_store::NodeStore;
 use crate::operation::Operation;
+use crate::sources::{Source, SourceStatus, Sources};
+use crate::undo;
+use crate::undo::Undo;
+use std::collections::BTreeSet;
 use std::io;
 use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+/// When an edit is merged into the operation before it, see `Operation::combine_operations`.
+/// Typing a name one key at a time then adds one operation instead of one per key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Coalescing {
+    /// Every edit is its own operation
+    #[default]
+    Off,
+    /// Merge edits that come less than this long after the one before
+    Within(Duration),
+    /// Merge edits however far apart they are. A snapshot or any other operation in between
+    /// still ends the merging.
+    UntilSnapshot,
+}
 
 pub struct Document {
     /// Journal containing all revisions
@@ -14,16 +35,34 @@ pub struct Document {
     /// Revision that have been undone to
     pub undo_revision: Option<usize>,
     pub node_id_generator: NodeIdGenerator,
+    pub coalescing: Coalescing,
+    /// When the last operation was added by `add_and_apply`, or `None` if the next one must not
+    /// be merged into it
+    last_edit: Option<Instant>,
+    /// How to undo each operation up to the current revision, so undo and redo only touch the
+    /// operations they step over
+    undo_log: Vec<Undo>,
+    /// Operations that are undone and redone in one step with the operation before them
+    grouped: BTreeSet<usize>,
+    /// Number of open transactions, see `begin_transaction`
+    transaction_depth: usize,
+    /// Revision the outermost open transaction started at
+    transaction_start: usize,
+    /// Operations before this are shared with others, so they can't be undone
+    committed: usize,
 }
 
-fn compute_nodes(journal: &Journal, end_revision: Option<usize>) -> NodeStore {
+fn compute_nodes(journal: &Journal, end_revision: Option<usize>) -> (NodeStore, Vec<Undo>) {
     let mut nodes: NodeStore = NodeStore::new();
+    let mut undo_log = vec![];
 
     let to = end_revision.unwrap_or(journal.operations.len());
     for operation in &journal.operations.as_slice()[..to] {
-        operation.apply(&mut nodes);
+        // Operations that don't fit, like ones from a damaged file, are skipped. Rejected
+        // operations leave the nodes unchanged, so replaying always gives the same result.
+        undo_log.push(undo::apply(operation, &mut nodes).unwrap_or(Undo::Nothing));
     }
-    nodes
+    (nodes, undo_log)
 }
 
 impl Default for Document {
@@ -33,6 +72,13 @@ impl Default for Document {
             nodes: NodeStore::new(),
             undo_revision: None,
             node_id_generator: NodeIdGenerator::new(),
+            coalescing: Coalescing::Off,
+            last_edit: None,
+            undo_log: vec![],
+            grouped: BTreeSet::new(),
+            transaction_depth: 0,
+            transaction_start: 0,
+            committed: 0,
         }
     }
 }
@@ -42,23 +88,40 @@ impl Document {
         self.node_id_generator.next_id()
     }
 
+    /// Hand out ids from a block reserved for this document, like by a server, before any
+    /// others, see `NodeIdGenerator::use_reserved`. Ids in it that nodes already have are skipped.
+    pub fn use_reserved_ids(&mut self, first: NodeId, count: usize) {
+        let used = self.nodes.len().saturating_sub(first.index()).min(count);
+        let start = first.index() + used;
+        self.node_id_generator
+            .use_reserved(NodeId { id: start }, count - used);
+    }
+
     pub fn new(journal: Journal) -> Document {
-        let nodes = compute_nodes(&journal, None);
+        let (nodes, undo_log) = compute_nodes(&journal, None);
+        let node_id_generator = NodeIdGenerator::for_operations(&journal.operations);
         Document {
             journal,
             nodes,
             undo_revision: None,
-            node_id_generator: NodeIdGenerator::new(),
+            node_id_generator,
+            coalescing: Coalescing::Off,
+            last_edit: None,
+            undo_log,
+            grouped: BTreeSet::new(),
+            transaction_depth: 0,
+            transaction_start: 0,
+            committed: 0,
         }
     }
 
-    pub fn read<T: Read>(file: &mut T) -> io::Result<Document> {
+    pub fn read<T: Read>(file: &mut T) -> Result<Document, BincError> {
         let journal = Journal::read(file)?;
         Ok(Self::new(journal))
     }
 
     fn rebuild(&mut self, end_revision: Option<usize>) {
-        self.nodes = compute_nodes(&self.journal, end_revision);
+        (self.nodes, self.undo_log) = compute_nodes(&self.journal, end_revision);
     }
 
     pub fn write<T: Write>(&self, w: &mut T) -> io::Result<()> {
@@ -73,95 +136,344 @@ impl Document {
         self.nodes.find_roots()
     }
 
-    pub fn add_and_apply_changes(&mut self, changes: Changes) -> &mut Self {
-        for change in changes.operations {
-            self.add_and_apply(change);
+    /// Apply all changes, or none of them if one is rejected. The changes are undone and
+    /// redone in one step.
+    pub fn add_and_apply_changes(&mut self, changes: Changes) -> Result<&mut Self, BincError> {
+        let (nodes, undo_log) = self.apply_to_copy(&changes.operations)?;
+        self.nodes = nodes;
+        self.discard_redo();
+        self.last_edit = None;
+        for (i, (operation, undo)) in changes.operations.into_iter().zip(undo_log).enumerate() {
+            self.push_applied(operation, undo, i > 0);
         }
-        self
+        Ok(self)
     }
 
-    pub fn add_and_apply(&mut self, operation: Operation) {
-        if self.undo_revision.is_some() {
-            self.journal
-                .operations
-                .truncate(self.undo_revision.unwrap() as usize);
-            self.undo_revision = None;
+    /// Add an operation that was applied to the journal. It is undone in one step with the
+    /// operation before it if `joined` is set, or both are in the same transaction.
+    fn push_applied(&mut self, operation: Operation, undo: Undo, joined: bool) {
+        let index = self.num_operations();
+        let in_transaction = self.transaction_depth > 0 && index > self.transaction_start;
+        if joined || in_transaction {
+            self.grouped.insert(index);
         }
-        operation.apply(&mut self.nodes);
+        self.node_id_generator.skip_past_ids_in(&operation);
         self.journal.add_operation(operation);
+        self.undo_log.push(undo);
+    }
 
-        /* let last_change = self.pending_changes.changes.last();
-        let combined_change = if last_change.is_some() {
-            change.combine_change(last_change.unwrap())
-        } else {
-            None
+    /// Start a group of operations that are undone and redone in one step, like the ones
+    /// making up a new node. Transactions can be nested, and the group ends when the outermost
+    /// one is committed. Undo and redo end all open transactions.
+    pub fn begin_transaction(&mut self) {
+        if self.transaction_depth == 0 {
+            self.transaction_start = self.current_revision();
+        }
+        self.transaction_depth += 1;
+    }
+
+    pub fn commit_transaction(&mut self) {
+        self.transaction_depth = self.transaction_depth.saturating_sub(1);
+    }
+
+    /// End all open transactions, and take back the operations added since the outermost one
+    /// began. They are removed from the journal, so they can't be redone.
+    pub fn rollback_transaction(&mut self) {
+        if self.transaction_depth == 0 {
+            return;
+        }
+        self.transaction_depth = 0;
+        if self.undo_revision.is_none() && self.num_operations() > self.transaction_start {
+            while self.current_revision() > self.transaction_start && self.undo_one() {}
+            self.discard_redo();
+        }
+    }
+
+    /// Run `f` in a transaction, so everything it adds is undone in one step
+    pub fn transaction<R>(&mut self, f: impl FnOnce(&mut Document) -> R) -> R {
+        self.begin_transaction();
+        let result = f(self);
+        self.commit_transaction();
+        result
+    }
+
+    /// Apply the operation and add it to the journal. A rejected operation is not added, and
+    /// the document is unchanged. Depending on `coalescing`, the operation can replace the last
+    /// one instead, so undo goes back to before both.
+    pub fn add_and_apply(&mut self, operation: Operation) -> Result<(), InvalidOperation> {
+        let undo = undo::apply(&operation, &mut self.nodes)?;
+        self.discard_redo();
+
+        let now = Instant::now();
+        // The last operation must stay as it is if it was added before the open transaction,
+        // or rolling back would find nothing to take back
+        let before_transaction =
+            self.transaction_depth > 0 && self.num_operations() <= self.transaction_start;
+        let combined = match self.last_edit {
+            Some(last_edit) if !before_transaction && self.can_coalesce(now - last_edit) => self
+                .journal
+                .operations
+                .last()
+                .and_then(|last| operation.combine_operations(last)),
+            _ => None,
         };
+        match combined {
+            // Undoing the combined operation goes back to before the one it replaces, which is
+            // what the last undo already does
+            Some(combined) => {
+                self.journal.operations.pop();
+                self.journal.add_operation(combined);
+            }
+            None => self.push_applied(operation, undo, false),
+        }
+        self.last_edit = Some(now);
+        Ok(())
+    }
 
-        if let Some(combined_change) = combined_change {
-            self.pending_changes.changes.pop();
-            self.pending_changes.changes.push(combined_change);
-        } else {
-            self.pending_changes.changes.push(change);
-        }*/
+    fn can_coalesce(&self, since_last_edit: Duration) -> bool {
+        match self.coalescing {
+            Coalescing::Off => false,
+            Coalescing::Within(window) => since_last_edit <= window,
+            Coalescing::UntilSnapshot => true,
+        }
+    }
+
+    /// Make the next edit a new operation, even if it could be merged into the last one. Call
+    /// this when the operations so far are sent or appended somewhere they can't be replaced.
+    pub fn end_coalescing(&mut self) {
+        self.last_edit = None;
     }
 
-    pub fn append_and_apply<T: Read>(&mut self, r: &mut T) -> io::Result<()> {
-        let from = self.num_operations();
-        self.journal.append(r)?;
-        let to = self.num_operations();
+    /// Add an operation that was made to fit the current nodes, so a rejection would be a bug
+    pub(crate) fn add_and_apply_valid(&mut self, operation: Operation) {
+        if let Err(e) = self.add_and_apply(operation) {
+            panic!("Operation rejected: {}", e);
+        }
+    }
 
-        for i in from..to {
-            let change = &self.journal.operations[i as usize];
-            change.apply(&mut self.nodes);
+    /// Take the undone operations out of the journal, so they can't be redone. Do this before
+    /// sending the journal somewhere, as it only shows the operations that are applied.
+    pub fn discard_redo(&mut self) {
+        if let Some(revision) = self.undo_revision.take() {
+            self.journal.operations.truncate(revision);
+            self.grouped.split_off(&revision);
         }
+    }
 
+    /// Read more operations, like ones from another client, and apply them to the latest
+    /// revision. Nothing is applied if reading fails or one of the operations is rejected.
+    /// The operations are undone and redone in one step.
+    pub fn append_and_apply<T: Read>(&mut self, r: &mut T) -> Result<(), BincError> {
+        let mut appended = Journal::new();
+        appended.append(r)?;
+        self.last_edit = None;
+
+        if self.undo_revision.is_some() {
+            self.undo_revision = None;
+            self.rebuild(None);
+        }
+        let (nodes, undo_log) = self.apply_to_copy(&appended.operations)?;
+        self.nodes = nodes;
+        for (i, (operation, undo)) in appended.operations.into_iter().zip(undo_log).enumerate() {
+            self.push_applied(operation, undo, i > 0);
+        }
         Ok(())
     }
 
+    /// Put `operations` from elsewhere, like ones another client added after `revision`, in
+    /// front of the operations this document has after `revision`. Those are rewritten to fit
+    /// after the new ones, see `merge::merge`, and what can't be kept is returned as conflicts.
+    /// The new operations are undone and redone in one step. Undone operations are discarded,
+    /// and open transactions end.
+    pub fn rebase(&mut self, revision: usize, operations: &[Operation]) -> Vec<Conflict> {
+        match self.undo_revision {
+            Some(undo_revision) if undo_revision >= revision => self.discard_redo(),
+            // Operations before `revision` were undone, but they stay in the journal
+            _ => self.undo_revision = None,
+        }
+        self.transaction_depth = 0;
+        self.last_edit = None;
+
+        let mut base = Journal::new();
+        base.version = self.journal.version;
+        base.header = self.journal.header.clone();
+        base.operations = self.journal.operations[..revision].to_vec();
+        let local = &self.journal.operations[revision..];
+        let merged = merge(&base, local, operations);
+
+        // The local steps stay as they were, unless some of their operations were left out
+        let local_groups = self.grouped.split_off(&revision);
+        if merged.ours.len() == local.len() {
+            let shift = operations.len();
+            self.grouped.extend(local_groups.iter().map(|i| i + shift));
+        }
+        self.grouped
+            .extend(revision + 1..revision + operations.len());
+
+        self.journal = merged.journal;
+        self.rebuild(None);
+        for operation in &self.journal.operations[revision..] {
+            self.node_id_generator.skip_past_ids_in(operation);
+        }
+        merged.conflicts
+    }
+
+    /// Check that `operations` can be applied after the current revision, without changing
+    /// anything. Use this before accepting operations from elsewhere.
+    pub fn validate(&self, operations: &[Operation]) -> Result<(), BincError> {
+        self.apply_to_copy(operations).map(|_| ())
+    }
+
+    fn apply_to_copy(&self, operations: &[Operation]) -> Result<(NodeStore, Vec<Undo>), BincError> {
+        let mut nodes = self.nodes.clone();
+        let mut undo_log = vec![];
+        let first = self.undo_revision.unwrap_or(self.num_operations());
+        for (i, operation) in operations.iter().enumerate() {
+            let undo =
+                undo::apply(operation, &mut nodes).map_err(|reason| BincError::Rejected {
+                    revision: first + i,
+                    reason,
+                })?;
+            undo_log.push(undo);
+        }
+        Ok((nodes, undo_log))
+    }
+
+    pub fn sources(&self) -> &Sources {
+        &self.nodes.sources
+    }
+
+    /// Record that `data` was imported from `path`. If the same file was imported before, the
+    /// source is updated when the data has changed, so a re-import can tell what it is dealing with.
+    pub fn register_source(&mut self, importer: &str, path: &str, data: &[u8]) -> SourceStatus {
+        let hash = Source::hash_data(data);
+        match self.nodes.sources.find(importer, path) {
+            Some(id) if self.nodes.sources.get(id).is_some_and(|s| s.hash == hash) => {
+                SourceStatus::Unchanged(id)
+            }
+            Some(id) => {
+                self.add_and_apply_valid(Operation::UpdateSource {
+                    id,
+                    hash,
+                    imported: Source::now(),
+                });
+                SourceStatus::Updated(id)
+            }
+            None => {
+                let id = self.nodes.sources.next_id();
+                self.add_and_apply_valid(Operation::AddSource {
+                    id,
+                    importer: importer.to_string(),
+                    path: path.to_string(),
+                    hash,
+                    imported: Source::now(),
+                });
+                SourceStatus::Added(id)
+            }
+        }
+    }
+
     pub fn num_operations(&self) -> usize {
         self.journal.operations.len()
     }
 
     pub fn can_undo(&self) -> bool {
-        self.num_operations() > 0
+        self.current_revision() > self.committed
+    }
+
+    /// Number of operations that are shared with others, see `set_committed`
+    pub fn committed(&self) -> usize {
+        self.committed
+    }
+
+    /// Mark the operations before `revision` as shared with others, like once a server has
+    /// them. Undo stops there, so they are never taken out of the journal again.
+    pub fn set_committed(&mut self, revision: usize) {
+        self.committed = revision.min(self.num_operations());
     }
 
     pub fn can_redo(&self) -> bool {
         self.undo_revision.is_some()
     }
 
+    /// Number of operations that are applied, which is less than `num_operations` after undo
+    fn current_revision(&self) -> usize {
+        self.undo_revision.unwrap_or(self.num_operations())
+    }
+
+    /// Go back one step, which is one operation or all operations of a transaction. Only the
+    /// operations being undone are touched.
     pub fn undo(&mut self) {
-        self.undo_revision = match self.undo_revision {
-            Some(rev) => {
-                if rev == 0 {
-                    return;
-                }
-                Some(rev - 1)
+        self.transaction_depth = 0;
+        while self.undo_one() && self.grouped.contains(&self.current_revision()) {}
+    }
+
+    /// Apply the next undone step again
+    pub fn redo(&mut self) {
+        self.transaction_depth = 0;
+        while self.redo_one() && self.grouped.contains(&self.current_revision()) {}
+    }
+
+    /// Go back to the last snapshot before the current revision, so the document is the way it
+    /// was when the snapshot was made. Without an earlier snapshot, everything is undone.
+    pub fn undo_to_snapshot(&mut self) {
+        while self.can_undo() {
+            self.undo();
+            if self.after_snapshot() {
+                break;
             }
-            None => {
-                if self.num_operations() == 0 {
-                    return;
-                }
-                Some(self.num_operations() - 1)
+        }
+    }
+
+    /// Redo up to and including the next snapshot, or everything if there is none
+    pub fn redo_to_snapshot(&mut self) {
+        while self.can_redo() {
+            self.redo();
+            if self.after_snapshot() {
+                break;
             }
-        };
+        }
+    }
 
-        self.rebuild(self.undo_revision);
+    /// True if the last applied operation is a snapshot
+    fn after_snapshot(&self) -> bool {
+        let revision = self.current_revision();
+        revision > 0
+            && matches!(
+                self.journal.operations[revision - 1],
+                Operation::Snapshot { .. }
+            )
     }
 
-    pub fn redo(&mut self) {
-        self.undo_revision = match self.undo_revision {
-            Some(rev) => {
-                if rev + 1 >= self.num_operations() {
-                    None
-                } else {
-                    Some(rev + 1)
-                }
-            }
-            None => None,
-        };
+    /// Undo a single operation. Returns false if there was nothing to undo.
+    fn undo_one(&mut self) -> bool {
+        let revision = self.current_revision();
+        if revision <= self.committed {
+            return false;
+        }
+        if let Some(undo) = self.undo_log.pop() {
+            undo.revert(&mut self.nodes);
+        }
+        self.undo_revision = Some(revision - 1);
+        // The last operation is no longer the latest edit, so it is never merged into
+        self.last_edit = None;
+        true
+    }
 
-        self.rebuild(self.undo_revision);
+    /// Redo a single operation. Returns false if there was nothing to redo.
+    fn redo_one(&mut self) -> bool {
+        let Some(revision) = self.undo_revision else {
+            return false;
+        };
+        let undo = undo::apply(&self.journal.operations[revision], &mut self.nodes);
+        self.undo_log.push(undo.unwrap_or(Undo::Nothing));
+        self.undo_revision = if revision + 1 >= self.num_operations() {
+            None
+        } else {
+            Some(revision + 1)
+        };
+        self.last_edit = None;
+        true
     }
 
     pub fn get_or_define_attribute_id(&mut self, key: &str) -> usize {
@@ -169,7 +481,7 @@ impl Document {
             Some(index) => index,
             None => {
                 let next_id = self.nodes.attribute_names.len();
-                self.add_and_apply(Operation::DefineAttributeName {
+                self.add_and_apply_valid(Operation::DefineAttributeName {
                     id: next_id,
                     name: key.to_string(),
                 });
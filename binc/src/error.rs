@@ -0,0 +1,190 @@
+use crate::journal::ChecksumError;
+use crate::node_id::NodeId;
+use std::fmt::{Display, Formatter};
+use std::io;
+
+/// Why reading binc data stopped. Offsets are in bytes from the start of the data being read.
+#[derive(Debug)]
+pub enum BincError {
+    /// The data ended cleanly, between two operations or messages
+    EndOfStream,
+    /// The data ended in the middle of the operation or message starting at `offset`
+    Truncated { offset: u64 },
+    /// The operation or message starting at `offset` is not valid
+    InvalidData { offset: u64, message: String },
+    /// The data doesn't start with the binc container id
+    NotABincFile,
+    /// The container version is not one that can be read
+    UnsupportedVersion { version: u32 },
+    /// Checksums in the journal didn't match the data before them
+    ChecksumMismatch(ChecksumError),
+    /// The operation that would have been at `revision` in the journal can't be applied
+    Rejected {
+        revision: usize,
+        reason: InvalidOperation,
+    },
+    /// The operation at `index` uses `feature`, which is not in version 1 of the spec
+    NotInV1 { index: usize, feature: String },
+    /// Reading or writing failed for reasons unrelated to the data
+    Io(io::Error),
+}
+
+impl BincError {
+    /// Classify an error from reading the operation or message that starts at `offset`
+    pub(crate) fn from_io(error: io::Error, offset: u64) -> BincError {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => BincError::Truncated { offset },
+            io::ErrorKind::InvalidData => BincError::InvalidData {
+                offset,
+                message: error.to_string(),
+            },
+            _ => BincError::Io(error),
+        }
+    }
+
+    /// Where in the data the problem is, if it is at one place
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            BincError::Truncated { offset } | BincError::InvalidData { offset, .. } => {
+                Some(*offset)
+            }
+            BincError::NotABincFile | BincError::UnsupportedVersion { .. } => Some(0),
+            _ => None,
+        }
+    }
+
+    /// Move the offset, when the data that was read started at `base` in a larger stream
+    pub(crate) fn offset_by(self, base: u64) -> BincError {
+        match self {
+            BincError::Truncated { offset } => BincError::Truncated {
+                offset: offset + base,
+            },
+            BincError::InvalidData { offset, message } => BincError::InvalidData {
+                offset: offset + base,
+                message,
+            },
+            _ => self,
+        }
+    }
+
+    pub fn is_end_of_stream(&self) -> bool {
+        matches!(self, BincError::EndOfStream)
+    }
+}
+
+impl Display for BincError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BincError::EndOfStream => write!(f, "End of stream"),
+            BincError::Truncated { offset } => write!(
+                f,
+                "Data ends in the middle of the operation at byte {}",
+                offset
+            ),
+            BincError::InvalidData { offset, message } => {
+                write!(f, "Invalid data at byte {}: {}", offset, message)
+            }
+            BincError::NotABincFile => write!(f, "Not a binc file"),
+            BincError::UnsupportedVersion { version } => {
+                write!(f, "Unsupported container version {}", version)
+            }
+            BincError::ChecksumMismatch(e) => write!(f, "{}", e),
+            BincError::Rejected { revision, reason } => {
+                write!(f, "Operation {} rejected: {}", revision, reason)
+            }
+            BincError::NotInV1 { index, feature } => write!(
+                f,
+                "Operation {} uses {}, which is not in version 1",
+                index, feature
+            ),
+            BincError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for BincError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BincError::ChecksumMismatch(e) => Some(e),
+            BincError::Rejected { reason, .. } => Some(reason),
+            BincError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BincError {
+    fn from(error: io::Error) -> Self {
+        BincError::Io(error)
+    }
+}
+
+impl From<BincError> for io::Error {
+    fn from(error: BincError) -> Self {
+        match error {
+            BincError::Io(e) => e,
+            e @ (BincError::EndOfStream | BincError::Truncated { .. }) => {
+                io::Error::new(io::ErrorKind::UnexpectedEof, e)
+            }
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+/// Why an operation can't be applied to a document. Applying it would panic or leave the nodes
+/// inconsistent, so it is rejected and nothing changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidOperation {
+    NodeNotFound(NodeId),
+    NodeExists(NodeId),
+    /// The root node can't be added, moved or removed, and no node can have the id `NO_NODE`
+    ReservedNode(NodeId),
+    IndexOutOfRange {
+        parent: NodeId,
+        index: usize,
+        len: usize,
+    },
+    /// Moving the node would make it a descendant of itself
+    MoveIntoItself {
+        node: NodeId,
+        new_parent: NodeId,
+    },
+    CommentNotFound {
+        node: NodeId,
+        comment: usize,
+    },
+    SourceNotFound(usize),
+    /// A new node, name or source id is too far past the ones in use, see
+    /// `NodeStore::MAX_ID_GAP`
+    IdOutOfRange {
+        id: usize,
+        next_id: usize,
+    },
+}
+
+impl Display for InvalidOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidOperation::NodeNotFound(id) => write!(f, "Node {} not found", id),
+            InvalidOperation::NodeExists(id) => write!(f, "Node {} already exists", id),
+            InvalidOperation::ReservedNode(id) => write!(f, "Node {} is reserved", id),
+            InvalidOperation::IndexOutOfRange { parent, index, len } => write!(
+                f,
+                "Index {} is out of range for node {} with {} children",
+                index, parent, len
+            ),
+            InvalidOperation::MoveIntoItself { node, new_parent } => {
+                write!(f, "Node {} can't be moved into {}", node, new_parent)
+            }
+            InvalidOperation::CommentNotFound { node, comment } => {
+                write!(f, "Comment {} not found on node {}", comment, node)
+            }
+            InvalidOperation::SourceNotFound(id) => write!(f, "Source {} not found", id),
+            InvalidOperation::IdOutOfRange { id, next_id } => {
+                write!(f, "Id {} is too far past the next free id {}", id, next_id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvalidOperation {}
@@ -0,0 +1,117 @@
+use crate::readwrite::{ReadExt, WriteExt};
+use chrono::{DateTime, SubsecRound, Utc};
+use std::io;
+use std::io::{Read, Write};
+use uuid::Uuid;
+
+/// Metadata at the start of a file, after the container id and version. Version 1 files have
+/// no header, so a journal read from one gets a new document id and no other metadata.
+///
+/// The header is written with its size first, so fields added later can be skipped by readers
+/// that don't know them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileHeader {
+    /// Identifies the document. It stays the same when the file is copied or renamed, but not
+    /// when it is compacted, see `compaction::compact`.
+    pub document_id: Uuid,
+    pub created: Option<DateTime<Utc>>,
+    /// Application that created the document
+    pub application: String,
+    /// Optional features used by the operations, see `Operation::feature`. A reader can use this
+    /// to tell if it will understand the whole file.
+    pub features: Vec<String>,
+    /// Reference to a schema the document follows, like a URL
+    pub schema: Option<String>,
+}
+
+impl Default for FileHeader {
+    fn default() -> Self {
+        FileHeader::new()
+    }
+}
+
+impl FileHeader {
+    /// Header for a new document
+    pub fn new() -> FileHeader {
+        FileHeader {
+            document_id: Uuid::new_v4(),
+            created: Some(Utc::now().trunc_subsecs(3)),
+            application: format!("binc-rs {}", env!("CARGO_PKG_VERSION")),
+            features: vec![],
+            schema: None,
+        }
+    }
+
+    /// Header for a document read from a version 1 file
+    pub(crate) fn without_metadata() -> FileHeader {
+        FileHeader {
+            document_id: Uuid::new_v4(),
+            created: None,
+            application: String::new(),
+            features: vec![],
+            schema: None,
+        }
+    }
+
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    pub(crate) fn write<T: Write>(&self, w: &mut T) -> io::Result<()> {
+        let mut data = vec![];
+        data.write_uuid(&self.document_id)?;
+        match self.created {
+            Some(created) => {
+                data.write_u8(1)?;
+                data.write_timestamp(created)?;
+            }
+            None => data.write_u8(0)?,
+        }
+        data.write_string(&self.application)?;
+        data.write_string_array(&self.features)?;
+        match &self.schema {
+            Some(schema) => {
+                data.write_u8(1)?;
+                data.write_string(schema)?;
+            }
+            None => data.write_u8(0)?,
+        }
+        w.write_bytes(&data)
+    }
+
+    pub(crate) fn read<T: Read>(r: &mut T) -> io::Result<FileHeader> {
+        let data = r.read_bytes()?;
+        Self::parse(&data).map_err(|e| {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Header is shorter than its size",
+                )
+            } else {
+                e
+            }
+        })
+    }
+
+    fn parse(mut r: &[u8]) -> io::Result<FileHeader> {
+        let document_id = r.read_uuid()?;
+        let created = match r.read_u8()? {
+            0 => None,
+            _ => Some(r.read_timestamp()?),
+        };
+        let application = r.read_string()?;
+        let features = r.read_string_array()?;
+        let schema = match r.read_u8()? {
+            0 => None,
+            _ => Some(r.read_string()?),
+        };
+        // Anything after this was added by a later version
+        Ok(FileHeader {
+            document_id,
+            created,
+            application,
+            features,
+            schema,
+        })
+    }
+}
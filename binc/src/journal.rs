@@ -1,11 +1,18 @@
 use crate::changes::Changes;
+use crate::error::BincError;
+use crate::header::FileHeader;
 use crate::operation::Operation;
-use crate::readwrite::{ReadExt, WriteExt};
+use crate::readwrite::{PositionReader, ReadExt, WriteExt};
 use io::Write;
+use std::fmt::{Display, Formatter};
 use std::io;
 use std::io::Read;
+use std::ops::Range;
 
 pub struct Journal {
+    /// Container version used by `write`. A journal read from a file keeps the version of the file.
+    pub version: u32,
+    pub header: FileHeader,
     pub operations: Vec<Operation>,
 }
 
@@ -19,10 +26,13 @@ impl From<Changes> for Journal {
 
 impl Journal {
     pub const CONTAINER_ID: u32 = u32::from_be_bytes(*b"binc");
-    pub const CONTAINER_VERSION: u32 = 1;
+    /// Latest container version, used for new journals
+    pub const CONTAINER_VERSION: u32 = 2;
 
     pub fn new() -> Journal {
         Journal {
+            version: Journal::CONTAINER_VERSION,
+            header: FileHeader::new(),
             operations: Vec::new(),
         }
     }
@@ -37,38 +47,649 @@ impl Journal {
         }
     }
 
+    /// Append a checksum covering the journal as `write` writes it, from the container id on
+    pub fn add_checksum(&mut self) -> io::Result<()> {
+        // Writing the checksum lists it in the header, which the checksum covers
+        if !self.header.has_feature("checksums") {
+            self.header.features.push("checksums".to_string());
+        }
+        let hash = self.write_hashed(&mut io::sink(), self.version)?;
+        self.add_operation(hash.checksum());
+        Ok(())
+    }
+
+    /// Checksum operation to append to a file that contains `data` so far
+    pub fn checksum_for_file(data: &[u8]) -> Result<Operation, BincError> {
+        let mut r = data;
+        Self::read_start(&mut r)?;
+        let mut hash = JournalHash::new();
+        hash.update(data);
+        Ok(hash.checksum())
+    }
+
+    /// Optional features the operations use, see `Operation::feature`
+    pub fn features_used(&self) -> Vec<&'static str> {
+        let mut features: Vec<&'static str> =
+            self.operations.iter().filter_map(|o| o.feature()).collect();
+        features.sort();
+        features.dedup();
+        features
+    }
+
     pub fn write<T: Write>(&self, w: &mut T) -> io::Result<()> {
+        self.write_version(w, self.version)
+    }
+
+    /// Write as a specific container version. Version 1 has no header, for readers that only
+    /// support that version. Checksums cover the version and header, so they are computed again
+    /// for the bytes written.
+    pub fn write_version<T: Write>(&self, w: &mut T, version: u32) -> io::Result<()> {
+        self.write_hashed(w, version).map(|_| ())
+    }
+
+    fn write_hashed<T: Write>(&self, w: &mut T, version: u32) -> io::Result<JournalHash> {
+        let mut w = HashingWriter {
+            inner: w,
+            hash: JournalHash::new(),
+        };
         w.write_u32(Journal::CONTAINER_ID)?;
-        w.write_u32(Journal::CONTAINER_VERSION)?;
+        w.write_u32(version)?;
+        match version {
+            1 => {}
+            2 => {
+                let mut header = self.header.clone();
+                for feature in self.features_used() {
+                    if !header.has_feature(feature) {
+                        header.features.push(feature.to_string());
+                    }
+                }
+                header.write(&mut w)?;
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unsupported container version {}", version),
+                ));
+            }
+        }
 
         for change in &self.operations {
-            change.write(w)?
+            match change {
+                Operation::Checksum { .. } => w.hash.checksum().write(&mut w)?,
+                _ => change.write(&mut w)?,
+            }
         }
-        Ok(())
+        Ok(w.hash)
     }
 
-    pub fn read<T: Read>(r: &mut T) -> io::Result<Journal> {
-        let mut repo = Journal::new();
-        let container_id = r.read_u32()?;
-        let container_version = r.read_u32()?;
-
+    /// Read the container id, version and header
+    fn read_start<T: Read>(r: &mut T) -> Result<(u32, FileHeader), BincError> {
+        let container_id = r.read_u32().map_err(|e| BincError::from_io(e, 0))?;
+        let version = r.read_u32().map_err(|e| BincError::from_io(e, 0))?;
         if container_id != Journal::CONTAINER_ID {
-            return Err(io::Error::from(io::ErrorKind::InvalidData));
-        } else if container_version != Journal::CONTAINER_VERSION {
-            return Err(io::Error::from(io::ErrorKind::InvalidData));
+            return Err(BincError::NotABincFile);
         }
+        match version {
+            1 => Ok((version, FileHeader::without_metadata())),
+            2 => {
+                let header = FileHeader::read(r).map_err(|e| BincError::from_io(e, 8))?;
+                Ok((version, header))
+            }
+            _ => Err(BincError::UnsupportedVersion { version }),
+        }
+    }
+
+    /// Read a complete journal. A journal that is truncated or damaged is an error, with the
+    /// offset of the first operation that can't be read. Use `read_recovering` to get the rest of
+    /// a damaged file.
+    pub fn read<T: Read>(r: &mut T) -> Result<Journal, BincError> {
+        Self::read_hashed(r).map(|(journal, _)| journal)
+    }
 
-        while let Ok(change) = Operation::read(r) {
+    /// Read a complete journal like `read`, and also return the hash of everything read, to
+    /// checksum operations appended to it later without reading it again
+    pub fn read_hashed<T: Read>(r: &mut T) -> Result<(Journal, JournalHash), BincError> {
+        let mut r = ChecksumReader::new(r);
+        let (version, header) = Self::read_start(&mut r)?;
+        r.finish_operation();
+        let mut repo = Journal {
+            version,
+            header,
+            operations: Vec::new(),
+        };
+
+        let mut mismatches = vec![];
+        let mut verified_until = 0;
+        loop {
+            let change = match Operation::read(&mut r) {
+                Ok(change) => change,
+                Err(BincError::EndOfStream) => break,
+                Err(e) => return Err(e.offset_by(r.operation_start)),
+            };
+            if let Operation::Checksum { data } = &change {
+                let start = r.operation_start;
+                if r.hash.checksum_bytes().as_slice() == data.as_slice() {
+                    verified_until = start + r.current.len() as u64;
+                } else {
+                    mismatches.push(verified_until..start);
+                }
+            }
+            r.finish_operation();
             repo.add_operation(change);
         }
 
-        Ok(repo)
+        if !mismatches.is_empty() {
+            return Err(BincError::ChecksumMismatch(ChecksumError { mismatches }));
+        }
+
+        Ok((repo, r.hash))
+    }
+
+    /// Read a journal that may be damaged. Bytes that can't be read as operations are skipped,
+    /// and reading continues at the next plausible operation. Checksums are not verified.
+    pub fn read_recovering<T: Read>(r: &mut T) -> io::Result<(Journal, RecoveryReport)> {
+        let mut data = vec![];
+        r.read_to_end(&mut data)?;
+        Ok(Self::recover(&data))
     }
 
-    pub fn append<T: Read>(&mut self, mut r: &mut T) -> io::Result<()> {
-        while let Ok(operation) = Operation::read(&mut r) {
-            self.add_operation(operation);
+    /// Recover all operations that can be found in `data`, see `read_recovering`
+    pub fn recover(data: &[u8]) -> (Journal, RecoveryReport) {
+        let mut journal = Journal::new();
+        let mut report = RecoveryReport::default();
+
+        let mut r = data;
+        let mut position = match Self::read_start(&mut r) {
+            Ok((version, header)) => {
+                journal.version = version;
+                journal.header = header;
+                data.len() - r.len()
+            }
+            Err(_) => {
+                let position = Self::damaged_header_length(data);
+                report.skip(0..position);
+                position
+            }
+        };
+
+        while position < data.len() {
+            if let Some((operation, length)) = read_operation_at(data, position) {
+                journal.add_operation(operation);
+                position += length;
+                continue;
+            }
+
+            // Damaged, look for the next operation that is known and followed by another one
+            let start = position;
+            position += 1;
+            while position < data.len() && !is_plausible_operation_at(data, position) {
+                position += 1;
+            }
+            report.skip(start..position);
+        }
+
+        (journal, report)
+    }
+
+    /// Read more operations, until the end of `r`. Checksums are not verified, as they cover
+    /// bytes that were read before. Offsets in errors are relative to the start of `r`, and the
+    /// operations before the error are kept.
+    pub fn append<T: Read>(&mut self, r: &mut T) -> Result<(), BincError> {
+        let mut r = PositionReader::new(r);
+        loop {
+            let start = r.position();
+            match Operation::read(&mut r) {
+                Ok(operation) => self.add_operation(operation),
+                Err(BincError::EndOfStream) => return Ok(()),
+                Err(e) => return Err(e.offset_by(start)),
+            }
+        }
+    }
+}
+
+impl Journal {
+    /// Where the operations are likely to start when the container id or header is damaged
+    fn damaged_header_length(data: &[u8]) -> usize {
+        let mut r = data.get(4..).unwrap_or_default();
+        if let (Ok(2), Ok(_)) = (r.read_u32(), FileHeader::read(&mut r)) {
+            data.len() - r.len()
+        } else {
+            8.min(data.len())
+        }
+    }
+}
+
+/// Read the operation starting at `position`, if it ends within `data`. Returns the operation and
+/// the number of bytes it takes.
+fn read_operation_at(data: &[u8], position: usize) -> Option<(Operation, usize)> {
+    let mut header = &data[position..];
+    header.read_length_flipped().ok()?;
+    let size = header.read_length().ok()?;
+    let header_length = data.len() - position - header.len();
+    let end = position
+        .checked_add(header_length)?
+        .checked_add(size)
+        .filter(|end| *end <= data.len())?;
+
+    let mut r = &data[position..end];
+    let operation = Operation::read(&mut r).ok()?;
+    Some((operation, end - position))
+}
+
+/// After damage, random bytes can look like an operation. Only resynchronize on a known operation
+/// that is followed by another one or the end of the data, or on a checksum, which has a marker.
+fn is_plausible_operation_at(data: &[u8], position: usize) -> bool {
+    match read_operation_at(data, position) {
+        Some((Operation::UnknownOperation { .. }, _)) | None => false,
+        Some((Operation::Checksum { .. }, _)) => true,
+        Some((_, length)) => {
+            let next = position + length;
+            next == data.len()
+                || matches!(
+                    read_operation_at(data, next),
+                    Some((operation, _)) if !matches!(operation, Operation::UnknownOperation { .. })
+                )
+        }
+    }
+}
+
+/// What `Journal::recover` had to skip to read a damaged journal
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Byte ranges that could not be read as operations, in order
+    pub skipped: Vec<Range<u64>>,
+}
+
+impl RecoveryReport {
+    /// True if nothing had to be skipped
+    pub fn is_clean(&self) -> bool {
+        self.skipped.is_empty()
+    }
+
+    pub fn skipped_bytes(&self) -> u64 {
+        self.skipped.iter().map(|r| r.end - r.start).sum()
+    }
+
+    fn skip(&mut self, range: Range<usize>) {
+        self.skipped.push(range.start as u64..range.end as u64);
+    }
+}
+
+/// Some checksums in a journal didn't match the data before them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumError {
+    /// Byte ranges that are likely corrupted. Each range starts after the last checksum that
+    /// matched and ends at the checksum that failed.
+    pub mismatches: Vec<Range<u64>>,
+}
+
+impl Display for ChecksumError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Checksum mismatch in bytes")?;
+        for (i, range) in self.mismatches.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}..{}", separator, range.start, range.end)?;
         }
         Ok(())
     }
 }
+
+impl std::error::Error for ChecksumError {}
+
+/// Running hash of the bytes of a journal, see `Journal::read_hashed`
+#[derive(Clone)]
+pub struct JournalHash {
+    hasher: blake3::Hasher,
+}
+
+impl JournalHash {
+    fn new() -> Self {
+        JournalHash {
+            hasher: blake3::Hasher::new(),
+        }
+    }
+
+    /// Add bytes appended to the journal
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    /// Checksum operation covering everything hashed so far
+    pub fn checksum(&self) -> Operation {
+        Operation::Checksum {
+            data: self.checksum_bytes(),
+        }
+    }
+
+    fn checksum_bytes(&self) -> Vec<u8> {
+        self.hasher.finalize().as_bytes().to_vec()
+    }
+}
+
+/// Hashes everything that is written
+struct HashingWriter<'a, W: Write> {
+    inner: &'a mut W,
+    hash: JournalHash,
+}
+
+impl<W: Write> Write for HashingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hash.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Hashes everything that is read, except the operation currently being read, so the hash is
+/// ready to compare when that operation turns out to be a checksum.
+struct ChecksumReader<'a, R: Read> {
+    inner: &'a mut R,
+    hash: JournalHash,
+    current: Vec<u8>,
+    operation_start: u64,
+}
+
+impl<'a, R: Read> ChecksumReader<'a, R> {
+    fn new(inner: &'a mut R) -> Self {
+        ChecksumReader {
+            inner,
+            hash: JournalHash::new(),
+            current: vec![],
+            operation_start: 0,
+        }
+    }
+
+    fn finish_operation(&mut self) {
+        self.hash.update(&self.current);
+        self.operation_start += self.current.len() as u64;
+        self.current.clear();
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.current.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node_id::NodeId;
+    use std::io::Cursor;
+
+    fn journal_with_checksums() -> Journal {
+        add_nodes_with_checksums(Journal::new())
+    }
+
+    fn add_nodes_with_checksums(mut journal: Journal) -> Journal {
+        for i in 1..4 {
+            journal.add_operation(Operation::AddNode {
+                id: NodeId::new(i),
+                node_type: 0,
+                parent: NodeId::ROOT_NODE,
+                index_in_parent: i - 1,
+            });
+            journal.add_checksum().unwrap();
+        }
+        journal
+    }
+
+    fn operations_length(journal: &Journal) -> usize {
+        let mut data = vec![];
+        for operation in &journal.operations {
+            operation.write(&mut data).unwrap();
+        }
+        data.len()
+    }
+
+    #[test]
+    fn test_checksums_verify() {
+        let mut data = vec![];
+        journal_with_checksums().write(&mut data).unwrap();
+        let journal = Journal::read(&mut Cursor::new(data)).unwrap();
+        assert_eq!(journal.operations.len(), 6);
+    }
+
+    #[test]
+    fn test_append_with_running_hash() {
+        let original = journal_with_checksums();
+        let mut data = vec![];
+        original.write(&mut data).unwrap();
+        let (journal, mut hash) = Journal::read_hashed(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(journal.operations, original.operations);
+
+        let mut appended = vec![];
+        original.operations[0].write(&mut appended).unwrap();
+        hash.update(&appended);
+        data.extend(&appended);
+        assert_eq!(hash.checksum(), Journal::checksum_for_file(&data).unwrap());
+        hash.checksum().write(&mut data).unwrap();
+
+        let journal = Journal::read(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(journal.operations.len(), 8);
+    }
+
+    #[test]
+    fn test_checksum_mismatch_reports_range() {
+        let mut data = vec![];
+        let journal = journal_with_checksums();
+        journal.write(&mut data).unwrap();
+
+        // Corrupt the node type of the second AddNode, which comes after the first checksum
+        let mut first_part = vec![];
+        Journal {
+            version: journal.version,
+            header: journal.header.clone(),
+            operations: journal.operations[..2].to_vec(),
+        }
+        .write(&mut first_part)
+        .unwrap();
+        let corrupt_at = first_part.len() + 3;
+        data[corrupt_at] ^= 0x01;
+
+        let error = Journal::read(&mut Cursor::new(data)).err().unwrap();
+        let BincError::ChecksumMismatch(error) = error else {
+            panic!("Expected a checksum mismatch, got {}", error);
+        };
+        let mismatches = &error.mismatches;
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(mismatches[0].start, first_part.len() as u64);
+        assert!(mismatches[0].contains(&(corrupt_at as u64)));
+        assert_eq!(mismatches[1].start, first_part.len() as u64);
+    }
+
+    #[test]
+    fn test_recover_clean_journal() {
+        let original = journal_with_checksums();
+        let mut data = vec![];
+        original.write(&mut data).unwrap();
+        let (journal, report) = Journal::recover(&data);
+        assert!(report.is_clean());
+        assert_eq!(journal.operations, original.operations);
+    }
+
+    #[test]
+    fn test_recover_skips_damaged_range() {
+        let original = journal_with_checksums();
+        let mut data = vec![];
+        original.write(&mut data).unwrap();
+
+        // Overwrite the second AddNode and the start of the checksum after it
+        let mut first_part = vec![];
+        Journal {
+            version: original.version,
+            header: original.header.clone(),
+            operations: original.operations[..2].to_vec(),
+        }
+        .write(&mut first_part)
+        .unwrap();
+        let damaged = first_part.len()..first_part.len() + 10;
+        for b in &mut data[damaged.clone()] {
+            *b = 0xEE;
+        }
+
+        let (journal, report) = Journal::recover(&data);
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].start, damaged.start as u64);
+        assert!(report.skipped[0].end >= damaged.end as u64);
+        assert_eq!(journal.operations[..2], original.operations[..2]);
+        assert_eq!(
+            journal.operations.last(),
+            original.operations.last(),
+            "Operations after the damage are recovered"
+        );
+    }
+
+    #[test]
+    fn test_recover_truncated_and_bad_header() {
+        let original = journal_with_checksums();
+        let mut data = vec![];
+        original.write(&mut data).unwrap();
+        let header_length = data.len() - operations_length(&original);
+        data[0] = b'x';
+        data.truncate(data.len() - 5);
+
+        let (journal, report) = Journal::recover(&data);
+        assert_eq!(report.skipped[0], 0..header_length as u64);
+        assert_eq!(report.skipped.last().unwrap().end, data.len() as u64);
+        assert_eq!(journal.operations.len(), 5);
+
+        let mut data = vec![];
+        original.write_version(&mut data, 1).unwrap();
+        data[0] = b'x';
+        let (journal, report) = Journal::recover(&data);
+        assert_eq!(report.skipped, vec![0..8]);
+        assert_eq!(journal.operations.len(), 6);
+    }
+
+    #[test]
+    fn test_read_errors_have_offsets() {
+        let mut data = vec![];
+        let original = journal_with_checksums();
+        original.write(&mut data).unwrap();
+        let mut last = vec![];
+        original
+            .operations
+            .last()
+            .unwrap()
+            .write(&mut last)
+            .unwrap();
+        let last_start = (data.len() - last.len()) as u64;
+
+        let mut journal = Journal::new();
+        let header_length = data.len() - operations_length(&original);
+        journal.append(&mut &data[header_length..]).unwrap();
+        assert_eq!(journal.operations.len(), 6);
+
+        data.truncate(data.len() - 5);
+        match Journal::read(&mut Cursor::new(&data)) {
+            Err(BincError::Truncated { offset }) => assert_eq!(offset, last_start),
+            other => panic!("Expected truncation, got {:?}", other.err()),
+        }
+
+        data[4..8].copy_from_slice(&7u32.to_be_bytes());
+        assert!(matches!(
+            Journal::read(&mut Cursor::new(&data)),
+            Err(BincError::UnsupportedVersion { version: 7 })
+        ));
+
+        data[0] = b'x';
+        assert!(matches!(
+            Journal::read(&mut Cursor::new(&data)),
+            Err(BincError::NotABincFile)
+        ));
+        assert!(matches!(
+            Journal::read(&mut Cursor::new(&data[..3])),
+            Err(BincError::Truncated { offset: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_huge_array_lengths_are_rejected() {
+        use crate::operation::OperationIds;
+        use crate::readwrite::WriteExt;
+
+        for operation in [OperationIds::SET_STRING_ARRAY, OperationIds::SET_UUID_ARRAY] {
+            // Node 1, attribute 0, and an array that claims 2^40 entries
+            let mut content = vec![];
+            content.write_length(1).unwrap();
+            content.write_length(0).unwrap();
+            content.write_length(1 << 40).unwrap();
+
+            let mut data = vec![];
+            Journal::new().write_version(&mut data, 1).unwrap();
+            let header_length = data.len() as u64;
+            data.write_length_flipped(operation as usize).unwrap();
+            data.write_length(content.len()).unwrap();
+            data.extend(content);
+
+            assert!(Journal::read(&mut Cursor::new(&data)).is_err());
+            let (journal, report) = Journal::recover(&data);
+            assert!(journal.operations.is_empty());
+            assert_eq!(report.skipped, vec![header_length..data.len() as u64]);
+        }
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let mut original = Journal::new();
+        original.header.schema = Some("https://example.com/tasks".to_string());
+        let original = add_nodes_with_checksums(original);
+        let mut data = vec![];
+        original.write(&mut data).unwrap();
+
+        let journal = Journal::read(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(journal.version, 2);
+        assert_eq!(journal.header.document_id, original.header.document_id);
+        assert_eq!(journal.header.created, original.header.created);
+        assert_eq!(journal.header.schema, original.header.schema);
+        assert_eq!(journal.header.features, vec!["checksums"]);
+        assert_eq!(journal.operations, original.operations);
+
+        let mut again = vec![];
+        journal.write(&mut again).unwrap();
+        assert_eq!(again, data);
+
+        // The header is covered by the checksums
+        let schema_at = data.windows(7).position(|w| w == b"example").unwrap();
+        data[schema_at] = b'E';
+        assert!(matches!(
+            Journal::read(&mut Cursor::new(&data)),
+            Err(BincError::ChecksumMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_version_1_is_kept() {
+        let original = journal_with_checksums();
+        let mut data = vec![];
+        original.write_version(&mut data, 1).unwrap();
+        assert_eq!(&data[4..8], &1u32.to_be_bytes());
+
+        // Checksums cover the version, so they are computed again for version 1
+        let journal = Journal::read(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(journal.version, 1);
+        assert_eq!(journal.header.created, None);
+        assert_eq!(journal.operations.len(), original.operations.len());
+        assert_ne!(journal.operations[1], original.operations[1]);
+
+        let mut again = vec![];
+        journal.write(&mut again).unwrap();
+        assert_eq!(again, data);
+
+        let mut version_1 = Journal::new();
+        version_1.version = 1;
+        let version_1 = add_nodes_with_checksums(version_1);
+        let mut data = vec![];
+        version_1.write(&mut data).unwrap();
+        let journal = Journal::read(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(journal.operations, version_1.operations);
+    }
+}
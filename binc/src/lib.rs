@@ -3,12 +3,22 @@ pub mod builder;
 pub mod changes;
 pub mod client;
 pub mod comments;
+pub mod compaction;
+pub mod compatibility;
 pub mod document;
+pub mod error;
+pub mod header;
 pub mod journal;
+pub mod merge;
 pub mod name_dictionary;
 pub mod network_protocol;
 pub mod node_id;
 pub mod node_store;
+pub mod numeric;
 pub mod operation;
+pub mod position;
 pub mod readwrite;
+pub mod sources;
+mod undo;
 pub mod util;
+pub mod validation;
@@ -0,0 +1,842 @@
+use crate::attributes::AttributeValue;
+use crate::error::InvalidOperation;
+use crate::journal::Journal;
+use crate::node_id::{NodeId, NodeIdGenerator};
+use crate::node_store::NodeStore;
+use crate::operation::Operation;
+use crate::validation::{dictionary, NameKind};
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// The result of `merge`
+pub struct Merge {
+    /// The base, then their operations, then ours rewritten to follow theirs
+    pub journal: Journal,
+    /// Our operations as they are at the end of `journal`. Operations left out because of a
+    /// conflict are not included.
+    pub ours: Vec<Operation>,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Which of the two merged operation sequences something came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Ours,
+    Theirs,
+}
+
+/// A change both sides made in ways that can't both be kept. Node, attribute and parent ids are
+/// the ones in the merged journal.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Conflict {
+    /// Both set the same attribute to different values, or one removed it. Ours is kept.
+    Attribute {
+        node: NodeId,
+        attribute: usize,
+        ours: Option<AttributeValue>,
+        theirs: Option<AttributeValue>,
+    },
+    /// Both renamed the node. Ours is kept.
+    Name {
+        node: NodeId,
+        ours: Option<String>,
+        theirs: Option<String>,
+    },
+    /// Both changed the type of the node. Ours is kept.
+    Type {
+        node: NodeId,
+        ours: Option<usize>,
+        theirs: Option<usize>,
+    },
+    /// Both moved the node to different parents. It ends up where ours put it.
+    Moved {
+        node: NodeId,
+        ours: NodeId,
+        theirs: NodeId,
+    },
+    /// One side changed a node, or something under it, that the other side removed. The node
+    /// stays removed, and `operation` has no effect.
+    RemovedNode {
+        node: NodeId,
+        removed_by: Side,
+        operation: Operation,
+    },
+    /// One of our operations doesn't fit after theirs for another reason, and is left out
+    Rejected {
+        operation: Operation,
+        reason: InvalidOperation,
+    },
+}
+
+impl Display for Side {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Side::Ours => write!(f, "ours"),
+            Side::Theirs => write!(f, "theirs"),
+        }
+    }
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let value = |value: &Option<AttributeValue>| match value {
+            Some(value) => value.to_string(),
+            None => "nothing".to_string(),
+        };
+        match self {
+            Conflict::Attribute {
+                node,
+                attribute,
+                ours,
+                theirs,
+            } => write!(
+                f,
+                "Attribute {} of node {} set to {} in ours and {} in theirs",
+                attribute,
+                node,
+                value(ours),
+                value(theirs)
+            ),
+            Conflict::Name { node, ours, theirs } => write!(
+                f,
+                "Node {} named {:?} in ours and {:?} in theirs",
+                node, ours, theirs
+            ),
+            Conflict::Type { node, ours, theirs } => write!(
+                f,
+                "Type of node {} set to {:?} in ours and {:?} in theirs",
+                node, ours, theirs
+            ),
+            Conflict::Moved { node, ours, theirs } => write!(
+                f,
+                "Node {} moved to {} in ours and {} in theirs",
+                node, ours, theirs
+            ),
+            Conflict::RemovedNode {
+                node,
+                removed_by,
+                operation,
+            } => write!(
+                f,
+                "Node {} was removed in {}, so {} is lost",
+                node, removed_by, operation
+            ),
+            Conflict::Rejected { operation, reason } => {
+                write!(f, "{} is left out: {}", operation, reason)
+            }
+        }
+    }
+}
+
+/// Merge two sequences of operations that were both added after `base`. Their operations are
+/// kept as they are, and ours are rewritten to follow them: nodes, names and sources we added
+/// get new ids if theirs took the same ones, and nodes we added or moved are placed after the
+/// same sibling as before. Where both changed the same thing differently, ours wins and the
+/// conflict is reported.
+///
+/// Checksums in ours are left out, as they wouldn't match the merged journal.
+pub fn merge(base: &Journal, ours: &[Operation], theirs: &[Operation]) -> Merge {
+    let mut journal = Journal::new();
+    journal.version = base.version;
+    journal.header = base.header.clone();
+    journal.operations = base.operations.clone();
+
+    let base_nodes = replay(NodeStore::new(), &base.operations);
+    let theirs_nodes = replay(base_nodes.clone(), theirs);
+    journal.operations.extend_from_slice(theirs);
+
+    let all = base.operations.iter().chain(theirs).chain(ours);
+    let mut generator = NodeIdGenerator::new();
+    all.for_each(|operation| generator.skip_past_ids_in(operation));
+
+    let mut merger = Merger {
+        ours_nodes: base_nodes.clone(),
+        merged: theirs_nodes.clone(),
+        theirs,
+        theirs_ids: theirs.iter().flat_map(|o| o.node_ids()).collect(),
+        generator,
+        nodes: HashMap::new(),
+        names: HashMap::new(),
+        sources: HashMap::new(),
+        comments: HashMap::new(),
+        dropped: HashSet::new(),
+        changed: vec![],
+        result: vec![],
+        conflicts: vec![],
+    };
+    for operation in ours {
+        merger.add(operation);
+    }
+    merger.find_conflicting_changes(&base_nodes, &theirs_nodes);
+
+    journal.operations.extend_from_slice(&merger.result);
+    Merge {
+        journal,
+        ours: merger.result,
+        conflicts: merger.conflicts,
+    }
+}
+
+/// Merge two journals that have the first `base_revision` operations in common
+pub fn merge_journals(ours: &Journal, theirs: &Journal, base_revision: usize) -> Merge {
+    let mut base = Journal::new();
+    base.version = theirs.version;
+    base.header = theirs.header.clone();
+    base.operations = theirs.operations[..base_revision].to_vec();
+    merge(
+        &base,
+        &ours.operations[base_revision..],
+        &theirs.operations[base_revision..],
+    )
+}
+
+fn replay(mut nodes: NodeStore, operations: &[Operation]) -> NodeStore {
+    for operation in operations {
+        let _ = operation.apply(&mut nodes);
+    }
+    nodes
+}
+
+/// Something one of our operations changed on a node, compared with theirs once all are added
+#[derive(Clone, Copy, PartialEq)]
+enum Change {
+    Name(NodeId),
+    Type(NodeId),
+    Attribute(NodeId, usize),
+    Parent(NodeId),
+}
+
+struct Merger<'a> {
+    /// The nodes as our operations leave them, before they are rewritten
+    ours_nodes: NodeStore,
+    merged: NodeStore,
+    theirs: &'a [Operation],
+    /// Every node id their operations use
+    theirs_ids: HashSet<NodeId>,
+    /// Generates ids for the nodes we added with ids theirs also used
+    generator: NodeIdGenerator,
+    /// New ids for what we added, by the id we used. Anything else keeps its id.
+    nodes: HashMap<NodeId, NodeId>,
+    names: HashMap<(NameKind, usize), usize>,
+    sources: HashMap<usize, usize>,
+    /// Comment ids by node and the comment id we used
+    comments: HashMap<(NodeId, usize), usize>,
+    /// Nodes we added that are left out, along with everything that was done to them
+    dropped: HashSet<NodeId>,
+    changed: Vec<Change>,
+    result: Vec<Operation>,
+    conflicts: Vec<Conflict>,
+}
+
+impl Merger<'_> {
+    fn node(&self, id: NodeId) -> NodeId {
+        *self.nodes.get(&id).unwrap_or(&id)
+    }
+
+    fn name(&self, kind: NameKind, id: usize) -> usize {
+        *self.names.get(&(kind, id)).unwrap_or(&id)
+    }
+
+    fn source(&self, id: usize) -> usize {
+        *self.sources.get(&id).unwrap_or(&id)
+    }
+
+    fn comment(&self, node: NodeId, comment: usize) -> usize {
+        *self.comments.get(&(node, comment)).unwrap_or(&comment)
+    }
+
+    fn add(&mut self, operation: &Operation) {
+        // Operations that didn't change anything for us are left out
+        if operation.apply(&mut self.ours_nodes).is_err() {
+            return;
+        }
+        if operation
+            .node_ids()
+            .iter()
+            .any(|id| self.dropped.contains(id))
+        {
+            return;
+        }
+        let Some(rewritten) = self.rewrite(operation) else {
+            return;
+        };
+        if let Operation::RemoveNode { id } = rewritten {
+            self.report_removed_changes(id);
+        }
+
+        match rewritten.apply(&mut self.merged) {
+            Ok(()) => {
+                self.record_change(operation);
+                self.result.push(rewritten);
+            }
+            Err(reason) => {
+                if let Operation::AddNode { id, .. } | Operation::AddNodeAt { id, .. } = operation {
+                    self.dropped.insert(*id);
+                }
+                self.conflicts.push(match reason {
+                    InvalidOperation::NodeNotFound(node) => Conflict::RemovedNode {
+                        node,
+                        removed_by: Side::Theirs,
+                        operation: operation.clone(),
+                    },
+                    reason => Conflict::Rejected {
+                        operation: rewritten,
+                        reason,
+                    },
+                });
+            }
+        }
+    }
+
+    /// The operation with the ids it has after the merge, or `None` if it should be left out
+    fn rewrite(&mut self, operation: &Operation) -> Option<Operation> {
+        let rewritten = match operation {
+            Operation::AddNode {
+                id,
+                node_type,
+                parent,
+                ..
+            } => {
+                self.assign_node_id(*id);
+                Operation::AddNode {
+                    id: self.node(*id),
+                    node_type: self.name(NameKind::Type, *node_type),
+                    parent: self.node(*parent),
+                    index_in_parent: self.index_in_merged(*id, *parent),
+                }
+            }
+            Operation::MoveNode { id, new_parent, .. } => Operation::MoveNode {
+                id: self.node(*id),
+                new_parent: self.node(*new_parent),
+                index_in_new_parent: self.index_in_merged(*id, *new_parent),
+            },
+            // Positions don't depend on the siblings, so only the ids change
+            Operation::AddNodeAt {
+                id,
+                node_type,
+                parent,
+                position,
+            } => {
+                self.assign_node_id(*id);
+                Operation::AddNodeAt {
+                    id: self.node(*id),
+                    node_type: self.name(NameKind::Type, *node_type),
+                    parent: self.node(*parent),
+                    position: position.clone(),
+                }
+            }
+            Operation::MoveNodeTo {
+                id,
+                new_parent,
+                position,
+            } => Operation::MoveNodeTo {
+                id: self.node(*id),
+                new_parent: self.node(*new_parent),
+                position: position.clone(),
+            },
+            Operation::RemoveNode { id } => Operation::RemoveNode { id: self.node(*id) },
+            Operation::SetType { node, type_id } => Operation::SetType {
+                node: self.node(*node),
+                type_id: self.name(NameKind::Type, *type_id),
+            },
+            Operation::SetName { node, name } => Operation::SetName {
+                node: self.node(*node),
+                name: name.clone(),
+            },
+            Operation::DefineTypeName { id, name } => {
+                let id = self.define_name(NameKind::Type, *id, name)?;
+                Operation::DefineTypeName {
+                    id,
+                    name: name.clone(),
+                }
+            }
+            Operation::DefineAttributeName { id, name } => {
+                let id = self.define_name(NameKind::Attribute, *id, name)?;
+                Operation::DefineAttributeName {
+                    id,
+                    name: name.clone(),
+                }
+            }
+            Operation::DefineTagName { id, name } => {
+                let id = self.define_name(NameKind::Tag, *id, name)?;
+                Operation::DefineTagName {
+                    id,
+                    name: name.clone(),
+                }
+            }
+            Operation::SetAttribute {
+                node,
+                attribute,
+                value,
+            } => Operation::SetAttribute {
+                node: self.node(*node),
+                attribute: self.name(NameKind::Attribute, *attribute),
+                value: self.value(value),
+            },
+            Operation::RemoveAttribute { node, attribute } => Operation::RemoveAttribute {
+                node: self.node(*node),
+                attribute: self.name(NameKind::Attribute, *attribute),
+            },
+            Operation::SetTag { node, tag } => Operation::SetTag {
+                node: self.node(*node),
+                tag: self.name(NameKind::Tag, *tag),
+            },
+            Operation::RemoveTag { node, tag } => Operation::RemoveTag {
+                node: self.node(*node),
+                tag: self.name(NameKind::Tag, *tag),
+            },
+            Operation::AddComment {
+                node,
+                comment,
+                author,
+                response_to,
+            } => {
+                let ours = self.ours_nodes.existing(*node).ok()?.comments.len();
+                let merged = self
+                    .merged
+                    .resolve(self.node(*node))
+                    .map_or(0, |n| n.comments.len());
+                self.comments.insert((*node, ours), merged + 1);
+                Operation::AddComment {
+                    node: self.node(*node),
+                    comment: comment.clone(),
+                    author: author.clone(),
+                    response_to: self.comment(*node, *response_to),
+                }
+            }
+            Operation::EditComment {
+                node,
+                comment,
+                text,
+            } => Operation::EditComment {
+                node: self.node(*node),
+                comment: self.comment(*node, *comment),
+                text: text.clone(),
+            },
+            Operation::RetractComment { node, comment } => Operation::RetractComment {
+                node: self.node(*node),
+                comment: self.comment(*node, *comment),
+            },
+            Operation::AddSource {
+                id,
+                importer,
+                path,
+                hash,
+                imported,
+            } => {
+                // Both imported the same file, so ours is an update of theirs
+                if let Some(theirs) = self.merged.sources.find(importer, path) {
+                    self.sources.insert(*id, theirs);
+                    return Some(Operation::UpdateSource {
+                        id: theirs,
+                        hash: *hash,
+                        imported: *imported,
+                    });
+                }
+                let next_id = self.merged.sources.next_id();
+                if *id < next_id {
+                    self.sources.insert(*id, next_id);
+                }
+                Operation::AddSource {
+                    id: self.source(*id),
+                    importer: importer.clone(),
+                    path: path.clone(),
+                    hash: *hash,
+                    imported: *imported,
+                }
+            }
+            Operation::UpdateSource { id, hash, imported } => Operation::UpdateSource {
+                id: self.source(*id),
+                hash: *hash,
+                imported: *imported,
+            },
+            Operation::RemoveSource { id } => Operation::RemoveSource {
+                id: self.source(*id),
+            },
+            Operation::Checksum { .. } => return None,
+            Operation::Snapshot { .. }
+            | Operation::ReserveNodeIds { .. }
+            | Operation::UnknownOperation { .. } => operation.clone(),
+        };
+        Some(rewritten)
+    }
+
+    /// Give a node we added a new id if theirs use the same one
+    fn assign_node_id(&mut self, id: NodeId) {
+        if self.theirs_ids.contains(&id) || self.merged.resolve(id).is_some() {
+            let new_id = self.generator.next_id();
+            self.nodes.insert(id, new_id);
+        }
+    }
+
+    /// The id for a name we defined. Names theirs also defined aren't defined again.
+    fn define_name(&mut self, kind: NameKind, id: usize, name: &str) -> Option<usize> {
+        let names = dictionary(&self.merged, kind);
+        if let Some(theirs) = names.get_index(name) {
+            self.names.insert((kind, id), theirs);
+            return None;
+        }
+        if names.get(id).is_some() {
+            let new_id = max(names.len(), 1);
+            self.names.insert((kind, id), new_id);
+        }
+        Some(self.name(kind, id))
+    }
+
+    fn value(&self, value: &AttributeValue) -> AttributeValue {
+        match value {
+            AttributeValue::NodeReference(id) => AttributeValue::NodeReference(self.node(*id)),
+            AttributeValue::NodeReferenceArray(ids) => {
+                AttributeValue::NodeReferenceArray(ids.iter().map(|id| self.node(*id)).collect())
+            }
+            _ => value.clone(),
+        }
+    }
+
+    /// Where to put a node we added or moved, so it comes after the same sibling as it did for
+    /// us. Siblings that aren't there after the merge are skipped. `id` and `parent` are the
+    /// ids we used, and our operation must have been applied.
+    fn index_in_merged(&self, id: NodeId, parent: NodeId) -> usize {
+        let siblings = match self.ours_nodes.resolve(parent) {
+            Some(parent) => &parent.children,
+            None => return 0,
+        };
+        let position = siblings.iter().position(|c| *c == id).unwrap_or(0);
+
+        let merged_id = self.node(id);
+        let merged_parent = self.node(parent);
+        let Some(merged_siblings) = self.merged.resolve(merged_parent).map(|p| &p.children) else {
+            return 0;
+        };
+        let others: Vec<NodeId> = merged_siblings
+            .iter()
+            .filter(|c| **c != merged_id)
+            .copied()
+            .collect();
+        let index = siblings[..position]
+            .iter()
+            .rev()
+            .find_map(|s| others.iter().position(|c| *c == self.node(*s)))
+            .map_or(0, |p| p + 1);
+
+        // A move within the same parent counts the node at its old position
+        match merged_siblings.iter().position(|c| *c == merged_id) {
+            Some(old_index) if old_index <= index => index + 1,
+            _ => index,
+        }
+    }
+
+    /// Their changes to the node, or to anything under it, are lost when we remove it
+    fn report_removed_changes(&mut self, id: NodeId) {
+        if self.merged.resolve(id).is_none() {
+            return;
+        }
+        let removed: HashSet<NodeId> = self.merged.subtree(id).iter().map(|n| n.id).collect();
+        for theirs in self.theirs {
+            let target = theirs.node_ids().first().copied();
+            if target.is_some_and(|target| removed.contains(&target)) {
+                self.conflicts.push(Conflict::RemovedNode {
+                    node: id,
+                    removed_by: Side::Ours,
+                    operation: theirs.clone(),
+                });
+            }
+        }
+    }
+
+    fn record_change(&mut self, operation: &Operation) {
+        let change = match operation {
+            Operation::SetName { node, .. } => Change::Name(*node),
+            Operation::SetType { node, .. } => Change::Type(*node),
+            Operation::SetAttribute {
+                node, attribute, ..
+            }
+            | Operation::RemoveAttribute { node, attribute } => {
+                Change::Attribute(*node, *attribute)
+            }
+            Operation::MoveNode { id, .. } | Operation::MoveNodeTo { id, .. } => {
+                Change::Parent(*id)
+            }
+            _ => return,
+        };
+        if !self.changed.contains(&change) {
+            self.changed.push(change);
+        }
+    }
+
+    /// Report what both sides changed to different values. Only nodes that were in the base
+    /// can have been changed by both, and they keep their ids.
+    fn find_conflicting_changes(&mut self, base: &NodeStore, theirs: &NodeStore) {
+        for change in self.changed.clone() {
+            let (Change::Name(node)
+            | Change::Type(node)
+            | Change::Attribute(node, _)
+            | Change::Parent(node)) = change;
+            let (Some(before), Some(ours), Some(theirs)) = (
+                base.resolve(node),
+                self.ours_nodes.resolve(node),
+                theirs.resolve(node),
+            ) else {
+                continue;
+            };
+            let conflict = match change {
+                Change::Name(_) => {
+                    differs(&before.name, &ours.name, &theirs.name).then_some(Conflict::Name {
+                        node,
+                        ours: ours.name.clone(),
+                        theirs: theirs.name.clone(),
+                    })
+                }
+                Change::Type(_) => {
+                    let ours_type = ours.type_id.map(|t| self.name(NameKind::Type, t));
+                    differs(&before.type_id, &ours_type, &theirs.type_id).then_some(
+                        Conflict::Type {
+                            node,
+                            ours: ours_type,
+                            theirs: theirs.type_id,
+                        },
+                    )
+                }
+                Change::Attribute(_, attribute) => {
+                    let merged_attribute = self.name(NameKind::Attribute, attribute);
+                    let before = before.get_attribute(attribute).cloned();
+                    let ours = ours.get_attribute(attribute).map(|v| self.value(v));
+                    let theirs = theirs.get_attribute(merged_attribute).cloned();
+                    differs(&before, &ours, &theirs).then_some(Conflict::Attribute {
+                        node,
+                        attribute: merged_attribute,
+                        ours,
+                        theirs,
+                    })
+                }
+                Change::Parent(_) => {
+                    let ours_parent = self.node(ours.parent);
+                    differs(&before.parent, &ours_parent, &theirs.parent).then_some(
+                        Conflict::Moved {
+                            node,
+                            ours: ours_parent,
+                            theirs: theirs.parent,
+                        },
+                    )
+                }
+            };
+            self.conflicts.extend(conflict);
+        }
+    }
+}
+
+/// True if both sides changed the value, to different things
+fn differs<T: PartialEq>(before: &T, ours: &T, theirs: &T) -> bool {
+    before != ours && before != theirs && ours != theirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::Changes;
+    use crate::compaction::same_nodes;
+    use crate::position::Position;
+
+    const A: NodeId = NodeId { id: 1 };
+    const B: NodeId = NodeId { id: 2 };
+    const C: NodeId = NodeId { id: 3 };
+    const D: NodeId = NodeId { id: 4 };
+
+    /// A, B and C under the root, with a status on A
+    fn base() -> Journal {
+        let mut changes = Changes::new();
+        changes
+            .add_node(A, NodeId::ROOT_NODE, 0)
+            .add_node(B, NodeId::ROOT_NODE, 1)
+            .add_node(C, NodeId::ROOT_NODE, 2)
+            .set_string_s(A, "status", "open");
+        Journal::from(changes)
+    }
+
+    fn nodes(journal: &Journal) -> NodeStore {
+        replay(NodeStore::new(), &journal.operations)
+    }
+
+    fn name(nodes: &NodeStore, id: NodeId) -> Option<&str> {
+        nodes.resolve(id).and_then(|n| n.get_name())
+    }
+
+    fn define(id: usize, name: &str) -> Operation {
+        Operation::DefineAttributeName {
+            id,
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_added_nodes_get_new_ids_and_keep_their_place() {
+        let mut theirs = Changes::new();
+        theirs
+            .add_node(D, NodeId::ROOT_NODE, 0)
+            .set_name(D, "theirs");
+        let mut ours = Changes::new();
+        ours.add_node(D, NodeId::ROOT_NODE, 2)
+            .set_name(D, "ours")
+            .add_node(NodeId::new(5), D, 0);
+        ours.operations.push(define(1, "link"));
+        ours.set_attribute(A, 1, AttributeValue::NodeReference(D));
+
+        let merge = merge(&base(), &ours.operations, &theirs.operations);
+        assert_eq!(merge.conflicts, vec![]);
+
+        // Our D is placed after B, as it was for us
+        let nodes = nodes(&merge.journal);
+        let ours_d = nodes.find_roots()[3];
+        assert_eq!(nodes.find_roots(), &vec![D, A, B, ours_d, C]);
+        assert_eq!(name(&nodes, D), Some("theirs"));
+        assert_eq!(name(&nodes, ours_d), Some("ours"));
+        assert_eq!(nodes.resolve(ours_d).unwrap().children.len(), 1);
+        assert_eq!(
+            nodes.resolve(A).unwrap().get_attribute(1),
+            Some(&AttributeValue::NodeReference(ours_d))
+        );
+    }
+
+    #[test]
+    fn test_names_defined_by_both() {
+        let mut theirs = Changes::new();
+        theirs.operations.push(define(1, "owner"));
+        theirs.set_string(B, 1, "bob").set_string(C, 0, "done");
+        let mut ours = Changes::new();
+        ours.operations.push(define(1, "priority"));
+        ours.set_string(B, 1, "high");
+        ours.operations.push(define(2, "owner"));
+        ours.set_string(C, 2, "ann");
+
+        let merge = merge(&base(), &ours.operations, &theirs.operations);
+        assert_eq!(merge.conflicts, vec![]);
+        let nodes = nodes(&merge.journal);
+        assert_eq!(nodes.attribute_names.get(1), Some("owner"));
+        assert_eq!(nodes.attribute_names.get(2), Some("priority"));
+        let b = nodes.resolve(B).unwrap();
+        assert_eq!(b.get_string_attribute(1), Some("bob"));
+        assert_eq!(b.get_string_attribute(2), Some("high"));
+        assert_eq!(
+            nodes.resolve(C).unwrap().get_string_attribute(1),
+            Some("ann")
+        );
+    }
+
+    #[test]
+    fn test_conflicts_are_reported() {
+        let mut theirs = Changes::new();
+        theirs
+            .set_string(A, 0, "done")
+            .set_name(B, "theirs")
+            .remove_node(C);
+        let mut ours = Changes::new();
+        ours.set_string(A, 0, "blocked")
+            .set_name(C, "renamed")
+            .remove_node(B);
+
+        let merge = merge(&base(), &ours.operations, &theirs.operations);
+        let nodes = nodes(&merge.journal);
+        assert_eq!(
+            nodes.resolve(A).unwrap().get_string_attribute(0),
+            Some("blocked")
+        );
+        assert!(nodes.resolve(B).is_none());
+        assert!(nodes.resolve(C).is_none());
+
+        assert_eq!(
+            merge.conflicts,
+            vec![
+                Conflict::RemovedNode {
+                    node: C,
+                    removed_by: Side::Theirs,
+                    operation: ours.operations[1].clone(),
+                },
+                Conflict::RemovedNode {
+                    node: B,
+                    removed_by: Side::Ours,
+                    operation: theirs.operations[1].clone(),
+                },
+                Conflict::Attribute {
+                    node: A,
+                    attribute: 0,
+                    ours: Some(AttributeValue::String("blocked".to_string())),
+                    theirs: Some(AttributeValue::String("done".to_string())),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_moves_and_comments() {
+        let mut theirs = Changes::new();
+        theirs
+            .move_node(C, A, 0)
+            .add_comment(B, "From bob", "bob", 0);
+        let mut ours = Changes::new();
+        ours.move_node(C, B, 0)
+            .add_comment(B, "From ann", "ann", 0)
+            .add_comment(B, "Reply", "ann", 1)
+            .edit_comment(B, 1, "Edited");
+
+        let merge = merge(&base(), &ours.operations, &theirs.operations);
+        assert_eq!(
+            merge.conflicts,
+            vec![Conflict::Moved {
+                node: C,
+                ours: B,
+                theirs: A
+            }]
+        );
+
+        let nodes = nodes(&merge.journal);
+        assert_eq!(nodes.resolve(C).unwrap().parent, B);
+        let comments = &nodes.resolve(B).unwrap().comments;
+        assert_eq!(comments.get(1).unwrap().text, "From bob");
+        assert_eq!(comments.get(2).unwrap().text, "Edited");
+        assert_eq!(comments.get(3).unwrap().response_to, Some(2));
+    }
+
+    #[test]
+    fn test_merge_without_their_changes_is_ours() {
+        let mut ours = Changes::new();
+        ours.add_node(D, A, 0)
+            .move_node(C, NodeId::ROOT_NODE, 0)
+            .set_name(D, "new");
+        let mut expected = base();
+        expected.operations.extend(ours.operations.clone());
+
+        let merge = merge_journals(&expected, &base(), base().operations.len());
+        assert_eq!(merge.ours, ours.operations);
+        assert!(same_nodes(&nodes(&merge.journal), &nodes(&expected)));
+    }
+
+    #[test]
+    fn test_positions_converge() {
+        let e = NodeId::new(5);
+        let first = Position::between(None, None);
+        let last = Position::between(Some(&first), None);
+        let middle = Position::between(Some(&first), Some(&last));
+        let mut base = Changes::new();
+        base.add_node_at(A, NodeId::ROOT_NODE, first.clone())
+            .add_node_at(B, NodeId::ROOT_NODE, last.clone());
+        let base = Journal::from(base);
+
+        // Both add a node between A and B
+        let mut ours = Changes::new();
+        ours.add_node_at(C, NodeId::ROOT_NODE, middle.clone())
+            .move_node_to(A, NodeId::ROOT_NODE, Position::between(Some(&last), None));
+        let mut theirs = Changes::new();
+        theirs
+            .add_node_at(D, NodeId::ROOT_NODE, middle)
+            .add_node_at(e, NodeId::ROOT_NODE, Position::between(None, Some(&first)));
+
+        let one = merge(&base, &ours.operations, &theirs.operations);
+        let other = merge(&base, &theirs.operations, &ours.operations);
+        assert_eq!(one.conflicts, vec![]);
+        assert_eq!(one.ours, ours.operations);
+        let roots = nodes(&one.journal).find_roots().clone();
+        assert_eq!(roots, vec![e, C, D, B, A]);
+        assert_eq!(&roots, nodes(&other.journal).find_roots());
+    }
+}
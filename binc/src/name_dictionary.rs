@@ -1,6 +1,6 @@
 use std::cmp::max;
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct NameDictionary {
     names: Vec<Option<String>>,
 }
@@ -25,6 +25,14 @@ impl NameDictionary {
         self.names[index] = Some(name.to_string());
     }
 
+    /// Undo an `insert`, given the name it replaced and the length before it
+    pub(crate) fn restore(&mut self, index: usize, name: Option<String>, len: usize) {
+        if let Some(slot) = self.names.get_mut(index) {
+            *slot = name;
+        }
+        self.names.truncate(len);
+    }
+
     pub fn get(&self, index: usize) -> Option<&str> {
         self.names.get(index).and_then(|x| x.as_deref())
     }
@@ -1,13 +1,17 @@
+use crate::error::BincError;
 use crate::journal::Journal;
 use crate::readwrite::{ReadExt, WriteExt};
 use std::fmt::{Display, Formatter};
 use std::io;
+use uuid::Uuid;
 
 const DISCONNECT: u8 = 0;
 const LIST_FILES: u8 = 1;
 const GET_FILE_DATA: u8 = 2;
 const CREATE_FILE: u8 = 3;
 const APPEND_FILE: u8 = 4;
+const RESERVE_NODE_IDS: u8 = 5;
+const ERROR: u8 = 255;
 
 pub enum NetworkRequest {
     Disconnect,
@@ -21,19 +25,51 @@ pub enum NetworkRequest {
         from: u64,
         path: String,
     },
+    /// Only appended if the file still has `document_id`, see `NetworkResponse::GetFileData`
     AppendFile {
         from: u64,
         to: u64,
         path: String,
+        document_id: Uuid,
         data: Vec<u8>,
     },
+    /// Ask for a block of node ids only this client adds nodes with. The server records the
+    /// block in the file, see `Operation::ReserveNodeIds`.
+    ReserveNodeIds {
+        path: String,
+        client: String,
+        count: u64,
+    },
 }
 
 pub enum NetworkResponse {
-    ListFiles { files: Vec<String> },
-    CreateFile { result: Result<(), String> },
-    GetFileData { from: u64, to: u64, data: Vec<u8> },
-    AppendFile { result: Result<(), String> },
+    ListFiles {
+        files: Vec<String>,
+    },
+    CreateFile {
+        result: Result<(), String>,
+    },
+    /// The id changes when the file is rewritten, like when it is compacted, as its revisions
+    /// are no longer the ones a client has. Version 1 files have no id, and send a nil one.
+    GetFileData {
+        from: u64,
+        to: u64,
+        document_id: Uuid,
+        data: Vec<u8>,
+    },
+    /// The revision of the file after the operations were appended. The server may add
+    /// operations of its own, like a checksum, so it can be more than the `to` that was sent.
+    AppendFile {
+        result: Result<u64, String>,
+    },
+    ReserveNodeIds {
+        first: u64,
+        count: u64,
+    },
+    /// The request could not be handled
+    Error {
+        message: String,
+    },
 }
 
 impl NetworkRequest {
@@ -44,11 +80,17 @@ impl NetworkRequest {
             NetworkRequest::GetFileData { .. } => GET_FILE_DATA,
             NetworkRequest::CreateFile { .. } => CREATE_FILE,
             NetworkRequest::AppendFile { .. } => APPEND_FILE,
+            NetworkRequest::ReserveNodeIds { .. } => RESERVE_NODE_IDS,
         }
     }
 
-    pub fn read<T: ReadExt>(r: &mut T) -> io::Result<NetworkRequest> {
-        let message_id = r.read_u8()?;
+    /// Read the next request. `BincError::EndOfStream` means the other side closed the connection.
+    pub fn read<T: ReadExt>(r: &mut T) -> Result<NetworkRequest, BincError> {
+        let message_id = read_message_id(r)?;
+        Self::read_message(message_id, r).map_err(|e| BincError::from_io(e, 0))
+    }
+
+    fn read_message<T: ReadExt>(message_id: u8, r: &mut T) -> io::Result<NetworkRequest> {
         match message_id {
             DISCONNECT => Ok(NetworkRequest::Disconnect),
             LIST_FILES => {
@@ -71,14 +113,26 @@ impl NetworkRequest {
                 let from_revision = r.read_varint()?;
                 let to_revision = r.read_varint()?;
                 let path = r.read_string()?;
+                let document_id = r.read_uuid()?;
                 let data = r.read_bytes()?;
                 Ok(NetworkRequest::AppendFile {
                     from: from_revision,
                     to: to_revision,
                     path,
+                    document_id,
                     data,
                 })
             }
+            RESERVE_NODE_IDS => {
+                let path = r.read_string()?;
+                let client = r.read_string()?;
+                let count = r.read_varint()?;
+                Ok(NetworkRequest::ReserveNodeIds {
+                    path,
+                    client,
+                    count,
+                })
+            }
             _ => Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 format!("Unsupported message id {}", message_id),
@@ -97,7 +151,7 @@ impl NetworkRequest {
                 from: from_revision,
                 path,
             } => {
-                w.write_length_vlq(*from_revision)?;
+                w.write_varint(*from_revision)?;
                 w.write_string(path)?;
             }
             NetworkRequest::CreateFile { path } => {
@@ -107,13 +161,24 @@ impl NetworkRequest {
                 from: from_revision,
                 to: to_revision,
                 path,
+                document_id,
                 data,
             } => {
-                w.write_length_vlq(*from_revision)?;
-                w.write_length_vlq(*to_revision)?;
+                w.write_varint(*from_revision)?;
+                w.write_varint(*to_revision)?;
                 w.write_string(path)?;
+                w.write_uuid(document_id)?;
                 w.write_bytes(data)?;
             }
+            NetworkRequest::ReserveNodeIds {
+                path,
+                client,
+                count,
+            } => {
+                w.write_string(path)?;
+                w.write_string(client)?;
+                w.write_varint(*count)?;
+            }
         }
         Ok(())
     }
@@ -126,11 +191,18 @@ impl NetworkResponse {
             NetworkResponse::GetFileData { .. } => GET_FILE_DATA,
             NetworkResponse::CreateFile { .. } => CREATE_FILE,
             NetworkResponse::AppendFile { .. } => APPEND_FILE,
+            NetworkResponse::ReserveNodeIds { .. } => RESERVE_NODE_IDS,
+            NetworkResponse::Error { .. } => ERROR,
         }
     }
 
-    pub fn read<T: ReadExt>(r: &mut T) -> io::Result<NetworkResponse> {
-        let message_id = r.read_u8()?;
+    /// Read the next response. `BincError::EndOfStream` means the other side closed the connection.
+    pub fn read<T: ReadExt>(r: &mut T) -> Result<NetworkResponse, BincError> {
+        let message_id = read_message_id(r)?;
+        Self::read_message(message_id, r).map_err(|e| BincError::from_io(e, 0))
+    }
+
+    fn read_message<T: ReadExt>(message_id: u8, r: &mut T) -> io::Result<NetworkResponse> {
         match message_id {
             LIST_FILES => {
                 let files = r.read_string_array()?;
@@ -139,10 +211,12 @@ impl NetworkResponse {
             GET_FILE_DATA => {
                 let from_revision = r.read_varint()?;
                 let to_revision = r.read_varint()?;
+                let document_id = r.read_uuid()?;
                 let data = r.read_bytes()?;
                 Ok(NetworkResponse::GetFileData {
                     from: from_revision,
                     to: to_revision,
+                    document_id,
                     data,
                 })
             }
@@ -160,12 +234,21 @@ impl NetworkResponse {
                 let result = r.read_u8()?;
                 Ok(NetworkResponse::AppendFile {
                     result: if result == 0 {
-                        Ok(())
+                        Ok(r.read_varint()?)
                     } else {
                         Err(r.read_string()?)
                     },
                 })
             }
+            RESERVE_NODE_IDS => {
+                let first = r.read_varint()?;
+                let count = r.read_varint()?;
+                Ok(NetworkResponse::ReserveNodeIds { first, count })
+            }
+            ERROR => {
+                let message = r.read_string()?;
+                Ok(NetworkResponse::Error { message })
+            }
             _ => Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 format!("Unsupported message id {}", message_id),
@@ -180,10 +263,12 @@ impl NetworkResponse {
             NetworkResponse::GetFileData {
                 from: from_revision,
                 to: to_revision,
+                document_id,
                 data,
             } => {
-                w.write_length_vlq(*from_revision)?;
-                w.write_length_vlq(*to_revision)?;
+                w.write_varint(*from_revision)?;
+                w.write_varint(*to_revision)?;
+                w.write_uuid(document_id)?;
                 w.write_bytes(data)
             }
             NetworkResponse::CreateFile { result } => {
@@ -194,14 +279,21 @@ impl NetworkResponse {
                     w.write_u8(0)
                 }
             }
-            NetworkResponse::AppendFile { result } => {
-                if let Err(e) = result {
+            NetworkResponse::AppendFile { result } => match result {
+                Ok(revision) => {
+                    w.write_u8(0)?;
+                    w.write_varint(*revision)
+                }
+                Err(e) => {
                     w.write_u8(1)?;
                     w.write_string(e)
-                } else {
-                    w.write_u8(0)
                 }
+            },
+            NetworkResponse::ReserveNodeIds { first, count } => {
+                w.write_varint(*first)?;
+                w.write_varint(*count)
             }
+            NetworkResponse::Error { message } => w.write_string(message),
         }
     }
 }
@@ -229,6 +321,7 @@ impl Display for NetworkRequest {
                 to: to_revision,
                 path,
                 data,
+                ..
             } => {
                 write!(
                     f,
@@ -239,6 +332,13 @@ impl Display for NetworkRequest {
                     data.len()
                 )
             }
+            NetworkRequest::ReserveNodeIds {
+                path,
+                client,
+                count,
+            } => {
+                write!(f, "ReserveNodeIds: {}, {} ids for {}", path, count, client)
+            }
         }
     }
 }
@@ -253,6 +353,7 @@ impl Display for NetworkResponse {
                 from: from_revision,
                 to: to_revision,
                 data,
+                ..
             } => {
                 write!(
                     f,
@@ -267,25 +368,162 @@ impl Display for NetworkResponse {
                 Err(e) => write!(f, "CreateFile: {}", e),
             },
             NetworkResponse::AppendFile { result } => match result {
-                Ok(()) => write!(f, "AppendFile: OK"),
+                Ok(revision) => write!(f, "AppendFile: OK, revision {}", revision),
                 Err(e) => write!(f, "AppendFile: {}", e),
             },
+            NetworkResponse::ReserveNodeIds { first, count } => {
+                write!(f, "ReserveNodeIds: {}..{}", first, first + count)
+            }
+            NetworkResponse::Error { message } => write!(f, "Error: {}", message),
         }
     }
 }
 
 impl NetworkResponse {
-    pub fn as_journal(&self) -> io::Result<Journal> {
+    /// The operations in a `GetFileData` response, in a journal with the document id of the file
+    pub fn as_journal(&self) -> Result<Journal, BincError> {
         match self {
-            NetworkResponse::GetFileData { data, .. } => {
+            NetworkResponse::GetFileData {
+                document_id, data, ..
+            } => {
                 let mut repo = Journal::new();
+                repo.header.document_id = *document_id;
                 repo.append(&mut data.as_slice())?;
                 Ok(repo)
             }
-            _ => Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Not a GetFileData response",
-            )),
+            NetworkResponse::Error { message } => Err(io::Error::other(message.clone()).into()),
+            _ => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "Not a GetFileData response").into())
+            }
+        }
+    }
+}
+
+/// A message id, or `BincError::EndOfStream` if the stream ends cleanly before the next message
+fn read_message_id<T: ReadExt>(r: &mut T) -> Result<u8, BincError> {
+    match r.read_u8() {
+        Ok(message_id) => Ok(message_id),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(BincError::EndOfStream),
+        Err(e) => Err(BincError::Io(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_large_revisions_round_trip() {
+        let document_id = Uuid::new_v4();
+        let mut data = vec![];
+        NetworkRequest::AppendFile {
+            from: 200,
+            to: 70000,
+            path: "a.binc".to_string(),
+            document_id,
+            data: vec![1, 2, 3],
+        }
+        .write(&mut data)
+        .unwrap();
+
+        let mut r = data.as_slice();
+        match NetworkRequest::read(&mut r).unwrap() {
+            NetworkRequest::AppendFile {
+                from,
+                to,
+                path,
+                document_id: id,
+                ..
+            } => {
+                assert_eq!((from, to, path.as_str()), (200, 70000, "a.binc"));
+                assert_eq!(id, document_id);
+            }
+            request => panic!("Unexpected request {}", request),
+        }
+        assert!(matches!(
+            NetworkRequest::read(&mut r),
+            Err(BincError::EndOfStream)
+        ));
+    }
+
+    #[test]
+    fn test_reserve_node_ids_round_trip() {
+        let mut data = vec![];
+        NetworkRequest::ReserveNodeIds {
+            path: "a.binc".to_string(),
+            client: "ann".to_string(),
+            count: 1024,
+        }
+        .write(&mut data)
+        .unwrap();
+        NetworkResponse::ReserveNodeIds {
+            first: 5000,
+            count: 1024,
         }
+        .write(&mut data)
+        .unwrap();
+
+        let mut r = data.as_slice();
+        match NetworkRequest::read(&mut r).unwrap() {
+            NetworkRequest::ReserveNodeIds {
+                path,
+                client,
+                count,
+            } => {
+                assert_eq!(
+                    (path.as_str(), client.as_str(), count),
+                    ("a.binc", "ann", 1024)
+                );
+            }
+            request => panic!("Unexpected request {}", request),
+        }
+        match NetworkResponse::read(&mut r).unwrap() {
+            NetworkResponse::ReserveNodeIds { first, count } => {
+                assert_eq!((first, count), (5000, 1024));
+            }
+            response => panic!("Unexpected response {}", response),
+        }
+    }
+
+    #[test]
+    fn test_append_file_response_round_trip() {
+        let mut data = vec![];
+        NetworkResponse::AppendFile { result: Ok(70001) }
+            .write(&mut data)
+            .unwrap();
+        NetworkResponse::AppendFile {
+            result: Err("Revision mismatch".to_string()),
+        }
+        .write(&mut data)
+        .unwrap();
+
+        let mut r = data.as_slice();
+        match NetworkResponse::read(&mut r).unwrap() {
+            NetworkResponse::AppendFile { result } => assert_eq!(result, Ok(70001)),
+            response => panic!("Unexpected response {}", response),
+        }
+        match NetworkResponse::read(&mut r).unwrap() {
+            NetworkResponse::AppendFile { result } => {
+                assert_eq!(result, Err("Revision mismatch".to_string()))
+            }
+            response => panic!("Unexpected response {}", response),
+        }
+    }
+
+    #[test]
+    fn test_error_response() {
+        let mut data = vec![];
+        NetworkResponse::Error {
+            message: "No such file".to_string(),
+        }
+        .write(&mut data)
+        .unwrap();
+
+        let response = NetworkResponse::read(&mut data.as_slice()).unwrap();
+        assert!(response.as_journal().is_err());
+        assert!(matches!(
+            NetworkResponse::read(&mut &data[..2]),
+            Err(BincError::Truncated { offset: 0 })
+        ));
     }
 }
@@ -1,4 +1,7 @@
+use crate::operation::Operation;
+use std::collections::VecDeque;
 use std::fmt::Display;
+use std::ops::Range;
 
 #[derive(PartialEq, Clone, Copy, Debug, Eq, Hash)]
 pub struct NodeId {
@@ -51,6 +54,12 @@ impl Display for NodeId {
 #[derive(Debug, Clone)]
 pub struct NodeIdGenerator {
     next_id: usize,
+    /// Blocks of ids reserved for this generator, see `Operation::ReserveNodeIds`. They are
+    /// handed out before any other ids.
+    reserved: VecDeque<Range<usize>>,
+    /// Set once ids are reserved for this generator. Others may be handed any id outside its
+    /// blocks, so after that it only hands out reserved ones.
+    only_reserved: bool,
 }
 
 impl Default for NodeIdGenerator {
@@ -61,12 +70,86 @@ impl Default for NodeIdGenerator {
 
 impl NodeIdGenerator {
     pub fn new() -> NodeIdGenerator {
-        NodeIdGenerator { next_id: 1 }
+        NodeIdGenerator {
+            next_id: 1,
+            reserved: VecDeque::new(),
+            only_reserved: false,
+        }
+    }
+
+    /// A generator for a document with these operations, so new ids are higher than every id
+    /// the journal uses, including ids of removed nodes, node references and reserved blocks
+    pub fn for_operations(operations: &[Operation]) -> NodeIdGenerator {
+        let mut generator = NodeIdGenerator::new();
+        for operation in operations {
+            generator.skip_past_ids_in(operation);
+        }
+        generator
     }
 
+    /// The next free id, or `NodeId::NO_NODE` once every id is used, or every reserved id if
+    /// ids were reserved for this generator. Adding a node with that is rejected, so running
+    /// out of ids is an error instead of a panic or a node with the same id as someone else's.
     pub fn next_id(&mut self) -> NodeId {
+        while let Some(block) = self.reserved.front_mut() {
+            match block.next() {
+                Some(id) if id < NodeId::NO_NODE_ID => return NodeId::new(id),
+                _ => {
+                    self.reserved.pop_front();
+                }
+            }
+        }
+        if self.only_reserved || self.next_id >= NodeId::NO_NODE_ID {
+            return NodeId::NO_NODE;
+        }
         let id = self.next_id;
         self.next_id += 1;
         NodeId::new(id)
     }
+
+    /// Make sure `id` is never handed out. Ids are handed out in order, so the ids before it in
+    /// a reserved block are skipped too.
+    pub fn skip_past(&mut self, id: NodeId) {
+        if !id.exists() {
+            return;
+        }
+        self.skip_counter_past(id.index());
+        for block in &mut self.reserved {
+            if block.contains(&id.index()) {
+                block.start = id.index() + 1;
+            }
+        }
+    }
+
+    fn skip_counter_past(&mut self, index: usize) {
+        if index >= self.next_id {
+            self.next_id = index + 1;
+        }
+    }
+
+    pub fn skip_past_ids_in(&mut self, operation: &Operation) {
+        // The block may be this generator's own, which must still be handed out
+        if let Operation::ReserveNodeIds { first, count, .. } = operation
+            && *count > 0
+        {
+            self.skip_counter_past(first.index().saturating_add(count - 1));
+        }
+        for id in operation.node_ids() {
+            self.skip_past(id);
+        }
+    }
+
+    /// Hand out the `count` ids from `first` before any others. The block must have been
+    /// reserved for this generator, like by a server with `Operation::ReserveNodeIds`.
+    /// From then on, only reserved ids are handed out.
+    pub fn use_reserved(&mut self, first: NodeId, count: usize) {
+        self.only_reserved = true;
+        self.reserved
+            .push_back(first.index()..first.index().saturating_add(count));
+    }
+
+    /// Number of reserved ids that haven't been handed out yet
+    pub fn reserved_left(&self) -> usize {
+        self.reserved.iter().map(|block| block.len()).sum()
+    }
 }
@@ -1,19 +1,33 @@
 use crate::attributes::{AttributeStore, AttributeValue};
 use crate::comments::Comments;
+use crate::error::InvalidOperation;
 use crate::name_dictionary::NameDictionary;
 use crate::node_id::NodeId;
+use crate::position::Position;
+use crate::sources::Sources;
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use std::fmt::{Display, Formatter};
 
 pub type NodeStore = FlatNodeStore;
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct FlatNodeStore {
     nodes: Vec<Node>,
     pub type_names: NameDictionary,
     pub attribute_names: NameDictionary,
     pub tag_names: NameDictionary,
+    pub sources: Sources,
+    /// The id after the last block set aside by `Operation::ReserveNodeIds`
+    reserved_end: usize,
 }
 
 impl FlatNodeStore {
+    /// How far past the ids in use a new node, name or source id can be. Ids are indexes, so
+    /// an operation with a much larger one would make the store allocate without limit.
+    pub const MAX_ID_GAP: usize = 1 << 16;
+    /// Most node ids one `Operation::ReserveNodeIds` can set aside
+    pub const MAX_RESERVED_IDS: usize = 1 << 20;
+
     pub fn new() -> NodeStore {
         let mut nodes = vec![Node::default()];
         nodes[0].id = NodeId::ROOT_NODE;
@@ -23,6 +37,8 @@ impl FlatNodeStore {
             type_names: NameDictionary::default(),
             attribute_names: NameDictionary::default(),
             tag_names: NameDictionary::default(),
+            sources: Sources::default(),
+            reserved_end: 0,
         }
     }
 
@@ -45,8 +61,8 @@ impl FlatNodeStore {
         type_id: usize,
         parent: NodeId,
         index_in_parent: usize,
-    ) {
-        self.add_with_type(id, type_id, parent, index_in_parent);
+    ) -> Result<(), InvalidOperation> {
+        self.add_with_type(id, type_id, parent, index_in_parent)
     }
 
     pub(crate) fn add_with_type(
@@ -55,7 +71,16 @@ impl FlatNodeStore {
         node_type: usize,
         parent: NodeId,
         index_in_parent: usize,
-    ) {
+    ) -> Result<(), InvalidOperation> {
+        if !id.exists() || id.is_root() {
+            return Err(InvalidOperation::ReservedNode(id));
+        }
+        if self.resolve(id).is_some() {
+            return Err(InvalidOperation::NodeExists(id));
+        }
+        self.check_child_index(parent, index_in_parent)?;
+        check_new_id(id.index(), self.nodes.len().max(self.reserved_end))?;
+
         let i = id.index();
         let p = parent.index();
 
@@ -65,12 +90,36 @@ impl FlatNodeStore {
 
         self.nodes[i] = Node::new_with_id(id, node_type, parent);
         self.nodes[p].children.insert(index_in_parent, id.clone());
+        Ok(())
+    }
+
+    /// Add a node at `position` among the children of `parent`, see `index_for_position`
+    pub(crate) fn add_at(
+        &mut self,
+        id: NodeId,
+        node_type: usize,
+        parent: NodeId,
+        position: &Position,
+    ) -> Result<(), InvalidOperation> {
+        let index = self.index_for_position(parent, id, position)?;
+        self.add_with_type(id, node_type, parent, index)?;
+        self.nodes[id.index()].position = Some(position.clone());
+        Ok(())
+    }
+
+    pub(crate) fn delete_recursive(&mut self, id: NodeId) -> Result<(), InvalidOperation> {
+        if id.is_root() {
+            return Err(InvalidOperation::ReservedNode(id));
+        }
+        self.existing(id)?;
+        self.delete_subtree(id);
+        Ok(())
     }
 
-    pub(crate) fn delete_recursive(&mut self, id: NodeId) {
+    fn delete_subtree(&mut self, id: NodeId) {
         let i = id.index();
         for c in self.nodes[i].children.clone() {
-            self.delete_recursive(c);
+            self.delete_subtree(c);
         }
         let p = self.nodes[i].parent.index();
 
@@ -79,7 +128,28 @@ impl FlatNodeStore {
         self.nodes[i] = Node::default();
     }
 
-    pub(crate) fn move_node(&mut self, id: NodeId, new_parent: NodeId, index_in_new_parent: usize) {
+    pub(crate) fn move_node(
+        &mut self,
+        id: NodeId,
+        new_parent: NodeId,
+        index_in_new_parent: usize,
+    ) -> Result<(), InvalidOperation> {
+        if id.is_root() {
+            return Err(InvalidOperation::ReservedNode(id));
+        }
+        self.existing(id)?;
+        self.check_child_index(new_parent, index_in_new_parent)?;
+        let mut ancestor = new_parent;
+        while ancestor.exists() {
+            if ancestor == id {
+                return Err(InvalidOperation::MoveIntoItself {
+                    node: id,
+                    new_parent,
+                });
+            }
+            ancestor = self.nodes[ancestor.index()].parent;
+        }
+
         let i = id.index();
         let p1 = self.nodes[i].parent.index();
         let p2 = new_parent.index();
@@ -100,6 +170,88 @@ impl FlatNodeStore {
         self.nodes[p1].children.retain(|x| *x != id);
         self.nodes[p2].children.insert(insert_index, id.clone());
         self.nodes[i].parent = new_parent.clone();
+        // The node is placed by index now
+        self.nodes[i].position = None;
+        Ok(())
+    }
+
+    /// Move a node to `position` among the children of `new_parent`, see `index_for_position`
+    pub(crate) fn move_to(
+        &mut self,
+        id: NodeId,
+        new_parent: NodeId,
+        position: &Position,
+    ) -> Result<(), InvalidOperation> {
+        let mut index = self.index_for_position(new_parent, id, position)?;
+        // `move_node` counts the node at its old index when it stays in the same parent
+        let old_index = self
+            .resolve(id)
+            .filter(|node| node.parent == new_parent)
+            .and_then(|_| self.nodes[new_parent.index()].get_child_index(id));
+        if old_index.is_some_and(|old_index| old_index <= index) {
+            index += 1;
+        }
+        self.move_node(id, new_parent, index)?;
+        self.nodes[id.index()].position = Some(position.clone());
+        Ok(())
+    }
+
+    /// Where a node with `position` goes among the other children of `parent`: before the first
+    /// one with a later position, or with the same position and a higher id. Children without
+    /// a position, the ones added or moved by index, are passed over, so they stay after the
+    /// sibling they follow.
+    fn index_for_position(
+        &self,
+        parent: NodeId,
+        id: NodeId,
+        position: &Position,
+    ) -> Result<usize, InvalidOperation> {
+        let key = (position, id.index());
+        let siblings: Vec<&Node> = self
+            .existing(parent)?
+            .children
+            .iter()
+            .filter(|c| **c != id)
+            .map(|c| &self.nodes[c.index()])
+            .collect();
+        let index = siblings.iter().position(|sibling| {
+            sibling
+                .position
+                .as_ref()
+                .is_some_and(|p| (p, sibling.id.index()) > key)
+        });
+        Ok(index.unwrap_or(siblings.len()))
+    }
+
+    /// A position that puts a new child of `parent` at `index`, between the nearest children
+    /// before and after it that have positions. Children without a position in between can end
+    /// up on either side of the new one.
+    pub fn position_at(&self, parent: NodeId, index: usize) -> Position {
+        let children = self.get(parent).map_or(&[][..], |p| p.children.as_slice());
+        let index = index.min(children.len());
+        let position = |c: &NodeId| self.nodes[c.index()].position.as_ref();
+        let before = children[..index].iter().rev().find_map(position);
+        let after = children[index..].iter().find_map(position);
+        Position::between(before, after)
+    }
+
+    /// The node, if it was added and not removed
+    pub(crate) fn existing(&self, id: NodeId) -> Result<&Node, InvalidOperation> {
+        self.resolve(id).ok_or(InvalidOperation::NodeNotFound(id))
+    }
+
+    pub(crate) fn existing_mut(&mut self, id: NodeId) -> Result<&mut Node, InvalidOperation> {
+        self.existing(id)?;
+        Ok(&mut self.nodes[id.index()])
+    }
+
+    /// A child can be inserted at any index up to the number of children
+    fn check_child_index(&self, parent: NodeId, index: usize) -> Result<(), InvalidOperation> {
+        let len = self.existing(parent)?.children.len();
+        if index > len {
+            return Err(InvalidOperation::IndexOutOfRange { parent, index, len });
+        }
+        Ok(())
     }
 
     pub fn get(&self, id: NodeId) -> Option<&Node> {
@@ -110,28 +262,164 @@ impl FlatNodeStore {
         self.nodes.get_mut(id.index())
     }
 
-    pub(crate) fn define_type_name(&mut self, index: usize, name: &str) {
+    /// Follow a node reference. Returns `None` if the target was removed or never added.
+    pub fn resolve(&self, target: NodeId) -> Option<&Node> {
+        self.get(target).filter(|node| node.id == target)
+    }
+
+    /// All references whose target no longer exists
+    pub fn dangling_references(&self) -> Vec<DanglingReference> {
+        let mut dangling = vec![];
+        for node in self.nodes.iter().filter(|n| n.id.exists()) {
+            for entry in node.attributes.iter() {
+                for target in entry.value.node_references() {
+                    if self.resolve(*target).is_none() {
+                        dangling.push(DanglingReference {
+                            node: node.id,
+                            attribute: entry.key,
+                            target: *target,
+                        });
+                    }
+                }
+            }
+        }
+        dangling
+    }
+
+    pub(crate) fn define_type_name(
+        &mut self,
+        index: usize,
+        name: &str,
+    ) -> Result<(), InvalidOperation> {
+        check_new_id(index, self.type_names.len())?;
         self.type_names.insert(index, name);
+        Ok(())
     }
 
-    pub(crate) fn define_tag_name(&mut self, index: usize, name: &str) {
+    pub(crate) fn define_tag_name(
+        &mut self,
+        index: usize,
+        name: &str,
+    ) -> Result<(), InvalidOperation> {
+        check_new_id(index, self.tag_names.len())?;
         self.tag_names.insert(index, name);
+        Ok(())
     }
 
-    pub(crate) fn define_attribute_name(&mut self, index: usize, name: &str) {
+    pub(crate) fn define_attribute_name(
+        &mut self,
+        index: usize,
+        name: &str,
+    ) -> Result<(), InvalidOperation> {
+        check_new_id(index, self.attribute_names.len())?;
         self.attribute_names.insert(index, name);
+        Ok(())
+    }
+
+    /// Let nodes be added with ids up to the end of the block, see `Operation::ReserveNodeIds`
+    pub(crate) fn reserve_ids(
+        &mut self,
+        first: NodeId,
+        count: usize,
+    ) -> Result<(), InvalidOperation> {
+        if count > Self::MAX_RESERVED_IDS {
+            return Err(InvalidOperation::IdOutOfRange {
+                id: first.index().saturating_add(count),
+                next_id: first.index(),
+            });
+        }
+        check_new_id(first.index(), self.nodes.len().max(self.reserved_end))?;
+        self.reserved_end = self.reserved_end.max(first.index() + count);
+        Ok(())
     }
 
     pub(crate) fn len(&self) -> usize {
         self.nodes.len()
     }
+
+    /// Copies of the node and every node under it, parents before children
+    pub(crate) fn subtree(&self, id: NodeId) -> Vec<Node> {
+        let mut result = vec![];
+        let mut pending = vec![id];
+        while let Some(id) = pending.pop() {
+            let node = &self.nodes[id.index()];
+            pending.extend(node.children.iter().rev());
+            result.push(node.clone());
+        }
+        result
+    }
+
+    /// Put back nodes from `subtree`, with the first one at `index` in `parent`
+    pub(crate) fn restore_subtree(&mut self, nodes: Vec<Node>, parent: NodeId, index: usize) {
+        let Some(top) = nodes.first().map(|n| n.id) else {
+            return;
+        };
+        for node in nodes {
+            let i = node.id.index();
+            if i >= self.nodes.len() {
+                self.nodes.resize_with(i + 1, Node::default);
+            }
+            self.nodes[i] = node;
+        }
+        self.nodes[parent.index()].children.insert(index, top);
+    }
+
+    /// Remove a node that was just added, and the slots added for it, so there are `len` again
+    pub(crate) fn remove_added(&mut self, id: NodeId, len: usize) {
+        self.delete_subtree(id);
+        self.nodes.truncate(len);
+    }
+
+    /// Move a node to exactly `index` in `parent`, with the position it had there
+    pub(crate) fn place(
+        &mut self,
+        id: NodeId,
+        parent: NodeId,
+        index: usize,
+        position: Option<Position>,
+    ) {
+        let old_parent = self.nodes[id.index()].parent;
+        self.nodes[old_parent.index()].children.retain(|x| *x != id);
+        self.nodes[parent.index()].children.insert(index, id);
+        self.nodes[id.index()].parent = parent;
+        self.nodes[id.index()].position = position;
+    }
+}
+
+/// Reject an id for a new node, name or source too far past `next_id`, see `MAX_ID_GAP`
+pub(crate) fn check_new_id(id: usize, next_id: usize) -> Result<(), InvalidOperation> {
+    if id >= next_id.saturating_add(FlatNodeStore::MAX_ID_GAP) {
+        return Err(InvalidOperation::IdOutOfRange { id, next_id });
+    }
+    Ok(())
+}
+
+/// A node reference attribute pointing at a node that doesn't exist
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DanglingReference {
+    pub node: NodeId,
+    pub attribute: usize,
+    pub target: NodeId,
+}
+
+impl Display for DanglingReference {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Node {} attribute {} links to missing node {}",
+            self.node, self.attribute, self.target
+        )
+    }
 }
 
+#[derive(Clone)]
 pub struct Node {
     pub id: NodeId,
     pub name: Option<String>,
     pub type_id: Option<usize>,
     pub parent: NodeId,
+    /// Where the node is among its siblings, if it was added or moved there by position
+    pub position: Option<Position>,
     pub children: Vec<NodeId>,
     pub attributes: AttributeStore,
     pub comments: Comments,
@@ -143,6 +431,7 @@ impl Default for Node {
         Node {
             id: NodeId::NO_NODE,
             parent: NodeId::NO_NODE,
+            position: None,
             name: None,
             type_id: None,
             children: vec![],
@@ -158,6 +447,7 @@ impl Node {
         Node {
             id,
             parent,
+            position: None,
             name: None,
             type_id: Some(type_id),
             children: vec![],
@@ -203,6 +493,10 @@ impl Node {
         self.attributes.set(key, value);
     }
 
+    pub fn remove_attribute(&mut self, key: usize) -> Option<AttributeValue> {
+        self.attributes.remove(key)
+    }
+
     pub fn get_attribute(&self, key: usize) -> Option<&AttributeValue> {
         self.attributes.get(key)
     }
@@ -229,12 +523,40 @@ impl Node {
         }
     }
 
+    pub fn get_timestamp_attribute(&self, key: usize) -> Option<DateTime<Utc>> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::Timestamp(t)) => Some(*t),
+            _ => None,
+        }
+    }
+
+    pub fn get_date_attribute(&self, key: usize) -> Option<NaiveDate> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::Date(d)) => Some(*d),
+            _ => None,
+        }
+    }
+
+    pub fn get_duration_attribute(&self, key: usize) -> Option<TimeDelta> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::Duration(d)) => Some(*d),
+            _ => None,
+        }
+    }
+
+    pub fn get_bytes_attribute(&self, key: usize) -> Option<&[u8]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::Bytes(b)) => Some(b),
+            _ => None,
+        }
+    }
+
     pub(crate) fn get_child_index(&self, id: NodeId) -> Option<usize> {
         self.children.iter().position(|x| *x == id)
     }
 
-    pub(crate) fn add_comment(&mut self, comment: &str, author: &str, response_to: usize) {
-        self.comments.add_comment(comment, author, response_to);
+    pub(crate) fn add_comment(&mut self, comment: &str, author: &str, response_to: usize) -> usize {
+        self.comments.add_comment(comment, author, response_to)
     }
 }
 
@@ -254,7 +576,7 @@ mod tests {
     fn test_insert_and_get_node() {
         let mut store = FlatNodeStore::new();
         let node_id = NodeId::new(1);
-        store.add(node_id, 0, NodeId::ROOT_NODE, 0);
+        store.add(node_id, 0, NodeId::ROOT_NODE, 0).unwrap();
         assert!(store.get(node_id).is_some());
         assert!(store.get(node_id).expect("Node not found").parent == NodeId::ROOT_NODE);
         assert!(store.get(node_id).expect("Node not found").id == node_id);
@@ -275,8 +597,8 @@ mod tests {
         store.nodes.push(root_node);
         let roots = store.find_roots();
         assert_eq!(roots.len(), 0);
-        let node_id = NodeId::new(1);
-        store.add(node_id, 0, NodeId::ROOT_NODE, 0);
+        let node_id = NodeId::new(2);
+        store.add(node_id, 0, NodeId::ROOT_NODE, 0).unwrap();
         let roots = store.find_roots();
         assert_eq!(roots.len(), 1);
         assert!(roots[0] == node_id);
@@ -287,11 +609,11 @@ mod tests {
         let mut store = FlatNodeStore::new();
         let id1 = NodeId::new(1);
         let id2 = NodeId::new(2);
-        store.add(id1, 0, NodeId::ROOT_NODE, 0);
-        store.add(id2, 0, id1, 0);
+        store.add(id1, 0, NodeId::ROOT_NODE, 0).unwrap();
+        store.add(id2, 0, id1, 0).unwrap();
         assert_eq!(store.get(id1).unwrap().parent, NodeId::ROOT_NODE);
         assert_eq!(store.get(id2).unwrap().parent, id1);
-        store.delete_recursive(id2);
+        store.delete_recursive(id2).unwrap();
         assert_eq!(store.nodes.len(), 3);
         assert_eq!(store.find_roots().len(), 1)
     }
@@ -301,9 +623,9 @@ mod tests {
         let mut store = FlatNodeStore::new();
         let id1 = NodeId::new(1);
         let id2 = NodeId::new(2);
-        store.add(id1, 0, NodeId::ROOT_NODE, 0);
-        store.add(id2, 0, id1, 0);
-        store.delete_recursive(id1);
+        store.add(id1, 0, NodeId::ROOT_NODE, 0).unwrap();
+        store.add(id2, 0, id1, 0).unwrap();
+        store.delete_recursive(id1).unwrap();
         assert_eq!(store.nodes.len(), 3);
         assert_eq!(store.find_roots().len(), 0)
     }
@@ -314,9 +636,140 @@ mod tests {
         let id1 = NodeId::new(1);
         let id2 = NodeId::new(2);
         let id3 = NodeId::new(3);
-        store.add(id1, 0, NodeId::ROOT_NODE, 0);
-        store.add(id2, 0, NodeId::ROOT_NODE, 1);
-        store.add(id3, 0, NodeId::ROOT_NODE, 2);
-        store.move_node(id1, NodeId::ROOT_NODE, 3);
+        store.add(id1, 0, NodeId::ROOT_NODE, 0).unwrap();
+        store.add(id2, 0, NodeId::ROOT_NODE, 1).unwrap();
+        store.add(id3, 0, NodeId::ROOT_NODE, 2).unwrap();
+        store.move_node(id1, NodeId::ROOT_NODE, 3).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_changes_are_rejected() {
+        let mut store = FlatNodeStore::new();
+        let id1 = NodeId::new(1);
+        let id2 = NodeId::new(2);
+        store.add(id1, 0, NodeId::ROOT_NODE, 0).unwrap();
+        store.add(id2, 0, id1, 0).unwrap();
+
+        assert_eq!(
+            store.add(id1, 0, NodeId::ROOT_NODE, 0),
+            Err(InvalidOperation::NodeExists(id1))
+        );
+        assert_eq!(
+            store.add(NodeId::new(3), 0, id1, 2),
+            Err(InvalidOperation::IndexOutOfRange {
+                parent: id1,
+                index: 2,
+                len: 1
+            })
+        );
+        assert_eq!(
+            store.move_node(id1, id2, 0),
+            Err(InvalidOperation::MoveIntoItself {
+                node: id1,
+                new_parent: id2
+            })
+        );
+        assert_eq!(
+            store.delete_recursive(NodeId::ROOT_NODE),
+            Err(InvalidOperation::ReservedNode(NodeId::ROOT_NODE))
+        );
+
+        store.delete_recursive(id1).unwrap();
+        assert_eq!(
+            store.delete_recursive(id2),
+            Err(InvalidOperation::NodeNotFound(id2))
+        );
+        assert!(store.find_roots().is_empty());
+    }
+
+    #[test]
+    fn test_positions_ignore_order() {
+        let ids = [
+            NodeId::new(1),
+            NodeId::new(2),
+            NodeId::new(3),
+            NodeId::new(4),
+        ];
+        let first = Position::between(None, None);
+        let second = Position::between(Some(&first), None);
+        // 3 and 4 were added at the same place, so the id decides
+        let positions = [second.clone(), first.clone(), second, first];
+
+        let mut orders = vec![];
+        for order in [[0, 1, 2, 3], [3, 2, 1, 0], [2, 0, 3, 1]] {
+            let mut store = FlatNodeStore::new();
+            for i in order {
+                store
+                    .add_at(ids[i], 0, NodeId::ROOT_NODE, &positions[i])
+                    .unwrap();
+            }
+            orders.push(store.find_roots().clone());
+        }
+        assert_eq!(orders[0], vec![ids[1], ids[3], ids[0], ids[2]]);
+        assert!(orders.iter().all(|order| *order == orders[0]));
+    }
+
+    #[test]
+    fn test_move_to_position() {
+        let ids = [NodeId::new(1), NodeId::new(2), NodeId::new(3)];
+        let mut store = FlatNodeStore::new();
+        for id in ids {
+            let position = store.position_at(NodeId::ROOT_NODE, store.find_roots().len());
+            store.add_at(id, 0, NodeId::ROOT_NODE, &position).unwrap();
+        }
+        assert_eq!(store.find_roots(), &ids.to_vec());
+
+        let last = store.position_at(NodeId::ROOT_NODE, 3);
+        store.move_to(ids[0], NodeId::ROOT_NODE, &last).unwrap();
+        assert_eq!(store.find_roots(), &vec![ids[1], ids[2], ids[0]]);
+        let middle = store.position_at(NodeId::ROOT_NODE, 1);
+        store.move_to(ids[0], NodeId::ROOT_NODE, &middle).unwrap();
+        assert_eq!(store.find_roots(), &vec![ids[1], ids[0], ids[2]]);
+        assert_eq!(store.get(ids[0]).unwrap().position, Some(middle));
+
+        // A node added by index stays after the node before it, and moving by index clears
+        // the position
+        store.add(NodeId::new(4), 0, NodeId::ROOT_NODE, 1).unwrap();
+        let first = store.position_at(NodeId::ROOT_NODE, 0);
+        store.add_at(NodeId::new(5), 0, ids[1], &first).unwrap();
+        store.move_node(ids[2], NodeId::ROOT_NODE, 0).unwrap();
+        assert_eq!(store.get(ids[2]).unwrap().position, None);
+        assert_eq!(
+            store.find_roots(),
+            &vec![ids[2], ids[1], NodeId::new(4), ids[0]]
+        );
+        assert_eq!(
+            store.move_to(ids[1], NodeId::new(5), &first),
+            Err(InvalidOperation::MoveIntoItself {
+                node: ids[1],
+                new_parent: NodeId::new(5)
+            })
+        );
+    }
+
+    #[test]
+    fn test_dangling_references() {
+        let mut store = FlatNodeStore::new();
+        let id1 = NodeId::new(1);
+        let id2 = NodeId::new(2);
+        let id3 = NodeId::new(3);
+        store.add(id1, 0, NodeId::ROOT_NODE, 0).unwrap();
+        store.add(id2, 0, NodeId::ROOT_NODE, 1).unwrap();
+        store.add(id3, 0, id2, 0).unwrap();
+        let node = store.get_mut(id1).unwrap();
+        node.set_attribute(0, AttributeValue::NodeReference(id2));
+        node.set_attribute(1, AttributeValue::NodeReferenceArray(vec![id2, id3]));
+        assert!(store.resolve(id3).is_some());
+        assert!(store.dangling_references().is_empty());
+
+        store.delete_recursive(id2).unwrap();
+        assert!(store.resolve(id2).is_none());
+        assert!(store.resolve(NodeId::new(99)).is_none());
+        let targets: Vec<(usize, NodeId)> = store
+            .dangling_references()
+            .iter()
+            .map(|d| (d.attribute, d.target))
+            .collect();
+        assert_eq!(targets, vec![(0, id2), (1, id2), (1, id3)]);
     }
 }
@@ -0,0 +1,342 @@
+use std::fmt::{Display, Formatter};
+
+/// Error returned when a value doesn't fit in one of the smaller numeric types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfRange;
+
+impl Display for OutOfRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Value out of range")
+    }
+}
+
+impl std::error::Error for OutOfRange {}
+
+/// Unsigned 24-bit integer, stored as three big-endian bytes in files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct U24(u32);
+
+impl U24 {
+    pub const MIN: U24 = U24(0);
+    pub const MAX: U24 = U24(0xFF_FFFF);
+
+    /// Returns `None` if the value does not fit in 24 bits
+    pub const fn new(value: u32) -> Option<U24> {
+        if value <= Self::MAX.0 {
+            Some(U24(value))
+        } else {
+            None
+        }
+    }
+
+    pub const fn get(self) -> u32 {
+        self.0
+    }
+
+    pub fn to_be_bytes(self) -> [u8; 3] {
+        let b = self.0.to_be_bytes();
+        [b[1], b[2], b[3]]
+    }
+
+    pub fn from_be_bytes(bytes: [u8; 3]) -> U24 {
+        U24(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]))
+    }
+}
+
+/// Signed 24-bit integer, stored as three big-endian bytes (two's complement) in files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct I24(i32);
+
+impl I24 {
+    pub const MIN: I24 = I24(-0x80_0000);
+    pub const MAX: I24 = I24(0x7F_FFFF);
+
+    /// Returns `None` if the value does not fit in 24 bits
+    pub const fn new(value: i32) -> Option<I24> {
+        if value >= Self::MIN.0 && value <= Self::MAX.0 {
+            Some(I24(value))
+        } else {
+            None
+        }
+    }
+
+    pub const fn get(self) -> i32 {
+        self.0
+    }
+
+    pub fn to_be_bytes(self) -> [u8; 3] {
+        let b = self.0.to_be_bytes();
+        [b[1], b[2], b[3]]
+    }
+
+    pub fn from_be_bytes(bytes: [u8; 3]) -> I24 {
+        // Shift up and back down again to sign extend
+        I24(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], 0]) >> 8)
+    }
+}
+
+/// IEEE 754 half precision float. Arithmetic should be done after converting to `f32`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct F16(u16);
+
+impl F16 {
+    pub const ZERO: F16 = F16(0x0000);
+    pub const ONE: F16 = F16(0x3C00);
+    /// Largest finite value, 65504
+    pub const MAX: F16 = F16(0x7BFF);
+    /// Smallest finite value, -65504
+    pub const MIN: F16 = F16(0xFBFF);
+    pub const INFINITY: F16 = F16(0x7C00);
+    pub const NEG_INFINITY: F16 = F16(0xFC00);
+    pub const NAN: F16 = F16(0x7E00);
+
+    pub const fn from_bits(bits: u16) -> F16 {
+        F16(bits)
+    }
+
+    pub const fn to_bits(self) -> u16 {
+        self.0
+    }
+
+    pub fn to_be_bytes(self) -> [u8; 2] {
+        self.0.to_be_bytes()
+    }
+
+    pub fn from_be_bytes(bytes: [u8; 2]) -> F16 {
+        F16(u16::from_be_bytes(bytes))
+    }
+
+    pub fn is_nan(self) -> bool {
+        self.0 & 0x7C00 == 0x7C00 && self.0 & 0x03FF != 0
+    }
+
+    pub fn is_finite(self) -> bool {
+        self.0 & 0x7C00 != 0x7C00
+    }
+
+    /// Convert with round-to-nearest-even. Values too large for half precision become infinite,
+    /// use `try_from` to get an error instead.
+    pub fn from_f32(value: f32) -> F16 {
+        let x = value.to_bits();
+        let sign = ((x >> 16) & 0x8000) as u16;
+        let exponent = ((x >> 23) & 0xFF) as i32;
+        let mantissa = x & 0x7F_FFFF;
+
+        if exponent == 0xFF {
+            // Infinity stays infinity, NaN stays NaN (made quiet)
+            let nan = if mantissa != 0 { 0x0200 } else { 0 };
+            return F16(sign | 0x7C00 | nan);
+        }
+
+        let e = exponent - 127 + 15;
+        if e >= 0x1F {
+            return F16(sign | 0x7C00);
+        }
+
+        if e <= 0 {
+            // Subnormal in half precision, or too small and rounds to zero
+            if e < -10 {
+                return F16(sign);
+            }
+            let m = mantissa | 0x80_0000;
+            let shift = (14 - e) as u32;
+            let half = 1 << (shift - 1);
+            let rest = m & ((1 << shift) - 1);
+            let mut result = m >> shift;
+            if rest > half || (rest == half && result & 1 == 1) {
+                result += 1;
+            }
+            return F16(sign | result as u16);
+        }
+
+        let rest = mantissa & 0x1FFF;
+        let mut result = ((e as u32) << 10) | (mantissa >> 13);
+        if rest > 0x1000 || (rest == 0x1000 && result & 1 == 1) {
+            // May carry into the exponent, which is still correct (and may become infinity)
+            result += 1;
+        }
+        F16(sign | result as u16)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        let sign = ((self.0 & 0x8000) as u32) << 16;
+        let exponent = ((self.0 >> 10) & 0x1F) as u32;
+        let mantissa = (self.0 & 0x03FF) as u32;
+
+        match exponent {
+            0 => {
+                let value = mantissa as f32 / 16777216.0;
+                if sign != 0 {
+                    -value
+                } else {
+                    value
+                }
+            }
+            0x1F => f32::from_bits(sign | 0x7F80_0000 | (mantissa << 13)),
+            _ => f32::from_bits(sign | ((exponent + 127 - 15) << 23) | (mantissa << 13)),
+        }
+    }
+}
+
+impl Display for U24 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl Display for I24 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl Display for F16 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.to_f32(), f)
+    }
+}
+
+impl TryFrom<f32> for F16 {
+    type Error = OutOfRange;
+
+    fn try_from(value: f32) -> Result<Self, Self::Error> {
+        let result = F16::from_f32(value);
+        if value.is_finite() && !result.is_finite() {
+            Err(OutOfRange)
+        } else {
+            Ok(result)
+        }
+    }
+}
+
+impl TryFrom<f64> for F16 {
+    type Error = OutOfRange;
+
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        if value.is_finite() && value.abs() > f32::MAX as f64 {
+            return Err(OutOfRange);
+        }
+        F16::try_from(value as f32)
+    }
+}
+
+impl From<F16> for f32 {
+    fn from(value: F16) -> Self {
+        value.to_f32()
+    }
+}
+
+impl From<F16> for f64 {
+    fn from(value: F16) -> Self {
+        value.to_f32() as f64
+    }
+}
+
+macro_rules! impl_int_conversions {
+    ($t:ident, $inner:ty, lossless: [$($from:ty),*], checked: [$($try_from:ty),*], into: [$($into:ty),*]) => {
+        $(
+            impl From<$from> for $t {
+                fn from(value: $from) -> Self {
+                    $t(value as $inner)
+                }
+            }
+        )*
+        $(
+            impl TryFrom<$try_from> for $t {
+                type Error = OutOfRange;
+
+                fn try_from(value: $try_from) -> Result<Self, Self::Error> {
+                    let value = <$inner>::try_from(value).map_err(|_| OutOfRange)?;
+                    $t::new(value).ok_or(OutOfRange)
+                }
+            }
+        )*
+        $(
+            impl From<$t> for $into {
+                fn from(value: $t) -> Self {
+                    value.0 as $into
+                }
+            }
+        )*
+    };
+}
+
+impl_int_conversions!(U24, u32, lossless: [u8, u16], checked: [u32, u64, i32, i64, usize], into: [u32, u64, i32, i64, usize, f32, f64]);
+impl_int_conversions!(I24, i32, lossless: [u8, u16, i8, i16], checked: [i32, i64, u32, u64], into: [i32, i64, f32, f64]);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_u24_range() {
+        assert_eq!(U24::try_from(16777215u32).unwrap().get(), 16777215);
+        assert_eq!(U24::try_from(16777216u32), Err(OutOfRange));
+        assert_eq!(U24::try_from(-1i32), Err(OutOfRange));
+        assert_eq!(u32::from(U24::from(65535u16)), 65535);
+        assert_eq!(U24::MAX.to_be_bytes(), [0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_i24_range() {
+        assert_eq!(I24::try_from(8388607).unwrap().get(), 8388607);
+        assert_eq!(I24::try_from(-8388608).unwrap().get(), -8388608);
+        assert_eq!(I24::try_from(8388608), Err(OutOfRange));
+        assert_eq!(I24::try_from(-8388609i64), Err(OutOfRange));
+        assert_eq!(f64::from(I24::from(-5i8)), -5.0);
+    }
+
+    #[test]
+    fn test_24_bit_bytes() {
+        for value in [0, 1, -1, 255, 256, -256, 8388607, -8388608, 123456, -654321] {
+            let v = I24::new(value).unwrap();
+            assert_eq!(I24::from_be_bytes(v.to_be_bytes()), v);
+        }
+        for value in [0, 1, 255, 256, 65536, 16777215, 123456] {
+            let v = U24::new(value).unwrap();
+            assert_eq!(U24::from_be_bytes(v.to_be_bytes()), v);
+        }
+        assert_eq!(I24::new(-1).unwrap().to_be_bytes(), [0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_f16_known_values() {
+        assert_eq!(F16::from_f32(1.0), F16::ONE);
+        assert_eq!(F16::from_f32(-2.0).to_bits(), 0xC000);
+        assert_eq!(F16::from_f32(65504.0), F16::MAX);
+        assert_eq!(F16::from_f32(2f32.powi(-24)).to_bits(), 0x0001);
+        assert_eq!(F16::from_f32(0.333333).to_bits(), 0x3555);
+        assert_eq!(F16::from_f32(1e6), F16::INFINITY);
+        assert!(F16::from_f32(f32::NAN).is_nan());
+        assert_eq!(F16::MAX.to_f32(), 65504.0);
+        assert_eq!(F16::from_bits(0x0001).to_f32(), 2f32.powi(-24));
+        assert_eq!(F16::ONE.to_string(), "1");
+    }
+
+    #[test]
+    fn test_f16_range() {
+        assert_eq!(F16::try_from(65504.0f32), Ok(F16::MAX));
+        assert_eq!(F16::try_from(70000.0f32), Err(OutOfRange));
+        assert_eq!(F16::try_from(-1e300f64), Err(OutOfRange));
+        assert_eq!(F16::try_from(f32::INFINITY), Ok(F16::INFINITY));
+    }
+
+    #[test]
+    fn test_f16_all_values_round_trip() {
+        for bits in 0..=u16::MAX {
+            let value = F16::from_bits(bits);
+            if value.is_nan() {
+                assert!(F16::from_f32(value.to_f32()).is_nan());
+            } else {
+                assert_eq!(F16::from_f32(value.to_f32()), value, "bits {:04x}", bits);
+            }
+        }
+    }
+
+    #[test]
+    fn test_f16_rounds_to_nearest_even() {
+        // 1 + 2^-11 is exactly between 1 and the next half value, and rounds to the even one
+        assert_eq!(F16::from_f32(1.0 + 1.0 / 2048.0), F16::ONE);
+        assert_eq!(F16::from_f32(1.0 + 3.0 / 2048.0).to_bits(), 0x3C02);
+    }
+}
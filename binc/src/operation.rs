@@ -1,7 +1,12 @@
-use crate::attributes::{attribute_type, AttributeValue};
+use crate::attributes::{attribute_type, AttributeValue, F16, I24, U24};
+use crate::error::{BincError, InvalidOperation};
 use crate::node_id::NodeId;
-use crate::node_store::NodeStore;
-use crate::readwrite::{ReadExt, WriteExt};
+use crate::node_store::{check_new_id, NodeStore};
+use crate::position::Position;
+use crate::readwrite::{PositionReader, ReadExt, WriteExt};
+use crate::sources::Source;
+use blake3::Hash;
+use chrono::{DateTime, Utc};
 use std::fmt::{Debug, Display, Formatter};
 use std::io;
 use std::io::{Read, Write};
@@ -22,19 +27,27 @@ impl OperationIds {
     pub const SET_STRING: u64 = 0x09;
 
     // work in progress
+    pub const ADD_NODE_AT: u64 = 0x0A;
+    pub const MOVE_NODE_TO: u64 = 0x0B;
+
     pub const DEFINE_TAG_NAME: u64 = 0x14;
 
     pub const SNAPSHOT: u64 = 0x10;
     pub const CHECKSUM: u64 = 0x11;
+    pub const RESERVE_NODE_IDS: u64 = 0x12;
 
     pub const ADD_TAG: u64 = 0x18;
     pub const REMOVE_TAG: u64 = 0x19;
 
+    pub const REMOVE_ATTRIBUTE: u64 = 0x1A;
+
     pub const ADD_SOURCE: u64 = 0x21;
     pub const UPDATE_SOURCE: u64 = 0x22;
     pub const REMOVE_SOURCE: u64 = 0x23;
 
     pub const ADD_COMMENT: u64 = 0x31;
+    pub const EDIT_COMMENT: u64 = 0x32;
+    pub const RETRACT_COMMENT: u64 = 0x33;
 
     pub const SET_UUID: u64 = 0x42;
     pub const SET_UINT8: u64 = 0x43;
@@ -51,6 +64,13 @@ impl OperationIds {
     pub const SET_FLOAT32: u64 = 0x4E;
     pub const SET_FLOAT64: u64 = 0x4F;
 
+    pub const SET_BYTES: u64 = 0x50;
+    pub const SET_TIMESTAMP: u64 = 0x51;
+    pub const SET_DATE: u64 = 0x52;
+    pub const SET_DURATION: u64 = 0x53;
+    pub const SET_NODE_REFERENCE: u64 = 0x54;
+    pub const SET_NODE_REFERENCE_ARRAY: u64 = 0x55;
+
     pub const SET_BOOL_ARRAY: u64 = 0x60;
     pub const SET_STRING_ARRAY: u64 = 0x61;
     pub const SET_UUID_ARRAY: u64 = 0x62;
@@ -69,7 +89,7 @@ impl OperationIds {
     pub const SET_FLOAT64_ARRAY: u64 = 0x6F;
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Operation {
     /// Add a new node to the document tree with a given parent and index
     AddNode {
@@ -86,6 +106,23 @@ pub enum Operation {
         index_in_new_parent: usize,
     },
 
+    /// Add a new node at a position among the children of `parent`. Unlike an index, the
+    /// position doesn't depend on what others added or moved at the same time, so the children
+    /// end up in the same order whatever order the operations are applied in.
+    AddNodeAt {
+        id: NodeId,
+        node_type: usize,
+        parent: NodeId,
+        position: Position,
+    },
+
+    /// Move a node to a position among the children of a new parent, see `AddNodeAt`
+    MoveNodeTo {
+        id: NodeId,
+        new_parent: NodeId,
+        position: Position,
+    },
+
     /// Remove a node from the document tree
     RemoveNode { id: NodeId },
 
@@ -108,6 +145,9 @@ pub enum Operation {
         value: AttributeValue,
     },
 
+    /// Remove an attribute from a node
+    RemoveAttribute { node: NodeId, attribute: usize },
+
     /// Defines a user-readable name for a tag id
     DefineTagName { id: usize, name: String },
 
@@ -120,10 +160,21 @@ pub enum Operation {
     /// Add a named snapshot of the document
     Snapshot { author: String, message: String },
 
-    /// Add a checksum to the document up until this point. This can be used to verify the document is not corrupted
+    /// Add a checksum to the document up until this point. This can be used to verify the document is not corrupted.
+    /// `data` is the blake3 hash of the container id followed by every operation before this one. The container
+    /// version and the header are not covered, as they change when a journal is written as another version.
     Checksum { data: Vec<u8> },
 
-    /// Add a comment to a node
+    /// Set aside `count` node ids starting at `first` for one client, so the nodes it adds can't
+    /// get the same ids as nodes added by others at the same time. `client` says who they are for.
+    ReserveNodeIds {
+        first: NodeId,
+        count: usize,
+        client: String,
+    },
+
+    /// Add a comment to a node. `response_to` is the id of the comment this replies to, or
+    /// `Comments::NO_COMMENT`
     AddComment {
         node: NodeId,
         comment: String,
@@ -131,6 +182,35 @@ pub enum Operation {
         response_to: usize,
     },
 
+    /// Replace the text of a comment
+    EditComment {
+        node: NodeId,
+        comment: usize,
+        text: String,
+    },
+
+    /// Take back a comment. Replies to it are kept.
+    RetractComment { node: NodeId, comment: usize },
+
+    /// Record where data in the document came from, like an imported file
+    AddSource {
+        id: usize,
+        importer: String,
+        path: String,
+        hash: Hash,
+        imported: DateTime<Utc>,
+    },
+
+    /// Record that a source was imported again, with different data
+    UpdateSource {
+        id: usize,
+        hash: Hash,
+        imported: DateTime<Utc>,
+    },
+
+    /// Forget a source. The id is not reused.
+    RemoveSource { id: usize },
+
     /// Unknown change type. Since the size is known, the data can be read and written without knowing the type
     UnknownOperation { operation: u64, data: Vec<u8> },
 }
@@ -140,7 +220,83 @@ impl Operation {
     // locate which ranges of the file are corrupted and automatically repair them using other sources.
     pub const HASH_ID: u32 = u32::from_be_bytes(*b"h@sH");
 
-    pub(crate) fn apply(&self, nodes: &mut NodeStore) {
+    /// Name of the optional feature the operation belongs to, or `None` for operations that are
+    /// part of version 1 of the format. These are the names used in `FileHeader::features`.
+    pub fn feature(&self) -> Option<&'static str> {
+        match self {
+            Operation::AddNode { .. }
+            | Operation::RemoveNode { .. }
+            | Operation::MoveNode { .. }
+            | Operation::SetType { .. }
+            | Operation::DefineTypeName { .. }
+            | Operation::SetName { .. }
+            | Operation::DefineAttributeName { .. }
+            | Operation::UnknownOperation { .. } => None,
+            Operation::SetAttribute { value, .. } => match value {
+                AttributeValue::Bool(_) | AttributeValue::String(_) => None,
+                AttributeValue::Timestamp(_)
+                | AttributeValue::Date(_)
+                | AttributeValue::Duration(_) => Some("dates"),
+                AttributeValue::NodeReference(_) | AttributeValue::NodeReferenceArray(_) => {
+                    Some("node-references")
+                }
+                _ => Some("typed-attributes"),
+            },
+            Operation::AddNodeAt { .. } | Operation::MoveNodeTo { .. } => Some("positions"),
+            Operation::RemoveAttribute { .. } => Some("remove-attribute"),
+            Operation::Snapshot { .. } => Some("snapshots"),
+            Operation::Checksum { .. } => Some("checksums"),
+            Operation::ReserveNodeIds { .. } => Some("id-blocks"),
+            Operation::DefineTagName { .. }
+            | Operation::SetTag { .. }
+            | Operation::RemoveTag { .. } => Some("tags"),
+            Operation::AddComment { .. }
+            | Operation::EditComment { .. }
+            | Operation::RetractComment { .. } => Some("comments"),
+            Operation::AddSource { .. }
+            | Operation::UpdateSource { .. }
+            | Operation::RemoveSource { .. } => Some("sources"),
+        }
+    }
+
+    /// Every node id the operation mentions, including the targets of node references
+    pub fn node_ids(&self) -> Vec<NodeId> {
+        match self {
+            Operation::AddNode { id, parent, .. } | Operation::AddNodeAt { id, parent, .. } => {
+                vec![*id, *parent]
+            }
+            Operation::MoveNode { id, new_parent, .. }
+            | Operation::MoveNodeTo { id, new_parent, .. } => vec![*id, *new_parent],
+            Operation::RemoveNode { id } => vec![*id],
+            Operation::SetAttribute { node, value, .. } => {
+                let mut ids = vec![*node];
+                ids.extend_from_slice(value.node_references());
+                ids
+            }
+            Operation::SetType { node, .. }
+            | Operation::SetName { node, .. }
+            | Operation::RemoveAttribute { node, .. }
+            | Operation::SetTag { node, .. }
+            | Operation::RemoveTag { node, .. }
+            | Operation::AddComment { node, .. }
+            | Operation::EditComment { node, .. }
+            | Operation::RetractComment { node, .. } => vec![*node],
+            Operation::DefineTypeName { .. }
+            | Operation::DefineAttributeName { .. }
+            | Operation::DefineTagName { .. }
+            | Operation::Snapshot { .. }
+            | Operation::Checksum { .. }
+            | Operation::ReserveNodeIds { .. }
+            | Operation::AddSource { .. }
+            | Operation::UpdateSource { .. }
+            | Operation::RemoveSource { .. }
+            | Operation::UnknownOperation { .. } => vec![],
+        }
+    }
+
+    /// Apply the operation to `nodes`. An operation that doesn't fit the current nodes, like one
+    /// for a node that doesn't exist, is rejected and leaves `nodes` unchanged.
+    pub(crate) fn apply(&self, nodes: &mut NodeStore) -> Result<(), InvalidOperation> {
         match self {
             Operation::AddNode {
                 id,
@@ -148,43 +304,62 @@ impl Operation {
                 parent,
                 index_in_parent,
             } => {
-                nodes.add(*id, *node_type, *parent, *index_in_parent as usize);
+                nodes.add(*id, *node_type, *parent, *index_in_parent as usize)?;
             }
             Operation::RemoveNode { id } => {
-                nodes.delete_recursive(*id);
+                nodes.delete_recursive(*id)?;
             }
             Operation::MoveNode {
                 id,
                 new_parent,
                 index_in_new_parent,
             } => {
-                nodes.move_node(*id, *new_parent, *index_in_new_parent as usize);
+                nodes.move_node(*id, *new_parent, *index_in_new_parent as usize)?;
+            }
+            Operation::AddNodeAt {
+                id,
+                node_type,
+                parent,
+                position,
+            } => {
+                nodes.add_at(*id, *node_type, *parent, position)?;
+            }
+            Operation::MoveNodeTo {
+                id,
+                new_parent,
+                position,
+            } => {
+                nodes.move_to(*id, *new_parent, position)?;
             }
             Operation::SetType { node, type_id: id } => {
-                let x = nodes.get_mut(*node).expect("Node not found");
+                let x = nodes.existing_mut(*node)?;
                 x.set_type(*id);
             }
             Operation::SetName { node, name } => {
-                let x = nodes.get_mut(*node).expect("Node not found");
+                let x = nodes.existing_mut(*node)?;
                 x.set_name(name);
             }
             Operation::DefineTypeName { id, name } => {
-                nodes.define_type_name(*id, name);
+                nodes.define_type_name(*id, name)?;
             }
             Operation::DefineAttributeName { id, name } => {
-                nodes.define_attribute_name(*id, name);
+                nodes.define_attribute_name(*id, name)?;
             }
             Operation::DefineTagName { id, name } => {
-                nodes.define_tag_name(*id, name);
+                nodes.define_tag_name(*id, name)?;
             }
             Operation::SetTag { node, tag } => {
-                let x = nodes.get_mut(*node).expect("Node not found");
+                let x = nodes.existing_mut(*node)?;
                 x.set_tag(*tag);
             }
             Operation::RemoveTag { node, tag } => {
-                let x = nodes.get_mut(*node).expect("Node not found");
+                let x = nodes.existing_mut(*node)?;
                 x.clear_tag(*tag);
             }
+            Operation::RemoveAttribute { node, attribute } => {
+                let x = nodes.existing_mut(*node)?;
+                x.remove_attribute(*attribute);
+            }
             Operation::Snapshot {
                 author: _,
                 message: _,
@@ -194,12 +369,16 @@ impl Operation {
             Operation::Checksum { data: _ } => {
                 // no-op
             }
+            Operation::ReserveNodeIds { first, count, .. } => {
+                // Otherwise only affects which ids are handed out, see `NodeIdGenerator`
+                nodes.reserve_ids(*first, *count)?;
+            }
             Operation::SetAttribute {
                 node,
                 attribute,
                 value,
             } => {
-                let x = nodes.get_mut(*node).expect("Node not found");
+                let x = nodes.existing_mut(*node)?;
                 x.set_attribute(*attribute, value.clone());
             }
             Operation::AddComment {
@@ -208,9 +387,57 @@ impl Operation {
                 author,
                 response_to,
             } => {
-                let x = nodes.get_mut(*node).expect("Node not found");
+                let x = nodes.existing_mut(*node)?;
                 x.add_comment(comment, author, *response_to);
             }
+            Operation::EditComment {
+                node,
+                comment,
+                text,
+            } => {
+                let x = nodes.existing_mut(*node)?;
+                if !x.comments.edit_comment(*comment, text) {
+                    return Err(InvalidOperation::CommentNotFound {
+                        node: *node,
+                        comment: *comment,
+                    });
+                }
+            }
+            Operation::RetractComment { node, comment } => {
+                let x = nodes.existing_mut(*node)?;
+                if !x.comments.retract_comment(*comment) {
+                    return Err(InvalidOperation::CommentNotFound {
+                        node: *node,
+                        comment: *comment,
+                    });
+                }
+            }
+            Operation::AddSource {
+                id,
+                importer,
+                path,
+                hash,
+                imported,
+            } => {
+                check_new_id(*id, nodes.sources.next_id())?;
+                nodes.sources.add(
+                    *id,
+                    Source {
+                        importer: importer.clone(),
+                        path: path.clone(),
+                        hash: *hash,
+                        imported: *imported,
+                    },
+                );
+            }
+            Operation::UpdateSource { id, hash, imported } => {
+                if !nodes.sources.update(*id, *hash, *imported) {
+                    return Err(InvalidOperation::SourceNotFound(*id));
+                }
+            }
+            Operation::RemoveSource { id } => {
+                nodes.sources.remove(*id);
+            }
             Operation::UnknownOperation {
                 operation: _,
                 data: _,
@@ -218,11 +445,52 @@ impl Operation {
                 // Do nothing
             }
         }
+        Ok(())
+    }
+
+    /// Read one operation. Offsets in errors are relative to the start of the operation, so
+    /// `BincError::EndOfStream` means there was no operation left at all.
+    pub(crate) fn read<T: Read>(r: &mut T) -> Result<Operation, BincError> {
+        let mut r = PositionReader::new(r);
+        let operation = match r.read_length_flipped() {
+            Ok(operation) => operation as u64,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && r.position() == 0 => {
+                return Err(BincError::EndOfStream);
+            }
+            Err(e) => return Err(BincError::from_io(e, 0)),
+        };
+        let size = r.read_length().map_err(|e| BincError::from_io(e, 0))?;
+        let content = r
+            .read_exact_vec(size)
+            .map_err(|e| BincError::from_io(e, 0))?;
+
+        let mut content = content.as_slice();
+        let result = Self::read_content(operation, &mut content).map_err(|e| {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                BincError::InvalidData {
+                    offset: 0,
+                    message: format!(
+                        "Operation {:#x} is longer than its size {}",
+                        operation, size
+                    ),
+                }
+            } else {
+                BincError::from_io(e, 0)
+            }
+        })?;
+        if !content.is_empty() {
+            return Err(BincError::InvalidData {
+                offset: 0,
+                message: format!(
+                    "Operation {:#x} is shorter than its size {}",
+                    operation, size
+                ),
+            });
+        }
+        Ok(result)
     }
 
-    pub(crate) fn read<T: Read>(r: &mut T) -> io::Result<Operation> {
-        let operation = r.read_length_flipped()? as u64;
-        let size = r.read_length()?;
+    fn read_content(operation: u64, r: &mut &[u8]) -> io::Result<Operation> {
         match operation {
             OperationIds::ADD_NODE => {
                 let id = r.read_id()?;
@@ -250,6 +518,28 @@ impl Operation {
                     index_in_new_parent,
                 })
             }
+            OperationIds::ADD_NODE_AT => {
+                let id = r.read_id()?;
+                let node_type = r.read_length()?;
+                let parent = r.read_id()?;
+                let position = read_position(r)?;
+                Ok(Operation::AddNodeAt {
+                    id,
+                    node_type,
+                    parent,
+                    position,
+                })
+            }
+            OperationIds::MOVE_NODE_TO => {
+                let id = r.read_id()?;
+                let new_parent = r.read_id()?;
+                let position = read_position(r)?;
+                Ok(Operation::MoveNodeTo {
+                    id,
+                    new_parent,
+                    position,
+                })
+            }
             OperationIds::SNAPSHOT => {
                 let author = r.read_string()?;
                 let message = r.read_string()?;
@@ -266,6 +556,16 @@ impl Operation {
                 let data = r.read_bytes()?;
                 Ok(Operation::Checksum { data })
             }
+            OperationIds::RESERVE_NODE_IDS => {
+                let first = r.read_id()?;
+                let count = r.read_length()?;
+                let client = r.read_string()?;
+                Ok(Operation::ReserveNodeIds {
+                    first,
+                    count,
+                    client,
+                })
+            }
             OperationIds::SET_STRING => {
                 let node = r.read_id()?;
                 let attribute = r.read_length()?;
@@ -396,6 +696,256 @@ impl Operation {
                     value: AttributeValue::F64(value),
                 })
             }
+            OperationIds::SET_UINT24 => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_u24()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::U24(value),
+                })
+            }
+            OperationIds::SET_INT24 => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_i24()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::I24(value),
+                })
+            }
+            OperationIds::SET_FLOAT16 => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_f16()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::F16(value),
+                })
+            }
+            OperationIds::SET_BYTES => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_bytes()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::Bytes(value),
+                })
+            }
+            OperationIds::SET_TIMESTAMP => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_timestamp()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::Timestamp(value),
+                })
+            }
+            OperationIds::SET_DATE => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_date()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::Date(value),
+                })
+            }
+            OperationIds::SET_DURATION => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_duration()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::Duration(value),
+                })
+            }
+            OperationIds::SET_NODE_REFERENCE => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_id()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::NodeReference(value),
+                })
+            }
+            OperationIds::SET_NODE_REFERENCE_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_id_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::NodeReferenceArray(value),
+                })
+            }
+            OperationIds::SET_BOOL_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_packed_bools()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::BoolArray(value),
+                })
+            }
+            OperationIds::SET_STRING_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_string_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::StringArray(value),
+                })
+            }
+            OperationIds::SET_UUID_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_uuid_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::UuidArray(value),
+                })
+            }
+            OperationIds::SET_UINT8_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_bytes()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::U8Array(value),
+                })
+            }
+            OperationIds::SET_UINT16_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_packed(u16::from_be_bytes)?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::U16Array(value),
+                })
+            }
+            OperationIds::SET_UINT24_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_packed(U24::from_be_bytes)?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::U24Array(value),
+                })
+            }
+            OperationIds::SET_UINT32_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_packed(u32::from_be_bytes)?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::U32Array(value),
+                })
+            }
+            OperationIds::SET_UINT64_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_packed(u64::from_be_bytes)?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::U64Array(value),
+                })
+            }
+            OperationIds::SET_INT8_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_packed(i8::from_be_bytes)?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::I8Array(value),
+                })
+            }
+            OperationIds::SET_INT16_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_packed(i16::from_be_bytes)?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::I16Array(value),
+                })
+            }
+            OperationIds::SET_INT24_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_packed(I24::from_be_bytes)?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::I24Array(value),
+                })
+            }
+            OperationIds::SET_INT32_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_packed(i32::from_be_bytes)?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::I32Array(value),
+                })
+            }
+            OperationIds::SET_INT64_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_packed(i64::from_be_bytes)?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::I64Array(value),
+                })
+            }
+            OperationIds::SET_FLOAT16_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_packed(F16::from_be_bytes)?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::F16Array(value),
+                })
+            }
+            OperationIds::SET_FLOAT32_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_packed(f32::from_be_bytes)?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::F32Array(value),
+                })
+            }
+            OperationIds::SET_FLOAT64_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_packed(f64::from_be_bytes)?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::F64Array(value),
+                })
+            }
             OperationIds::SET_NAME => {
                 let node = r.read_id()?;
                 let name = r.read_string()?;
@@ -424,6 +974,21 @@ impl Operation {
                 let name = r.read_string()?;
                 Ok(Operation::DefineTagName { id, name })
             }
+            OperationIds::ADD_TAG => {
+                let node = r.read_id()?;
+                let tag = r.read_length()?;
+                Ok(Operation::SetTag { node, tag })
+            }
+            OperationIds::REMOVE_TAG => {
+                let node = r.read_id()?;
+                let tag = r.read_length()?;
+                Ok(Operation::RemoveTag { node, tag })
+            }
+            OperationIds::REMOVE_ATTRIBUTE => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                Ok(Operation::RemoveAttribute { node, attribute })
+            }
             OperationIds::ADD_COMMENT => {
                 let node = r.read_id()?;
                 let comment = r.read_string()?;
@@ -436,9 +1001,48 @@ impl Operation {
                     response_to,
                 })
             }
+            OperationIds::ADD_SOURCE => {
+                let id = r.read_length()?;
+                let importer = r.read_string()?;
+                let path = r.read_string()?;
+                let hash = r.read_hash()?;
+                let imported = r.read_timestamp()?;
+                Ok(Operation::AddSource {
+                    id,
+                    importer,
+                    path,
+                    hash,
+                    imported,
+                })
+            }
+            OperationIds::UPDATE_SOURCE => {
+                let id = r.read_length()?;
+                let hash = r.read_hash()?;
+                let imported = r.read_timestamp()?;
+                Ok(Operation::UpdateSource { id, hash, imported })
+            }
+            OperationIds::REMOVE_SOURCE => {
+                let id = r.read_length()?;
+                Ok(Operation::RemoveSource { id })
+            }
+            OperationIds::EDIT_COMMENT => {
+                let node = r.read_id()?;
+                let comment = r.read_length()?;
+                let text = r.read_string()?;
+                Ok(Operation::EditComment {
+                    node,
+                    comment,
+                    text,
+                })
+            }
+            OperationIds::RETRACT_COMMENT => {
+                let node = r.read_id()?;
+                let comment = r.read_length()?;
+                Ok(Operation::RetractComment { node, comment })
+            }
             _ => {
-                let mut data = vec![0; size as usize];
-                r.read_exact(&mut data)?;
+                let data = r.to_vec();
+                *r = &[];
                 Ok(Operation::UnknownOperation { operation, data })
             }
         }
@@ -478,6 +1082,26 @@ impl Operation {
                 w.write_id(new_parent)?;
                 w.write_length(*index_in_new_parent)
             }
+            Operation::AddNodeAt {
+                id,
+                node_type,
+                parent,
+                position,
+            } => {
+                w.write_id(id)?;
+                w.write_length(*node_type)?;
+                w.write_id(parent)?;
+                w.write_bytes(position.as_bytes())
+            }
+            Operation::MoveNodeTo {
+                id,
+                new_parent,
+                position,
+            } => {
+                w.write_id(id)?;
+                w.write_id(new_parent)?;
+                w.write_bytes(position.as_bytes())
+            }
             Operation::RemoveNode { id } => w.write_id(id),
             Operation::Snapshot { author, message } => {
                 w.write_string(author)?;
@@ -487,6 +1111,15 @@ impl Operation {
                 w.write_u32(Operation::HASH_ID)?;
                 w.write_bytes(data)
             }
+            Operation::ReserveNodeIds {
+                first,
+                count,
+                client,
+            } => {
+                w.write_id(first)?;
+                w.write_length(*count)?;
+                w.write_string(client)
+            }
             Operation::SetName { node, name: label } => {
                 w.write_id(node)?;
                 w.write_string(label)
@@ -515,6 +1148,10 @@ impl Operation {
                 w.write_id(node)?;
                 w.write_length(*tag)
             }
+            Operation::RemoveAttribute { node, attribute } => {
+                w.write_id(node)?;
+                w.write_length(*attribute)
+            }
             Operation::SetAttribute {
                 node,
                 attribute,
@@ -528,16 +1165,39 @@ impl Operation {
                     AttributeValue::Uuid(u) => w.write_uuid(u),
                     AttributeValue::U8(u) => w.write_u8(*u),
                     AttributeValue::U16(u) => w.write_u16(*u),
-                    AttributeValue::U24(u) => w.write_bytes(u),
+                    AttributeValue::U24(u) => w.write_u24(*u),
                     AttributeValue::U32(u) => w.write_u32(*u),
                     AttributeValue::U64(u) => w.write_u64(*u),
                     AttributeValue::I8(u) => w.write_i8(*u),
                     AttributeValue::I16(u) => w.write_i16(*u),
-                    AttributeValue::I24(u) => w.write_bytes(u),
+                    AttributeValue::I24(u) => w.write_i24(*u),
                     AttributeValue::I32(u) => w.write_i32(*u),
                     AttributeValue::I64(u) => w.write_i64(*u),
+                    AttributeValue::F16(u) => w.write_f16(*u),
                     AttributeValue::F32(u) => w.write_f32(*u),
                     AttributeValue::F64(u) => w.write_f64(*u),
+                    AttributeValue::Bytes(b) => w.write_bytes(b),
+                    AttributeValue::Timestamp(t) => w.write_timestamp(*t),
+                    AttributeValue::Date(d) => w.write_date(*d),
+                    AttributeValue::Duration(d) => w.write_duration(*d),
+                    AttributeValue::NodeReference(id) => w.write_id(id),
+                    AttributeValue::NodeReferenceArray(a) => w.write_id_array(a),
+                    AttributeValue::BoolArray(a) => w.write_packed_bools(a),
+                    AttributeValue::StringArray(a) => w.write_string_array(a),
+                    AttributeValue::UuidArray(a) => w.write_uuid_array(a),
+                    AttributeValue::U8Array(a) => w.write_bytes(a),
+                    AttributeValue::U16Array(a) => w.write_packed(a, |v| v.to_be_bytes()),
+                    AttributeValue::U24Array(a) => w.write_packed(a, |v| v.to_be_bytes()),
+                    AttributeValue::U32Array(a) => w.write_packed(a, |v| v.to_be_bytes()),
+                    AttributeValue::U64Array(a) => w.write_packed(a, |v| v.to_be_bytes()),
+                    AttributeValue::I8Array(a) => w.write_packed(a, |v| v.to_be_bytes()),
+                    AttributeValue::I16Array(a) => w.write_packed(a, |v| v.to_be_bytes()),
+                    AttributeValue::I24Array(a) => w.write_packed(a, |v| v.to_be_bytes()),
+                    AttributeValue::I32Array(a) => w.write_packed(a, |v| v.to_be_bytes()),
+                    AttributeValue::I64Array(a) => w.write_packed(a, |v| v.to_be_bytes()),
+                    AttributeValue::F16Array(a) => w.write_packed(a, |v| v.to_be_bytes()),
+                    AttributeValue::F32Array(a) => w.write_packed(a, |v| v.to_be_bytes()),
+                    AttributeValue::F64Array(a) => w.write_packed(a, |v| v.to_be_bytes()),
                 }
             }
             Operation::AddComment {
@@ -551,6 +1211,38 @@ impl Operation {
                 w.write_string(author)?;
                 w.write_length(*response_to)
             }
+            Operation::EditComment {
+                node,
+                comment,
+                text,
+            } => {
+                w.write_id(node)?;
+                w.write_length(*comment)?;
+                w.write_string(text)
+            }
+            Operation::RetractComment { node, comment } => {
+                w.write_id(node)?;
+                w.write_length(*comment)
+            }
+            Operation::AddSource {
+                id,
+                importer,
+                path,
+                hash,
+                imported,
+            } => {
+                w.write_length(*id)?;
+                w.write_string(importer)?;
+                w.write_string(path)?;
+                w.write_hash(hash)?;
+                w.write_timestamp(*imported)
+            }
+            Operation::UpdateSource { id, hash, imported } => {
+                w.write_length(*id)?;
+                w.write_hash(hash)?;
+                w.write_timestamp(*imported)
+            }
+            Operation::RemoveSource { id } => w.write_length(*id),
             Operation::UnknownOperation { operation: _, data } => w.write_all(data),
         }
     }
@@ -568,12 +1260,15 @@ impl Operation {
                 new_parent: _,
                 index_in_new_parent: _,
             } => OperationIds::MOVE_NODE,
+            Operation::AddNodeAt { .. } => OperationIds::ADD_NODE_AT,
+            Operation::MoveNodeTo { .. } => OperationIds::MOVE_NODE_TO,
             Operation::RemoveNode { id: _ } => OperationIds::REMOVE_NODE,
             Operation::Snapshot {
                 author: _,
                 message: _,
             } => OperationIds::SNAPSHOT,
             Operation::Checksum { data: _ } => OperationIds::CHECKSUM,
+            Operation::ReserveNodeIds { .. } => OperationIds::RESERVE_NODE_IDS,
             Operation::SetName { node: _, name: _ } => OperationIds::SET_NAME,
             Operation::SetType {
                 node: _,
@@ -586,6 +1281,10 @@ impl Operation {
             Operation::DefineTagName { id: _, name: _ } => OperationIds::DEFINE_TAG_NAME,
             Operation::SetTag { node: _, tag: _ } => OperationIds::ADD_TAG,
             Operation::RemoveTag { node: _, tag: _ } => OperationIds::REMOVE_TAG,
+            Operation::RemoveAttribute {
+                node: _,
+                attribute: _,
+            } => OperationIds::REMOVE_ATTRIBUTE,
             Operation::SetAttribute {
                 node: _,
                 attribute: _,
@@ -604,8 +1303,31 @@ impl Operation {
                 AttributeValue::I24(_) => OperationIds::SET_INT24,
                 AttributeValue::I32(_) => OperationIds::SET_INT32,
                 AttributeValue::I64(_) => OperationIds::SET_INT64,
+                AttributeValue::F16(_) => OperationIds::SET_FLOAT16,
                 AttributeValue::F32(_) => OperationIds::SET_FLOAT32,
                 AttributeValue::F64(_) => OperationIds::SET_FLOAT64,
+                AttributeValue::Bytes(_) => OperationIds::SET_BYTES,
+                AttributeValue::Timestamp(_) => OperationIds::SET_TIMESTAMP,
+                AttributeValue::Date(_) => OperationIds::SET_DATE,
+                AttributeValue::Duration(_) => OperationIds::SET_DURATION,
+                AttributeValue::NodeReference(_) => OperationIds::SET_NODE_REFERENCE,
+                AttributeValue::NodeReferenceArray(_) => OperationIds::SET_NODE_REFERENCE_ARRAY,
+                AttributeValue::BoolArray(_) => OperationIds::SET_BOOL_ARRAY,
+                AttributeValue::StringArray(_) => OperationIds::SET_STRING_ARRAY,
+                AttributeValue::UuidArray(_) => OperationIds::SET_UUID_ARRAY,
+                AttributeValue::U8Array(_) => OperationIds::SET_UINT8_ARRAY,
+                AttributeValue::U16Array(_) => OperationIds::SET_UINT16_ARRAY,
+                AttributeValue::U24Array(_) => OperationIds::SET_UINT24_ARRAY,
+                AttributeValue::U32Array(_) => OperationIds::SET_UINT32_ARRAY,
+                AttributeValue::U64Array(_) => OperationIds::SET_UINT64_ARRAY,
+                AttributeValue::I8Array(_) => OperationIds::SET_INT8_ARRAY,
+                AttributeValue::I16Array(_) => OperationIds::SET_INT16_ARRAY,
+                AttributeValue::I24Array(_) => OperationIds::SET_INT24_ARRAY,
+                AttributeValue::I32Array(_) => OperationIds::SET_INT32_ARRAY,
+                AttributeValue::I64Array(_) => OperationIds::SET_INT64_ARRAY,
+                AttributeValue::F16Array(_) => OperationIds::SET_FLOAT16_ARRAY,
+                AttributeValue::F32Array(_) => OperationIds::SET_FLOAT32_ARRAY,
+                AttributeValue::F64Array(_) => OperationIds::SET_FLOAT64_ARRAY,
             },
             Operation::AddComment {
                 node: _,
@@ -613,6 +1335,28 @@ impl Operation {
                 author: _,
                 response_to: _,
             } => OperationIds::ADD_COMMENT,
+            Operation::EditComment {
+                node: _,
+                comment: _,
+                text: _,
+            } => OperationIds::EDIT_COMMENT,
+            Operation::RetractComment {
+                node: _,
+                comment: _,
+            } => OperationIds::RETRACT_COMMENT,
+            Operation::AddSource {
+                id: _,
+                importer: _,
+                path: _,
+                hash: _,
+                imported: _,
+            } => OperationIds::ADD_SOURCE,
+            Operation::UpdateSource {
+                id: _,
+                hash: _,
+                imported: _,
+            } => OperationIds::UPDATE_SOURCE,
+            Operation::RemoveSource { id: _ } => OperationIds::REMOVE_SOURCE,
             Operation::UnknownOperation {
                 operation: operation,
                 data: _,
@@ -620,6 +1364,8 @@ impl Operation {
         }
     }
 
+    /// One operation that does the same as `previous_operation` followed by this one, if there
+    /// is one. Setting the same name or attribute twice only needs the second value.
     pub fn combine_operations(&self, previous_operation: &Operation) -> Option<Operation> {
         if let Operation::SetAttribute {
             node,
@@ -662,6 +1408,12 @@ impl Operation {
     }
 }
 
+fn read_position(r: &mut &[u8]) -> io::Result<Position> {
+    let bytes = r.read_bytes()?;
+    Position::from_bytes(bytes)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid position"))
+}
+
 impl Display for Operation {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -684,11 +1436,35 @@ impl Display for Operation {
                 "MoveNode({} to {}[{}])",
                 id, new_parent, index_in_new_parent
             ),
+            Operation::AddNodeAt {
+                id,
+                node_type,
+                parent,
+                position,
+            } => write!(
+                f,
+                "AddNodeAt({}[{}] in {} at {})",
+                id, node_type, parent, position
+            ),
+            Operation::MoveNodeTo {
+                id,
+                new_parent,
+                position,
+            } => write!(f, "MoveNodeTo({} to {} at {})", id, new_parent, position),
             Operation::RemoveNode { id } => write!(f, "RemoveNode({})", id),
             Operation::Snapshot { author, message } => {
                 write!(f, "Snapshot by {} ({})", author, message)
             }
             Operation::Checksum { data } => write!(f, "Checksum({} bytes)", data.len()),
+            Operation::ReserveNodeIds {
+                first,
+                count,
+                client,
+            } => write!(
+                f,
+                "ReserveNodeIds({} ids from {} for {})",
+                count, first, client
+            ),
             Operation::SetType { node, type_id } => write!(f, "SetType({}, {})", node, type_id),
             Operation::SetName { node, name: label } => write!(f, "SetLabel({}, {})", node, label),
             Operation::DefineTypeName { id, name } => write!(f, "SetTypeName({}, {})", id, name),
@@ -698,6 +1474,9 @@ impl Display for Operation {
             Operation::DefineTagName { id, name } => write!(f, "SetTagName({}, {})", id, name),
             Operation::SetTag { node, tag } => write!(f, "SetTag({}, {})", node, tag),
             Operation::RemoveTag { node, tag } => write!(f, "RemoveTag({}, {})", node, tag),
+            Operation::RemoveAttribute { node, attribute } => {
+                write!(f, "RemoveAttribute({}, {})", node, attribute)
+            }
             Operation::SetAttribute {
                 node,
                 attribute,
@@ -739,6 +1518,326 @@ impl Display for Operation {
                 "AddComment({}, {} by {} in response to {})",
                 node, comment, author, response_to
             ),
+            Operation::EditComment {
+                node,
+                comment,
+                text,
+            } => write!(f, "EditComment({}, {} = {})", node, comment, text),
+            Operation::RetractComment { node, comment } => {
+                write!(f, "RetractComment({}, {})", node, comment)
+            }
+            Operation::AddSource {
+                id,
+                importer,
+                path,
+                hash: _,
+                imported,
+            } => write!(
+                f,
+                "AddSource({}, {} {} at {})",
+                id, importer, path, imported
+            ),
+            Operation::UpdateSource {
+                id,
+                hash: _,
+                imported,
+            } => write!(f, "UpdateSource({} at {})", id, imported),
+            Operation::RemoveSource { id } => write!(f, "RemoveSource({})", id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, TimeDelta};
+
+    /// Small xorshift generator so the round-trip tests are reproducible without extra dependencies
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> usize {
+            (self.next() % n) as usize
+        }
+
+        fn bytes<const N: usize>(&mut self) -> [u8; N] {
+            let mut b = [0; N];
+            b.iter_mut().for_each(|x| *x = self.next() as u8);
+            b
+        }
+
+        fn string(&mut self) -> String {
+            let len = self.below(20);
+            (0..len)
+                .map(|_| ['a', 'b', 'ä', ' ', '7', '€'][self.below(6)])
+                .collect()
+        }
+
+        fn vec<T>(&mut self, f: impl Fn(&mut Self) -> T) -> Vec<T> {
+            let len = self.below(12);
+            (0..len).map(|_| f(self)).collect()
+        }
+
+        fn node(&mut self) -> NodeId {
+            NodeId::new(1 + self.below(100000))
+        }
+
+        fn position(&mut self) -> Position {
+            let mut bytes = self.vec(|r| r.next() as u8);
+            bytes.push(1 + self.below(255) as u8);
+            Position::from_bytes(bytes).unwrap()
         }
     }
+
+    fn random_value(r: &mut Random, kind: usize) -> AttributeValue {
+        match kind {
+            0 => AttributeValue::String(r.string()),
+            1 => AttributeValue::Bool(r.next() & 1 == 1),
+            2 => AttributeValue::Uuid(uuid::Uuid::from_bytes(r.bytes())),
+            3 => AttributeValue::U8(r.next() as u8),
+            4 => AttributeValue::U16(r.next() as u16),
+            5 => AttributeValue::U24(U24::from_be_bytes(r.bytes())),
+            6 => AttributeValue::U32(r.next() as u32),
+            7 => AttributeValue::U64(r.next()),
+            8 => AttributeValue::I8(r.next() as i8),
+            9 => AttributeValue::I16(r.next() as i16),
+            10 => AttributeValue::I24(I24::from_be_bytes(r.bytes())),
+            11 => AttributeValue::I32(r.next() as i32),
+            12 => AttributeValue::I64(r.next() as i64),
+            13 => AttributeValue::F16(F16::from_f32(r.next() as i16 as f32 / 8.0)),
+            14 => AttributeValue::F32(r.next() as i32 as f32 / 7.0),
+            15 => AttributeValue::F64(r.next() as i64 as f64 / 7.0),
+            16 => AttributeValue::BoolArray(r.vec(|r| r.next() & 1 == 1)),
+            17 => AttributeValue::StringArray(r.vec(|r| r.string())),
+            18 => AttributeValue::UuidArray(r.vec(|r| uuid::Uuid::from_bytes(r.bytes()))),
+            19 => AttributeValue::U8Array(r.vec(|r| r.next() as u8)),
+            20 => AttributeValue::U16Array(r.vec(|r| r.next() as u16)),
+            21 => AttributeValue::U24Array(r.vec(|r| U24::from_be_bytes(r.bytes()))),
+            22 => AttributeValue::U32Array(r.vec(|r| r.next() as u32)),
+            23 => AttributeValue::U64Array(r.vec(|r| r.next())),
+            24 => AttributeValue::I8Array(r.vec(|r| r.next() as i8)),
+            25 => AttributeValue::I16Array(r.vec(|r| r.next() as i16)),
+            26 => AttributeValue::I24Array(r.vec(|r| I24::from_be_bytes(r.bytes()))),
+            27 => AttributeValue::I32Array(r.vec(|r| r.next() as i32)),
+            28 => AttributeValue::I64Array(r.vec(|r| r.next() as i64)),
+            29 => AttributeValue::F16Array(r.vec(|r| F16::from_bits(r.next() as u16 & 0x7BFF))),
+            30 => AttributeValue::F32Array(r.vec(|r| r.next() as i32 as f32 / 3.0)),
+            31 => AttributeValue::F64Array(r.vec(|r| r.next() as i64 as f64 / 3.0)),
+            32 => AttributeValue::Bytes(r.vec(|r| r.next() as u8)),
+            33 => AttributeValue::from(
+                DateTime::from_timestamp_millis(r.next() as i64 >> 20).unwrap(),
+            ),
+            34 => AttributeValue::Date(
+                NaiveDate::from_num_days_from_ce_opt(r.below(3_000_000) as i32).unwrap(),
+            ),
+            35 => AttributeValue::from(TimeDelta::milliseconds(r.next() as i64 >> 2)),
+            36 => AttributeValue::NodeReference(r.node()),
+            _ => AttributeValue::NodeReferenceArray(r.vec(|r| r.node())),
+        }
+    }
+
+    const VALUE_KINDS: usize = 38;
+
+    fn random_operation(r: &mut Random, kind: usize) -> Operation {
+        match kind {
+            0 => Operation::AddNode {
+                id: r.node(),
+                node_type: r.below(1000),
+                parent: r.node(),
+                index_in_parent: r.below(1000),
+            },
+            1 => Operation::RemoveNode { id: r.node() },
+            2 => Operation::MoveNode {
+                id: r.node(),
+                new_parent: r.node(),
+                index_in_new_parent: r.below(1000),
+            },
+            3 => Operation::SetType {
+                node: r.node(),
+                type_id: r.below(1000),
+            },
+            4 => Operation::DefineTypeName {
+                id: r.below(1000),
+                name: r.string(),
+            },
+            5 => Operation::SetName {
+                node: r.node(),
+                name: r.string(),
+            },
+            6 => Operation::DefineAttributeName {
+                id: r.below(1000),
+                name: r.string(),
+            },
+            7 => Operation::DefineTagName {
+                id: r.below(1000),
+                name: r.string(),
+            },
+            8 => Operation::SetTag {
+                node: r.node(),
+                tag: r.below(1000),
+            },
+            9 => Operation::RemoveTag {
+                node: r.node(),
+                tag: r.below(1000),
+            },
+            10 => Operation::Snapshot {
+                author: r.string(),
+                message: r.string(),
+            },
+            11 => Operation::Checksum {
+                data: r.vec(|r| r.next() as u8),
+            },
+            12 => Operation::AddComment {
+                node: r.node(),
+                comment: r.string(),
+                author: r.string(),
+                response_to: r.below(1000),
+            },
+            13 => Operation::RemoveAttribute {
+                node: r.node(),
+                attribute: r.below(1000),
+            },
+            14 => Operation::EditComment {
+                node: r.node(),
+                comment: r.below(1000),
+                text: r.string(),
+            },
+            15 => Operation::RetractComment {
+                node: r.node(),
+                comment: r.below(1000),
+            },
+            16 => Operation::AddSource {
+                id: r.below(1000),
+                importer: r.string(),
+                path: r.string(),
+                hash: Hash::from(r.bytes::<32>()),
+                imported: DateTime::from_timestamp_millis(r.next() as i64 >> 20).unwrap(),
+            },
+            17 => Operation::UpdateSource {
+                id: r.below(1000),
+                hash: Hash::from(r.bytes::<32>()),
+                imported: DateTime::from_timestamp_millis(r.next() as i64 >> 20).unwrap(),
+            },
+            18 => Operation::RemoveSource { id: r.below(1000) },
+            19 => Operation::ReserveNodeIds {
+                first: r.node(),
+                count: r.below(100000),
+                client: r.string(),
+            },
+            20 => Operation::AddNodeAt {
+                id: r.node(),
+                node_type: r.below(1000),
+                parent: r.node(),
+                position: r.position(),
+            },
+            21 => Operation::MoveNodeTo {
+                id: r.node(),
+                new_parent: r.node(),
+                position: r.position(),
+            },
+            _ => Operation::SetAttribute {
+                node: r.node(),
+                attribute: r.below(1000),
+                value: random_value(r, kind - 22),
+            },
+        }
+    }
+
+    const OPERATION_KINDS: usize = 22 + VALUE_KINDS;
+
+    fn round_trip(operation: &Operation) -> Operation {
+        let mut data = vec![];
+        operation.write(&mut data).unwrap();
+        let mut r = data.as_slice();
+        let result = Operation::read(&mut r).unwrap();
+        assert!(r.is_empty(), "{} left {} bytes unread", operation, r.len());
+        result
+    }
+
+    #[test]
+    fn test_every_operation_id_is_decoded() {
+        let mut r = Random(0x2545F4914F6CDD1D);
+        for kind in 0..OPERATION_KINDS {
+            let operation = random_operation(&mut r, kind);
+            let result = round_trip(&operation);
+            assert!(
+                !matches!(result, Operation::UnknownOperation { .. }),
+                "{} was read as {}",
+                operation,
+                result
+            );
+            assert_eq!(operation.operation_id(), result.operation_id());
+        }
+    }
+
+    #[test]
+    fn test_write_read_round_trip() {
+        let mut r = Random(0x9E3779B97F4A7C15);
+        for _ in 0..5000 {
+            let kind = r.below(OPERATION_KINDS as u64);
+            let operation = random_operation(&mut r, kind);
+            assert_eq!(operation, round_trip(&operation));
+        }
+    }
+
+    #[test]
+    fn test_round_trip_sequence() {
+        let mut r = Random(12345);
+        let operations: Vec<Operation> = (0..500)
+            .map(|_| {
+                let kind = r.below(OPERATION_KINDS as u64);
+                random_operation(&mut r, kind)
+            })
+            .collect();
+
+        let mut data = vec![];
+        for operation in &operations {
+            operation.write(&mut data).unwrap();
+        }
+
+        let mut reader = data.as_slice();
+        for operation in &operations {
+            assert_eq!(*operation, Operation::read(&mut reader).unwrap());
+        }
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_operation_round_trip() {
+        let operation = Operation::UnknownOperation {
+            operation: 0x7F,
+            data: vec![1, 2, 3, 4],
+        };
+        assert_eq!(operation, round_trip(&operation));
+    }
+
+    #[test]
+    fn test_size_must_match_content() {
+        let operation = Operation::SetAttribute {
+            node: NodeId::new(1),
+            attribute: 2,
+            value: AttributeValue::Bool(true),
+        };
+        let mut data = vec![];
+        operation.write(&mut data).unwrap();
+
+        assert!(Operation::read(&mut [].as_slice())
+            .unwrap_err()
+            .is_end_of_stream());
+        let error = Operation::read(&mut &data[..data.len() - 1]).unwrap_err();
+        assert!(matches!(error, BincError::Truncated { offset: 0 }));
+
+        // Claim one byte more than the content, and provide it
+        data[1] += 1;
+        data.push(0);
+        let error = Operation::read(&mut data.as_slice()).unwrap_err();
+        assert!(matches!(error, BincError::InvalidData { offset: 0, .. }));
+    }
 }
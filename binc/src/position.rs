@@ -0,0 +1,182 @@
+use std::fmt::Display;
+
+/// Where a node goes among its siblings, for `Operation::AddNodeAt` and
+/// `Operation::MoveNodeTo`. Positions are fractions between 0 and 1, written as the bytes after
+/// the point, so they compare like byte strings. There is always room for another position
+/// between two different ones, and siblings are kept in order of position, then id, so the
+/// order doesn't depend on the order the operations were applied in.
+///
+/// The last byte is never 0, so no two byte strings mean the same fraction.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Hash)]
+pub struct Position(Vec<u8>);
+
+impl Position {
+    /// The position with these bytes, or `None` if they are empty or end with 0
+    pub fn from_bytes(bytes: Vec<u8>) -> Option<Position> {
+        match bytes.last() {
+            Some(last) if *last != 0 => Some(Position(bytes)),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// A position after `before` and before `after`, where `None` means no limit. If `after`
+    /// is not after `before`, it is ignored. At either end the result is one step from the
+    /// limit, so adding many nodes at the start or the end keeps positions short.
+    pub fn between(before: Option<&Position>, after: Option<&Position>) -> Position {
+        let after = after.filter(|after| before.is_none_or(|before| *after > before));
+        let bytes = match (before, after) {
+            (None, None) => vec![0x80],
+            (Some(before), None) => above(before.as_bytes()),
+            (None, Some(after)) => below(after.as_bytes()),
+            (Some(before), Some(after)) => midpoint(before.as_bytes(), Some(after.as_bytes())),
+        };
+        Position(bytes)
+    }
+}
+
+/// Bytes of a fraction a little larger than `a`
+fn above(a: &[u8]) -> Vec<u8> {
+    match a.iter().position(|byte| *byte < 0xFF) {
+        Some(i) => {
+            let mut result = a[..=i].to_vec();
+            result[i] += 1;
+            result
+        }
+        None => [a, &[1]].concat(),
+    }
+}
+
+/// Bytes of a fraction a little smaller than `b`, which must not end with 0
+fn below(b: &[u8]) -> Vec<u8> {
+    match b.iter().position(|byte| *byte > 1) {
+        Some(i) => {
+            let mut result = b[..=i].to_vec();
+            result[i] -= 1;
+            result
+        }
+        None => {
+            let mut result = b.to_vec();
+            *result.last_mut().expect("Positions are not empty") -= 1;
+            result.push(0xFF);
+            result
+        }
+    }
+}
+
+/// Bytes of a fraction between `a` and `b`, with `None` meaning 1. `a` must be less than `b`,
+/// and neither may end with 0.
+fn midpoint(a: &[u8], b: Option<&[u8]>) -> Vec<u8> {
+    let digit = |bytes: &[u8], i: usize| bytes.get(i).copied().unwrap_or(0);
+
+    if let Some(b) = b {
+        let common = (0..b.len()).take_while(|i| digit(a, *i) == b[*i]).count();
+        if common > 0 {
+            let rest = a.get(common..).unwrap_or(&[]);
+            let mut result = b[..common].to_vec();
+            result.extend(midpoint(rest, Some(&b[common..])));
+            return result;
+        }
+    }
+
+    let low = digit(a, 0) as u16;
+    let high = b.map_or(256, |b| b[0] as u16);
+    if high - low > 1 {
+        vec![((low + high) / 2) as u8]
+    } else if let Some(b) = b.filter(|b| b.len() > 1) {
+        // `b` cut after its first byte is still after `a`
+        vec![b[0]]
+    } else {
+        let mut result = vec![low as u8];
+        result.extend(midpoint(a.get(1..).unwrap_or(&[]), None));
+        result
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "0.")?;
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(bytes: &[u8]) -> Position {
+        Position::from_bytes(bytes.to_vec()).unwrap()
+    }
+
+    fn check_between(before: Option<&Position>, after: Option<&Position>) -> Position {
+        let result = Position::between(before, after);
+        assert_ne!(result.as_bytes().last(), Some(&0));
+        assert!(before.is_none_or(|before| *before < result), "{}", result);
+        assert!(after.is_none_or(|after| result < *after), "{}", result);
+        result
+    }
+
+    #[test]
+    fn test_invalid_positions() {
+        assert_eq!(Position::from_bytes(vec![]), None);
+        assert_eq!(Position::from_bytes(vec![1, 0]), None);
+        assert_eq!(position(&[0, 1]).to_string(), "0.0001");
+    }
+
+    #[test]
+    fn test_between() {
+        assert_eq!(check_between(None, None), position(&[0x80]));
+        assert_eq!(
+            check_between(Some(&position(&[1])), Some(&position(&[2]))),
+            position(&[1, 0x80])
+        );
+        assert_eq!(
+            check_between(Some(&position(&[1])), Some(&position(&[2, 5]))),
+            position(&[2])
+        );
+        assert_eq!(
+            check_between(None, Some(&position(&[0, 0, 1]))),
+            position(&[0, 0, 0, 0xFF])
+        );
+        assert_eq!(
+            check_between(None, Some(&position(&[0x20, 0x30]))),
+            position(&[0x1F])
+        );
+        assert_eq!(
+            check_between(Some(&position(&[0xFF])), None),
+            position(&[0xFF, 1])
+        );
+        assert_eq!(
+            check_between(Some(&position(&[0x20, 0x30])), None),
+            position(&[0x21])
+        );
+        assert_eq!(
+            Position::between(Some(&position(&[3])), Some(&position(&[3]))),
+            position(&[4])
+        );
+    }
+
+    #[test]
+    fn test_repeated_inserts_stay_short() {
+        // Always adding at the start, at the end, or right after the same node
+        let mut first = check_between(None, None);
+        let mut last = first.clone();
+        let anchor = first.clone();
+        let mut next = check_between(Some(&anchor), None);
+        for _ in 0..1000 {
+            first = check_between(None, Some(&first));
+            last = check_between(Some(&last), None);
+            next = check_between(Some(&anchor), Some(&next));
+        }
+        assert!(first.as_bytes().len() <= 6);
+        assert!(last.as_bytes().len() <= 6);
+        // Each insert in the same gap halves it, so this takes a byte per 8 inserts
+        assert!(next.as_bytes().len() <= 1000 / 8 + 2);
+    }
+}
@@ -1,5 +1,7 @@
 use crate::node_id::NodeId;
+use crate::numeric::{F16, I24, U24};
 use blake3::Hash;
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
 use std::io::{self, Error, ErrorKind, Read, Write};
 use uuid::Uuid;
 
@@ -25,6 +27,14 @@ pub trait WriteExt: Write {
         self.write_all(&value.to_be_bytes())
     }
 
+    fn write_u24(&mut self, value: U24) -> io::Result<()> {
+        self.write_all(&value.to_be_bytes())
+    }
+
+    fn write_i24(&mut self, value: I24) -> io::Result<()> {
+        self.write_all(&value.to_be_bytes())
+    }
+
     fn write_u32(&mut self, value: u32) -> io::Result<()> {
         self.write_all(&value.to_be_bytes())
     }
@@ -41,6 +51,10 @@ pub trait WriteExt: Write {
         self.write_all(&value.to_be_bytes())
     }
 
+    fn write_f16(&mut self, value: F16) -> io::Result<()> {
+        self.write_all(&value.to_be_bytes())
+    }
+
     fn write_f32(&mut self, value: f32) -> io::Result<()> {
         self.write_all(&value.to_be_bytes())
     }
@@ -49,6 +63,22 @@ pub trait WriteExt: Write {
         self.write_all(&value.to_be_bytes())
     }
 
+    /// Milliseconds since the Unix epoch, sub-millisecond precision is dropped
+    fn write_timestamp(&mut self, value: DateTime<Utc>) -> io::Result<()> {
+        self.write_i64(value.timestamp_millis())
+    }
+
+    /// Days since 1970-01-01
+    fn write_date(&mut self, value: NaiveDate) -> io::Result<()> {
+        let days = value.signed_duration_since(unix_epoch_date()).num_days();
+        self.write_i32(days as i32)
+    }
+
+    /// Whole milliseconds, sub-millisecond precision is dropped
+    fn write_duration(&mut self, value: TimeDelta) -> io::Result<()> {
+        self.write_i64(value.num_milliseconds())
+    }
+
     fn write_bool(&mut self, value: bool) -> io::Result<()> {
         self.write_byte(if value { 1 } else { 0 })
     }
@@ -179,6 +209,14 @@ pub trait WriteExt: Write {
         self.write_length(id.id)
     }
 
+    fn write_id_array(&mut self, value: &Vec<NodeId>) -> io::Result<()> {
+        self.write_length(value.len())?;
+        for id in value {
+            self.write_id(id)?;
+        }
+        Ok(())
+    }
+
     fn write_uuid(&mut self, value: &Uuid) -> io::Result<()> {
         self.write_all(value.as_bytes())
     }
@@ -199,6 +237,32 @@ pub trait WriteExt: Write {
         Ok(())
     }
 
+    /// Write a length-prefixed array of fixed size values as one contiguous block of bytes.
+    fn write_packed<V, const N: usize>(
+        &mut self,
+        values: &[V],
+        to_bytes: impl Fn(&V) -> [u8; N],
+    ) -> io::Result<()> {
+        self.write_length(values.len())?;
+        let mut data = Vec::with_capacity(values.len() * N);
+        for value in values {
+            data.extend_from_slice(&to_bytes(value));
+        }
+        self.write_all(&data)
+    }
+
+    /// Write a length-prefixed array of booleans, packed eight to a byte.
+    fn write_packed_bools(&mut self, values: &[bool]) -> io::Result<()> {
+        self.write_length(values.len())?;
+        let mut data = vec![0u8; values.len().div_ceil(8)];
+        for (i, value) in values.iter().enumerate() {
+            if *value {
+                data[i / 8] |= 1 << (i % 8);
+            }
+        }
+        self.write_all(&data)
+    }
+
     fn write_hash(&mut self, value: &Hash) -> io::Result<()> {
         self.write_all(value.as_bytes())?;
         Ok(())
@@ -245,6 +309,18 @@ pub trait ReadExt: Read {
         Ok(i16::from_be_bytes(buf))
     }
 
+    fn read_u24(&mut self) -> io::Result<U24> {
+        let mut buf = [0; 3];
+        self.read_exact(&mut buf)?;
+        Ok(U24::from_be_bytes(buf))
+    }
+
+    fn read_i24(&mut self) -> io::Result<I24> {
+        let mut buf = [0; 3];
+        self.read_exact(&mut buf)?;
+        Ok(I24::from_be_bytes(buf))
+    }
+
     fn read_u32(&mut self) -> io::Result<u32> {
         let mut buf = [0; 4];
         self.read_exact(&mut buf)?;
@@ -269,6 +345,12 @@ pub trait ReadExt: Read {
         Ok(i64::from_be_bytes(buf))
     }
 
+    fn read_f16(&mut self) -> io::Result<F16> {
+        let mut buf = [0; 2];
+        self.read_exact(&mut buf)?;
+        Ok(F16::from_be_bytes(buf))
+    }
+
     fn read_f32(&mut self) -> io::Result<f32> {
         let mut buf = [0; 4];
         self.read_exact(&mut buf)?;
@@ -281,6 +363,25 @@ pub trait ReadExt: Read {
         Ok(f64::from_be_bytes(buf))
     }
 
+    fn read_timestamp(&mut self) -> io::Result<DateTime<Utc>> {
+        let millis = self.read_i64()?;
+        DateTime::from_timestamp_millis(millis)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid timestamp"))
+    }
+
+    fn read_date(&mut self) -> io::Result<NaiveDate> {
+        let days = self.read_i32()?;
+        unix_epoch_date()
+            .checked_add_signed(TimeDelta::days(days as i64))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid date"))
+    }
+
+    fn read_duration(&mut self) -> io::Result<TimeDelta> {
+        let millis = self.read_i64()?;
+        TimeDelta::try_milliseconds(millis)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid duration"))
+    }
+
     fn read_bool(&mut self) -> io::Result<bool> {
         let byte = self.read_byte()?;
         match byte {
@@ -440,10 +541,12 @@ pub trait ReadExt: Read {
         }
     }
 
-    fn read_string(&mut self) -> io::Result<String> {
-        let length = self.read_length()? as usize;
-        let mut buf = vec![0; length];
-        self.read_exact(&mut buf)?;
+    fn read_string(&mut self) -> io::Result<String>
+    where
+        Self: Sized,
+    {
+        let length = self.read_length()?;
+        let buf = self.read_exact_vec(length)?;
         String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
@@ -451,6 +554,16 @@ pub trait ReadExt: Read {
         self.read_length().map(|id| NodeId { id: id as usize })
     }
 
+    fn read_id_array(&mut self) -> io::Result<Vec<NodeId>> {
+        let length = self.read_length()?;
+        // Every id takes at least one byte, so don't trust the length for the allocation
+        let mut ids = Vec::with_capacity(length.min(4096));
+        for _ in 0..length {
+            ids.push(self.read_id()?);
+        }
+        Ok(ids)
+    }
+
     fn read_uuid(&mut self) -> io::Result<Uuid> {
         let mut buf = [0; 16];
         self.read_exact(&mut buf)?;
@@ -458,40 +571,117 @@ pub trait ReadExt: Read {
     }
 
     fn read_uuid_array(&mut self) -> io::Result<Vec<Uuid>> {
-        let length = self.read_length()? as usize;
-        let mut uuids = Vec::with_capacity(length);
+        let length = self.read_length()?;
+        let mut uuids = Vec::with_capacity(length.min(4096));
         for _ in 0..length {
             uuids.push(self.read_uuid()?);
         }
         Ok(uuids)
     }
 
-    fn read_string_array(&mut self) -> io::Result<Vec<String>> {
-        let length = self.read_length()? as usize;
-        let mut strings = Vec::with_capacity(length);
+    fn read_string_array(&mut self) -> io::Result<Vec<String>>
+    where
+        Self: Sized,
+    {
+        let length = self.read_length()?;
+        let mut strings = Vec::with_capacity(length.min(4096));
         for _ in 0..length {
             strings.push(self.read_string()?);
         }
         Ok(strings)
     }
 
+    /// Read exactly `length` bytes. The length is not trusted for the allocation, as it might
+    /// come from a corrupt file.
+    fn read_exact_vec(&mut self, length: usize) -> io::Result<Vec<u8>>
+    where
+        Self: Sized,
+    {
+        let mut buf = Vec::new();
+        self.take(length as u64).read_to_end(&mut buf)?;
+        if buf.len() != length {
+            return Err(Error::from(ErrorKind::UnexpectedEof));
+        }
+        Ok(buf)
+    }
+
+    /// Read a length-prefixed array of fixed size values written by `write_packed`.
+    fn read_packed<V, const N: usize>(
+        &mut self,
+        from_bytes: impl Fn([u8; N]) -> V,
+    ) -> io::Result<Vec<V>>
+    where
+        Self: Sized,
+    {
+        let length = self.read_length()?;
+        let size = length
+            .checked_mul(N)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Array too large"))?;
+        let data = self.read_exact_vec(size)?;
+        Ok(data
+            .chunks_exact(N)
+            .map(|chunk| from_bytes(chunk.try_into().expect("Chunk has the right size")))
+            .collect())
+    }
+
+    /// Read a length-prefixed array of booleans written by `write_packed_bools`.
+    fn read_packed_bools(&mut self) -> io::Result<Vec<bool>>
+    where
+        Self: Sized,
+    {
+        let length = self.read_length()?;
+        let data = self.read_exact_vec(length.div_ceil(8))?;
+        Ok((0..length)
+            .map(|i| data[i / 8] & (1 << (i % 8)) != 0)
+            .collect())
+    }
+
     fn read_hash(&mut self) -> io::Result<Hash> {
         let mut buf = [0; 32];
         self.read_exact(&mut buf)?;
         Ok(Hash::from(buf))
     }
 
-    fn read_bytes(&mut self) -> io::Result<Vec<u8>> {
-        let length = self.read_length()? as usize;
-        let mut buf = vec![0; length];
-        self.read_exact(&mut buf)?;
-        Ok(buf)
+    fn read_bytes(&mut self) -> io::Result<Vec<u8>>
+    where
+        Self: Sized,
+    {
+        let length = self.read_length()?;
+        self.read_exact_vec(length)
     }
 }
 
 /// Implement `ReadExt` for all types that implement `Read`.
 impl<T: Read> ReadExt for T {}
 
+fn unix_epoch_date() -> NaiveDate {
+    DateTime::UNIX_EPOCH.date_naive()
+}
+
+/// Reader that counts the bytes read through it, to report where in a stream something went wrong
+pub(crate) struct PositionReader<'a, T: Read> {
+    inner: &'a mut T,
+    position: u64,
+}
+
+impl<'a, T: Read> PositionReader<'a, T> {
+    pub(crate) fn new(inner: &'a mut T) -> Self {
+        PositionReader { inner, position: 0 }
+    }
+
+    pub(crate) fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+impl<T: Read> Read for PositionReader<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -547,6 +737,54 @@ mod tests {
         assert_eq!(cursor.read_uuid().unwrap(), id);
     }
 
+    #[test]
+    fn test_small_numbers() {
+        let mut buffer = Vec::new();
+        buffer.write_u24(U24::MAX).unwrap();
+        buffer.write_i24(I24::MIN).unwrap();
+        buffer.write_f16(F16::ONE).unwrap();
+        assert_eq!(buffer.len(), 8);
+
+        let mut cursor = &buffer[..];
+        assert_eq!(cursor.read_u24().unwrap(), U24::MAX);
+        assert_eq!(cursor.read_i24().unwrap(), I24::MIN);
+        assert_eq!(cursor.read_f16().unwrap(), F16::ONE);
+    }
+
+    #[test]
+    fn test_packed_bools() {
+        for length in [0, 1, 7, 8, 9, 100] {
+            let values: Vec<bool> = (0..length).map(|i| i % 3 == 0).collect();
+            let mut w: Vec<u8> = Vec::new();
+            w.write_packed_bools(&values).unwrap();
+            assert_eq!(w.len(), 1 + values.len().div_ceil(8));
+
+            let mut r = w.as_slice();
+            assert_eq!(values, r.read_packed_bools().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_packed_numbers() {
+        let values: Vec<i32> = (0..1000).map(|i| i * 7919 - 300000).collect();
+        let mut w: Vec<u8> = Vec::new();
+        w.write_packed(&values, |v| v.to_be_bytes()).unwrap();
+        assert_eq!(w.len(), 2 + values.len() * 4);
+
+        let mut r = w.as_slice();
+        assert_eq!(values, r.read_packed(i32::from_be_bytes).unwrap());
+    }
+
+    #[test]
+    fn test_packed_truncated() {
+        let mut w: Vec<u8> = Vec::new();
+        w.write_packed(&[1u64, 2, 3], |v| v.to_be_bytes()).unwrap();
+        w.pop();
+
+        let mut r = w.as_slice();
+        assert!(r.read_packed(u64::from_be_bytes).is_err());
+    }
+
     #[test]
     fn test_length() {
         let values = [
@@ -629,6 +867,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_huge_lengths() {
+        // Only the length is there, so reading fails instead of allocating for all of it
+        let mut w: Vec<u8> = Vec::new();
+        w.write_length(1 << 62).unwrap();
+
+        assert!(w.as_slice().read_string().is_err());
+        assert!(w.as_slice().read_string_array().is_err());
+        assert!(w.as_slice().read_uuid_array().is_err());
+        assert!(w.as_slice().read_id_array().is_err());
+    }
+
     #[test]
     fn test_u8() {
         let values: [u8; 11] = [0, 1, 2, 5, 88, 109, 127, 128, 129, 223, 255];
@@ -0,0 +1,143 @@
+use blake3::Hash;
+use chrono::{DateTime, SubsecRound, Utc};
+
+/// Where some of the data in a document came from, like a file that was imported.
+/// The hash of the original data makes it possible to tell if the file changed since.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    /// Name of the importer that read the data
+    pub importer: String,
+    /// Path of the original file
+    pub path: String,
+    /// blake3 hash of the original data
+    pub hash: Hash,
+    /// When the data was last imported
+    pub imported: DateTime<Utc>,
+}
+
+impl Source {
+    pub fn hash_data(data: &[u8]) -> Hash {
+        blake3::hash(data)
+    }
+
+    /// Time to record for an import happening now, with the precision it is stored with
+    pub fn now() -> DateTime<Utc> {
+        Utc::now().trunc_subsecs(3)
+    }
+
+    /// True if `data` is the same as what was imported
+    pub fn matches(&self, data: &[u8]) -> bool {
+        self.hash == Self::hash_data(data)
+    }
+}
+
+/// What happened when registering a source with `Document::register_source`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceStatus {
+    /// The source was not known before
+    Added(usize),
+    /// The source was imported before, but the data has changed since
+    Updated(usize),
+    /// The source was imported before with the same data
+    Unchanged(usize),
+}
+
+/// All sources of a document, by id. Ids of removed sources are not reused.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sources {
+    sources: Vec<Option<Source>>,
+}
+
+impl Sources {
+    pub fn get(&self, id: usize) -> Option<&Source> {
+        self.sources.get(id).and_then(|x| x.as_ref())
+    }
+
+    /// Id of the source imported from `path` by `importer`
+    pub fn find(&self, importer: &str, path: &str) -> Option<usize> {
+        self.iter()
+            .find(|(_, s)| s.importer == importer && s.path == path)
+            .map(|(id, _)| id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Source)> {
+        self.sources
+            .iter()
+            .enumerate()
+            .filter_map(|(id, s)| s.as_ref().map(|s| (id, s)))
+    }
+
+    pub fn next_id(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn add(&mut self, id: usize, source: Source) {
+        if id >= self.sources.len() {
+            self.sources.resize(id + 1, None);
+        }
+        self.sources[id] = Some(source);
+    }
+
+    /// Returns false if there is no source with that id
+    pub(crate) fn update(&mut self, id: usize, hash: Hash, imported: DateTime<Utc>) -> bool {
+        let Some(source) = self.sources.get_mut(id).and_then(|x| x.as_mut()) else {
+            return false;
+        };
+        source.hash = hash;
+        source.imported = imported;
+        true
+    }
+
+    /// Undo a change to a source, given the source it replaced and `next_id` before it
+    pub(crate) fn restore(&mut self, id: usize, source: Option<Source>, next_id: usize) {
+        if let Some(slot) = self.sources.get_mut(id) {
+            *slot = source;
+        }
+        self.sources.truncate(next_id);
+    }
+
+    pub(crate) fn remove(&mut self, id: usize) {
+        if let Some(source) = self.sources.get_mut(id) {
+            *source = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(path: &str, data: &[u8]) -> Source {
+        Source {
+            importer: "XML".to_string(),
+            path: path.to_string(),
+            hash: Source::hash_data(data),
+            imported: Source::now(),
+        }
+    }
+
+    #[test]
+    fn test_add_update_remove() {
+        let mut sources = Sources::default();
+        sources.add(0, source("a.xml", b"<a/>"));
+        sources.add(1, source("b.xml", b"<b/>"));
+        assert_eq!(sources.find("XML", "b.xml"), Some(1));
+        assert!(sources.get(0).unwrap().matches(b"<a/>"));
+
+        assert!(sources.update(0, Source::hash_data(b"<a></a>"), Source::now()));
+        assert!(!sources.get(0).unwrap().matches(b"<a/>"));
+
+        sources.remove(0);
+        assert_eq!(sources.find("XML", "a.xml"), None);
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources.next_id(), 2);
+    }
+}
@@ -0,0 +1,451 @@
+use crate::attributes::AttributeValue;
+use crate::comments::Comment;
+use crate::error::InvalidOperation;
+use crate::node_id::NodeId;
+use crate::node_store::{Node, NodeStore};
+use crate::operation::Operation;
+use crate::position::Position;
+use crate::sources::Source;
+use crate::validation::NameKind;
+
+/// What an operation changed, recorded when it is applied, so it can be undone without
+/// replaying the journal. Only the parts the operation touched are kept.
+#[derive(Clone)]
+pub(crate) enum Undo {
+    /// The operation changed nothing, like a snapshot or a rejected operation
+    Nothing,
+    NodeAdded {
+        id: NodeId,
+        /// Number of node slots before the node was added
+        len: usize,
+    },
+    NodeMoved {
+        id: NodeId,
+        parent: NodeId,
+        index: usize,
+        position: Option<Position>,
+    },
+    /// The removed node and everything under it, parents first
+    NodeRemoved {
+        nodes: Vec<Node>,
+        parent: NodeId,
+        index: usize,
+    },
+    Type {
+        node: NodeId,
+        type_id: Option<usize>,
+    },
+    Name {
+        node: NodeId,
+        name: Option<String>,
+    },
+    Attribute {
+        node: NodeId,
+        attribute: usize,
+        /// The value before, with its position among the node's attributes
+        value: Option<(usize, AttributeValue)>,
+    },
+    Tags {
+        node: NodeId,
+        tags: Vec<usize>,
+    },
+    CommentAdded {
+        node: NodeId,
+    },
+    CommentChanged {
+        node: NodeId,
+        comment: Comment,
+    },
+    NameDefined {
+        kind: NameKind,
+        id: usize,
+        name: Option<String>,
+        len: usize,
+    },
+    Source {
+        id: usize,
+        source: Option<Source>,
+        next_id: usize,
+    },
+}
+
+/// Apply the operation, and return what it takes to undo it. A rejected operation leaves the
+/// nodes unchanged, the same as with `Operation::apply`.
+pub(crate) fn apply(
+    operation: &Operation,
+    nodes: &mut NodeStore,
+) -> Result<Undo, InvalidOperation> {
+    let undo = Undo::record(operation, nodes);
+    operation.apply(nodes)?;
+    Ok(undo)
+}
+
+impl Undo {
+    /// What `operation` will change in `nodes`. This is only meaningful if it is then applied.
+    fn record(operation: &Operation, nodes: &NodeStore) -> Undo {
+        let node = |id: NodeId| nodes.resolve(id);
+        match operation {
+            Operation::AddNode { id, .. } | Operation::AddNodeAt { id, .. } => Undo::NodeAdded {
+                id: *id,
+                len: nodes.len(),
+            },
+            Operation::MoveNode { id, .. }
+            | Operation::MoveNodeTo { id, .. }
+            | Operation::RemoveNode { id } => {
+                let Some(moved) = node(*id) else {
+                    return Undo::Nothing;
+                };
+                let parent = moved.parent;
+                let Some(index) = node(parent).and_then(|p| p.get_child_index(*id)) else {
+                    return Undo::Nothing;
+                };
+                if let Operation::RemoveNode { .. } = operation {
+                    Undo::NodeRemoved {
+                        nodes: nodes.subtree(*id),
+                        parent,
+                        index,
+                    }
+                } else {
+                    Undo::NodeMoved {
+                        id: *id,
+                        parent,
+                        index,
+                        position: moved.position.clone(),
+                    }
+                }
+            }
+            Operation::SetType { node: id, .. } => match node(*id) {
+                Some(n) => Undo::Type {
+                    node: *id,
+                    type_id: n.type_id,
+                },
+                None => Undo::Nothing,
+            },
+            Operation::SetName { node: id, .. } => match node(*id) {
+                Some(n) => Undo::Name {
+                    node: *id,
+                    name: n.name.clone(),
+                },
+                None => Undo::Nothing,
+            },
+            Operation::SetAttribute {
+                node: id,
+                attribute,
+                ..
+            }
+            | Operation::RemoveAttribute {
+                node: id,
+                attribute,
+            } => match node(*id) {
+                Some(n) => Undo::Attribute {
+                    node: *id,
+                    attribute: *attribute,
+                    value: n.attributes.position(*attribute).and_then(|position| {
+                        n.get_attribute(*attribute)
+                            .map(|value| (position, value.clone()))
+                    }),
+                },
+                None => Undo::Nothing,
+            },
+            Operation::SetTag { node: id, .. } | Operation::RemoveTag { node: id, .. } => {
+                match node(*id) {
+                    Some(n) => Undo::Tags {
+                        node: *id,
+                        tags: n.tags.clone(),
+                    },
+                    None => Undo::Nothing,
+                }
+            }
+            Operation::AddComment { node: id, .. } => Undo::CommentAdded { node: *id },
+            Operation::EditComment {
+                node: id, comment, ..
+            }
+            | Operation::RetractComment { node: id, comment } => {
+                match node(*id).and_then(|n| n.comments.get(*comment)) {
+                    Some(c) => Undo::CommentChanged {
+                        node: *id,
+                        comment: c.clone(),
+                    },
+                    None => Undo::Nothing,
+                }
+            }
+            Operation::DefineTypeName { id, .. } => Self::name(nodes, NameKind::Type, *id),
+            Operation::DefineAttributeName { id, .. } => {
+                Self::name(nodes, NameKind::Attribute, *id)
+            }
+            Operation::DefineTagName { id, .. } => Self::name(nodes, NameKind::Tag, *id),
+            Operation::AddSource { id, .. }
+            | Operation::UpdateSource { id, .. }
+            | Operation::RemoveSource { id } => Undo::Source {
+                id: *id,
+                source: nodes.sources.get(*id).cloned(),
+                next_id: nodes.sources.next_id(),
+            },
+            Operation::Snapshot { .. }
+            | Operation::Checksum { .. }
+            | Operation::ReserveNodeIds { .. }
+            | Operation::UnknownOperation { .. } => Undo::Nothing,
+        }
+    }
+
+    fn name(nodes: &NodeStore, kind: NameKind, id: usize) -> Undo {
+        let names = match kind {
+            NameKind::Type => &nodes.type_names,
+            NameKind::Attribute => &nodes.attribute_names,
+            NameKind::Tag => &nodes.tag_names,
+        };
+        Undo::NameDefined {
+            kind,
+            id,
+            name: names.get(id).map(|name| name.to_string()),
+            len: names.len(),
+        }
+    }
+
+    /// Put `nodes` back the way they were before the operation. Operations applied after it
+    /// must have been undone first.
+    pub(crate) fn revert(self, nodes: &mut NodeStore) {
+        match self {
+            Undo::Nothing => {}
+            Undo::NodeAdded { id, len } => nodes.remove_added(id, len),
+            Undo::NodeMoved {
+                id,
+                parent,
+                index,
+                position,
+            } => nodes.place(id, parent, index, position),
+            Undo::NodeRemoved {
+                nodes: removed,
+                parent,
+                index,
+            } => nodes.restore_subtree(removed, parent, index),
+            Undo::Type { node, type_id } => {
+                if let Some(n) = nodes.get_mut(node) {
+                    n.type_id = type_id;
+                }
+            }
+            Undo::Name { node, name } => {
+                if let Some(n) = nodes.get_mut(node) {
+                    n.name = name;
+                }
+            }
+            Undo::Attribute {
+                node,
+                attribute,
+                value,
+            } => {
+                if let Some(n) = nodes.get_mut(node) {
+                    n.remove_attribute(attribute);
+                    if let Some((position, value)) = value {
+                        n.attributes.insert_at(position, attribute, value);
+                    }
+                }
+            }
+            Undo::Tags { node, tags } => {
+                if let Some(n) = nodes.get_mut(node) {
+                    n.tags = tags;
+                }
+            }
+            Undo::CommentAdded { node } => {
+                if let Some(n) = nodes.get_mut(node) {
+                    n.comments.remove_last();
+                }
+            }
+            Undo::CommentChanged { node, comment } => {
+                if let Some(n) = nodes.get_mut(node) {
+                    n.comments.restore(comment);
+                }
+            }
+            Undo::NameDefined {
+                kind,
+                id,
+                name,
+                len,
+            } => {
+                let names = match kind {
+                    NameKind::Type => &mut nodes.type_names,
+                    NameKind::Attribute => &mut nodes.attribute_names,
+                    NameKind::Tag => &mut nodes.tag_names,
+                };
+                names.restore(id, name, len);
+            }
+            Undo::Source {
+                id,
+                source,
+                next_id,
+            } => nodes.sources.restore(id, source, next_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comments::Comments;
+    use crate::compaction::same_nodes;
+    use crate::sources::Source;
+
+    /// Apply every operation, then undo them one at a time, checking each step against a replay
+    fn check_undo(operations: &[Operation]) {
+        let mut nodes = NodeStore::new();
+        let mut states = vec![nodes.clone()];
+        let mut undos = vec![];
+        for operation in operations {
+            undos.push(apply(operation, &mut nodes).unwrap_or(Undo::Nothing));
+            states.push(nodes.clone());
+        }
+        states.pop();
+
+        while let Some(undo) = undos.pop() {
+            undo.revert(&mut nodes);
+            let expected = states.pop().unwrap();
+            assert!(
+                same_nodes(&nodes, &expected),
+                "Undoing {} didn't restore the nodes",
+                operations[undos.len()]
+            );
+            assert_eq!(nodes.len(), expected.len());
+        }
+    }
+
+    #[test]
+    fn test_undo_every_operation() {
+        let a = NodeId::new(1);
+        let b = NodeId::new(2);
+        let c = NodeId::new(5);
+        let d = NodeId::new(6);
+        let middle = Position::between(None, None);
+        let add = |id, parent, index| Operation::AddNode {
+            id,
+            node_type: 0,
+            parent,
+            index_in_parent: index,
+        };
+        let source = |id, path: &str| Operation::AddSource {
+            id,
+            importer: "csv".to_string(),
+            path: path.to_string(),
+            hash: Source::hash_data(path.as_bytes()),
+            imported: Source::now(),
+        };
+        check_undo(&[
+            add(a, NodeId::ROOT_NODE, 0),
+            add(b, NodeId::ROOT_NODE, 1),
+            add(c, a, 0),
+            Operation::DefineTypeName {
+                id: 1,
+                name: "task".to_string(),
+            },
+            Operation::SetType {
+                node: a,
+                type_id: 1,
+            },
+            Operation::SetName {
+                node: a,
+                name: "first".to_string(),
+            },
+            Operation::SetName {
+                node: a,
+                name: "second".to_string(),
+            },
+            Operation::DefineAttributeName {
+                id: 0,
+                name: "done".to_string(),
+            },
+            Operation::DefineAttributeName {
+                id: 0,
+                name: "finished".to_string(),
+            },
+            Operation::SetAttribute {
+                node: a,
+                attribute: 0,
+                value: AttributeValue::Bool(false),
+            },
+            Operation::SetAttribute {
+                node: a,
+                attribute: 1,
+                value: AttributeValue::U32(3),
+            },
+            Operation::RemoveAttribute {
+                node: a,
+                attribute: 0,
+            },
+            Operation::SetAttribute {
+                node: a,
+                attribute: 1,
+                value: AttributeValue::U32(4),
+            },
+            Operation::DefineTagName {
+                id: 0,
+                name: "urgent".to_string(),
+            },
+            Operation::SetTag { node: b, tag: 0 },
+            Operation::RemoveTag { node: b, tag: 0 },
+            Operation::AddComment {
+                node: c,
+                comment: "Hi".to_string(),
+                author: "ann".to_string(),
+                response_to: Comments::NO_COMMENT,
+            },
+            Operation::EditComment {
+                node: c,
+                comment: 1,
+                text: "Hello".to_string(),
+            },
+            Operation::RetractComment {
+                node: c,
+                comment: 1,
+            },
+            Operation::MoveNode {
+                id: b,
+                new_parent: a,
+                index_in_new_parent: 0,
+            },
+            Operation::MoveNode {
+                id: c,
+                new_parent: a,
+                index_in_new_parent: 0,
+            },
+            Operation::MoveNode {
+                id: a,
+                new_parent: c,
+                index_in_new_parent: 0,
+            },
+            Operation::AddNodeAt {
+                id: d,
+                node_type: 0,
+                parent: NodeId::ROOT_NODE,
+                position: middle.clone(),
+            },
+            Operation::MoveNodeTo {
+                id: b,
+                new_parent: NodeId::ROOT_NODE,
+                position: Position::between(None, Some(&middle)),
+            },
+            Operation::MoveNodeTo {
+                id: b,
+                new_parent: NodeId::ROOT_NODE,
+                position: Position::between(Some(&middle), None),
+            },
+            Operation::MoveNode {
+                id: b,
+                new_parent: a,
+                index_in_new_parent: 0,
+            },
+            source(0, "a.csv"),
+            source(3, "b.csv"),
+            Operation::UpdateSource {
+                id: 0,
+                hash: Source::hash_data(b"new"),
+                imported: Source::now(),
+            },
+            Operation::RemoveSource { id: 3 },
+            Operation::Snapshot {
+                author: "ann".to_string(),
+                message: "Done".to_string(),
+            },
+            Operation::RemoveNode { id: a },
+            add(a, NodeId::ROOT_NODE, 0),
+        ]);
+    }
+}
@@ -0,0 +1,266 @@
+use crate::error::InvalidOperation;
+use crate::journal::Journal;
+use crate::name_dictionary::NameDictionary;
+use crate::node_store::NodeStore;
+use crate::operation::Operation;
+use std::fmt::{Display, Formatter};
+
+/// Something wrong with one operation in a journal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// Index of the operation in the journal
+    pub index: usize,
+    pub kind: ProblemKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProblemKind {
+    /// The operation doesn't fit the nodes, and is skipped when the journal is replayed
+    Rejected(InvalidOperation),
+    /// A type, attribute or tag id is used before it is defined
+    UndefinedName { kind: NameKind, id: usize },
+    /// An id is defined again, or a name is defined under a second id
+    DuplicateDefinition {
+        kind: NameKind,
+        id: usize,
+        name: String,
+    },
+    /// The operation uses a feature that is not in version 1 of the spec
+    NotInV1 { feature: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NameKind {
+    Type,
+    Attribute,
+    Tag,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Operation {}: {}", self.index, self.kind)
+    }
+}
+
+impl Display for ProblemKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProblemKind::Rejected(reason) => write!(f, "{}", reason),
+            ProblemKind::UndefinedName { kind, id } => {
+                write!(f, "{} #{} is used before it is defined", kind, id)
+            }
+            ProblemKind::DuplicateDefinition { kind, id, name } => {
+                write!(f, "{} #{} \"{}\" is defined twice", kind, id, name)
+            }
+            ProblemKind::NotInV1 { feature } => write!(f, "Uses {}, not in version 1", feature),
+        }
+    }
+}
+
+impl Display for NameKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NameKind::Type => write!(f, "Type"),
+            NameKind::Attribute => write!(f, "Attribute"),
+            NameKind::Tag => write!(f, "Tag"),
+        }
+    }
+}
+
+/// Replay a journal from the start and report every problem, with the index of the operation.
+/// An empty list means the journal is consistent. Operations with problems that make them
+/// impossible to apply are skipped, the same way as when a document is opened.
+pub fn check_journal(journal: &Journal) -> Vec<Problem> {
+    let mut nodes = NodeStore::new();
+    let mut problems = vec![];
+
+    for (index, operation) in journal.operations.iter().enumerate() {
+        let mut report = |kind| problems.push(Problem { index, kind });
+
+        for (kind, id) in names_used(operation) {
+            if dictionary(&nodes, kind).get(id).is_none() {
+                report(ProblemKind::UndefinedName { kind, id });
+            }
+        }
+        if let Some((kind, id, name)) = name_defined(operation) {
+            let names = dictionary(&nodes, kind);
+            let id_taken = names.get(id).is_some();
+            let name_taken = names.get_index(name).is_some_and(|other| other != id);
+            if id_taken || name_taken {
+                report(ProblemKind::DuplicateDefinition {
+                    kind,
+                    id,
+                    name: name.to_string(),
+                });
+            }
+        }
+
+        if let Err(reason) = operation.apply(&mut nodes) {
+            report(ProblemKind::Rejected(reason));
+        }
+    }
+    problems
+}
+
+pub(crate) fn dictionary(nodes: &NodeStore, kind: NameKind) -> &NameDictionary {
+    match kind {
+        NameKind::Type => &nodes.type_names,
+        NameKind::Attribute => &nodes.attribute_names,
+        NameKind::Tag => &nodes.tag_names,
+    }
+}
+
+/// Names an operation refers to. Type 0 means no type, so it doesn't need a definition.
+fn names_used(operation: &Operation) -> Vec<(NameKind, usize)> {
+    match operation {
+        Operation::AddNode { node_type, .. } | Operation::AddNodeAt { node_type, .. }
+            if *node_type != 0 =>
+        {
+            vec![(NameKind::Type, *node_type)]
+        }
+        Operation::SetType { type_id, .. } if *type_id != 0 => vec![(NameKind::Type, *type_id)],
+        Operation::SetAttribute { attribute, .. }
+        | Operation::RemoveAttribute { attribute, .. } => {
+            vec![(NameKind::Attribute, *attribute)]
+        }
+        Operation::SetTag { tag, .. } | Operation::RemoveTag { tag, .. } => {
+            vec![(NameKind::Tag, *tag)]
+        }
+        _ => vec![],
+    }
+}
+
+fn name_defined(operation: &Operation) -> Option<(NameKind, usize, &str)> {
+    match operation {
+        Operation::DefineTypeName { id, name } => Some((NameKind::Type, *id, name)),
+        Operation::DefineAttributeName { id, name } => Some((NameKind::Attribute, *id, name)),
+        Operation::DefineTagName { id, name } => Some((NameKind::Tag, *id, name)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes::AttributeValue;
+    use crate::changes::Changes;
+    use crate::node_id::NodeId;
+
+    #[test]
+    fn test_consistent_journal() {
+        let mut changes = Changes::new();
+        changes
+            .add_node(NodeId::new(1), NodeId::ROOT_NODE, 0)
+            .add_node(NodeId::new(2), NodeId::new(1), 0)
+            .move_node(NodeId::new(2), NodeId::ROOT_NODE, 1)
+            .remove_node(NodeId::new(1));
+        assert_eq!(check_journal(&Journal::from(changes)), vec![]);
+    }
+
+    #[test]
+    fn test_every_problem_is_reported() {
+        let a = NodeId::new(1);
+        let b = NodeId::new(2);
+        let mut journal = Journal::new();
+        let operations = vec![
+            Operation::AddNode {
+                id: a,
+                node_type: 0,
+                parent: NodeId::ROOT_NODE,
+                index_in_parent: 0,
+            },
+            Operation::AddNode {
+                id: b,
+                node_type: 3,
+                parent: a,
+                index_in_parent: 1,
+            },
+            Operation::AddNode {
+                id: b,
+                node_type: 0,
+                parent: a,
+                index_in_parent: 0,
+            },
+            Operation::AddNode {
+                id: b,
+                node_type: 0,
+                parent: a,
+                index_in_parent: 0,
+            },
+            Operation::MoveNode {
+                id: a,
+                new_parent: b,
+                index_in_new_parent: 0,
+            },
+            Operation::DefineAttributeName {
+                id: 0,
+                name: "status".to_string(),
+            },
+            Operation::DefineAttributeName {
+                id: 1,
+                name: "status".to_string(),
+            },
+            Operation::SetAttribute {
+                node: b,
+                attribute: 2,
+                value: AttributeValue::Bool(true),
+            },
+            Operation::RemoveNode { id: a },
+            Operation::SetName {
+                node: b,
+                name: "gone".to_string(),
+            },
+        ];
+        for operation in operations {
+            journal.add_operation(operation);
+        }
+
+        let problems = check_journal(&journal);
+        let found: Vec<(usize, ProblemKind)> =
+            problems.into_iter().map(|p| (p.index, p.kind)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    1,
+                    ProblemKind::UndefinedName {
+                        kind: NameKind::Type,
+                        id: 3
+                    }
+                ),
+                (
+                    1,
+                    ProblemKind::Rejected(InvalidOperation::IndexOutOfRange {
+                        parent: a,
+                        index: 1,
+                        len: 0
+                    })
+                ),
+                (3, ProblemKind::Rejected(InvalidOperation::NodeExists(b))),
+                (
+                    4,
+                    ProblemKind::Rejected(InvalidOperation::MoveIntoItself {
+                        node: a,
+                        new_parent: b
+                    })
+                ),
+                (
+                    6,
+                    ProblemKind::DuplicateDefinition {
+                        kind: NameKind::Attribute,
+                        id: 1,
+                        name: "status".to_string()
+                    }
+                ),
+                (
+                    7,
+                    ProblemKind::UndefinedName {
+                        kind: NameKind::Attribute,
+                        id: 2
+                    }
+                ),
+                (9, ProblemKind::Rejected(InvalidOperation::NodeNotFound(b))),
+            ]
+        );
+    }
+}
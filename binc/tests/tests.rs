@@ -3,12 +3,18 @@ mod tests {
     use std::fs;
     use std::fs::File;
     use std::io::{Cursor, Read, Write};
+    use std::time::Duration;
 
     use binc::journal::*;
 
+    use binc::attributes::AttributeValue;
+    use binc::builder::NodeBuilder;
     use binc::changes::Changes;
+    use binc::compaction::same_nodes;
     use binc::document::*;
+    use binc::error::{BincError, InvalidOperation};
     use binc::node_id::{NodeId, NodeIdGenerator};
+    use binc::operation::Operation;
 
     #[test]
     fn test_create_example_document() {
@@ -54,6 +60,522 @@ mod tests {
         assert_eq!(doc.find_roots().len(), 1)
     }
 
+    #[test]
+    fn tags_survive_save_and_load() {
+        let mut doc = Document::default();
+        let a = doc.add_node("task", NodeId::ROOT_NODE);
+        doc.set_node_tag(a, "urgent");
+        doc.set_node_tag(a, "bug");
+
+        let mut buf = Vec::<u8>::new();
+        doc.write(&mut buf).unwrap();
+        let doc2 = Document::read(&mut Cursor::new(buf)).unwrap();
+
+        assert_eq!(doc.num_operations(), doc2.num_operations());
+        let tags: Vec<String> = doc2
+            .nodes
+            .get(a)
+            .unwrap()
+            .tags
+            .iter()
+            .map(|t| doc2.tag_name(*t))
+            .collect();
+        assert_eq!(tags, vec!["urgent", "bug"]);
+    }
+
+    #[test]
+    fn invalid_operations_leave_document_unchanged() {
+        let mut doc = Document::default();
+        let a = doc.add_node("task", NodeId::ROOT_NODE);
+        let missing = NodeId::new(99);
+        let revisions = doc.num_operations();
+
+        let result = doc.add_and_apply(Operation::SetName {
+            node: missing,
+            name: "lost".to_string(),
+        });
+        assert_eq!(result, Err(InvalidOperation::NodeNotFound(missing)));
+        assert_eq!(doc.num_operations(), revisions);
+
+        // A batch where the second operation is invalid is rejected as a whole
+        let mut changes = Changes::new();
+        changes.set_name(a, "renamed").set_name(missing, "lost");
+        let mut data = vec![];
+        for operation in &changes.operations {
+            operation.write(&mut data).unwrap();
+        }
+        assert!(doc.validate(&changes.operations).is_err());
+        match doc.append_and_apply(&mut data.as_slice()) {
+            Err(BincError::Rejected { revision, .. }) => assert_eq!(revision, revisions + 1),
+            other => panic!("Expected a rejection, got {:?}", other),
+        }
+        assert_eq!(doc.num_operations(), revisions);
+        assert_eq!(doc.nodes.get(a).unwrap().get_name(), None);
+    }
+
+    #[test]
+    fn huge_ids_are_rejected() {
+        let mut doc = Document::default();
+        doc.add_node("task", NodeId::ROOT_NODE);
+
+        // Any of these would make the document allocate far more than it has memory for
+        let huge = [
+            Operation::AddNode {
+                id: NodeId::new(0xFFFFFFFE),
+                node_type: 0,
+                parent: NodeId::ROOT_NODE,
+                index_in_parent: 0,
+            },
+            Operation::DefineTypeName {
+                id: 1 << 40,
+                name: "huge".to_string(),
+            },
+            Operation::DefineAttributeName {
+                id: usize::MAX,
+                name: "huge".to_string(),
+            },
+            Operation::AddSource {
+                id: 1 << 40,
+                importer: "XML".to_string(),
+                path: "huge.xml".to_string(),
+                hash: blake3::hash(b""),
+                imported: chrono::Utc::now(),
+            },
+        ];
+        for operation in &huge {
+            match doc.validate(std::slice::from_ref(operation)) {
+                Err(BincError::Rejected {
+                    reason: InvalidOperation::IdOutOfRange { .. },
+                    ..
+                }) => {}
+                other => panic!("Expected {} to be rejected, got {:?}", operation, other),
+            }
+        }
+
+        // Ids in a reserved block are fine, however far they are
+        let reserved = [
+            Operation::ReserveNodeIds {
+                first: doc.node_id_generator.next_id(),
+                count: 1 << 20,
+                client: "ann".to_string(),
+            },
+            Operation::AddNode {
+                id: NodeId::new(1_000_000),
+                node_type: 0,
+                parent: NodeId::ROOT_NODE,
+                index_in_parent: 0,
+            },
+        ];
+        doc.validate(&reserved).unwrap();
+    }
+
+    #[test]
+    fn typing_is_coalesced_into_one_operation() {
+        let mut doc = Document::default();
+        let a = doc.add_node("task", NodeId::ROOT_NODE);
+        doc.coalescing = Coalescing::Within(Duration::from_secs(60));
+        let revisions = doc.num_operations();
+
+        let type_name = |doc: &mut Document, name: &str| {
+            for i in 1..=name.len() {
+                doc.add_and_apply(Operation::SetName {
+                    node: a,
+                    name: name[..i].to_string(),
+                })
+                .unwrap();
+            }
+        };
+        type_name(&mut doc, "first");
+        assert_eq!(doc.num_operations(), revisions + 1);
+
+        // Undo goes back to before the whole name
+        doc.undo();
+        assert_eq!(doc.nodes.get(a).unwrap().get_name(), None);
+        doc.redo();
+        assert_eq!(doc.nodes.get(a).unwrap().get_name(), Some("first"));
+
+        // After an undo or a snapshot, the last operation is left as it is
+        doc.undo();
+        type_name(&mut doc, "second");
+        assert_eq!(doc.num_operations(), revisions + 1);
+        doc.add_and_apply(Operation::Snapshot {
+            author: "ann".to_string(),
+            message: "Named".to_string(),
+        })
+        .unwrap();
+        type_name(&mut doc, "third");
+        doc.end_coalescing();
+        type_name(&mut doc, "fourth");
+        assert_eq!(doc.num_operations(), revisions + 4);
+
+        doc.undo();
+        assert_eq!(doc.nodes.get(a).unwrap().get_name(), Some("third"));
+        doc.undo();
+        assert_eq!(doc.nodes.get(a).unwrap().get_name(), Some("second"));
+
+        // An edit in a transaction is not merged into one from before it, so a rollback
+        // takes back only the edit
+        doc.redo();
+        type_name(&mut doc, "before");
+        doc.begin_transaction();
+        type_name(&mut doc, "in transaction");
+        doc.rollback_transaction();
+        assert_eq!(doc.nodes.get(a).unwrap().get_name(), Some("before"));
+    }
+
+    #[test]
+    fn undo_and_redo_match_a_replay() {
+        let mut doc = Document::default();
+        let a = doc.add_node("task", NodeId::ROOT_NODE);
+        let b = doc.add_node("subtask", a);
+        doc.set_node_attribute_s(b, "status", "open");
+        doc.add_and_apply(Operation::MoveNode {
+            id: b,
+            new_parent: NodeId::ROOT_NODE,
+            index_in_new_parent: 0,
+        })
+        .unwrap();
+        doc.add_and_apply(Operation::RemoveNode { id: a }).unwrap();
+
+        let replay = |doc: &Document, revision: usize| {
+            let mut replayed = Journal::new();
+            replayed.operations = doc.journal.operations[..revision].to_vec();
+            Document::new(replayed).nodes
+        };
+        while doc.can_undo() {
+            doc.undo();
+            let revision = doc.undo_revision.unwrap();
+            assert!(same_nodes(&doc.nodes, &replay(&doc, revision)));
+        }
+        while doc.can_redo() {
+            doc.redo();
+        }
+        assert_eq!(doc.undo_revision, None);
+        assert!(same_nodes(&doc.nodes, &replay(&doc, doc.num_operations())));
+    }
+
+    #[test]
+    fn transactions_are_undone_in_one_step() {
+        let mut doc = Document::default();
+        // Defines the type and adds the node
+        let a = doc.add_node("task", NodeId::ROOT_NODE);
+        assert_eq!(doc.num_operations(), 2);
+        doc.undo();
+        assert_eq!(doc.undo_revision, Some(0));
+        doc.redo();
+        assert_eq!(doc.undo_revision, None);
+
+        doc.begin_transaction();
+        doc.set_node_name(a, "first");
+        doc.transaction(|doc| {
+            doc.set_node_name(a, "second");
+            doc.set_node_tag(a, "urgent");
+        });
+        doc.commit_transaction();
+        let mut changes = Changes::new();
+        changes.set_name(a, "third").set_name(a, "fourth");
+        doc.add_and_apply_changes(changes).unwrap();
+        assert_eq!(doc.num_operations(), 8);
+
+        doc.undo();
+        assert_eq!(doc.nodes.get(a).unwrap().get_name(), Some("second"));
+        doc.undo();
+        assert_eq!(doc.nodes.get(a).unwrap().get_name(), None);
+        assert!(doc.nodes.get(a).unwrap().tags.is_empty());
+        doc.redo();
+        assert_eq!(doc.nodes.get(a).unwrap().get_name(), Some("second"));
+        assert_eq!(doc.undo_revision, Some(6));
+
+        // A rolled back transaction leaves nothing behind, not even redo
+        doc.begin_transaction();
+        doc.set_node_name(a, "lost");
+        doc.rollback_transaction();
+        assert_eq!(doc.num_operations(), 6);
+        assert_eq!(doc.nodes.get(a).unwrap().get_name(), Some("second"));
+        assert!(!doc.can_redo());
+    }
+
+    #[test]
+    fn undo_to_snapshot() {
+        let mut doc = Document::default();
+        let snapshot = |doc: &mut Document, message: &str| {
+            doc.add_and_apply(Operation::Snapshot {
+                author: "ann".to_string(),
+                message: message.to_string(),
+            })
+            .unwrap();
+        };
+        let a = doc.add_node("task", NodeId::ROOT_NODE);
+        doc.set_node_name(a, "first");
+        snapshot(&mut doc, "First");
+        doc.set_node_name(a, "second");
+        doc.set_node_name(a, "third");
+
+        doc.undo_to_snapshot();
+        assert_eq!(doc.nodes.get(a).unwrap().get_name(), Some("first"));
+        doc.undo_to_snapshot();
+        assert!(!doc.can_undo());
+        assert!(doc.nodes.resolve(a).is_none());
+
+        doc.redo_to_snapshot();
+        assert_eq!(doc.nodes.get(a).unwrap().get_name(), Some("first"));
+        doc.redo_to_snapshot();
+        assert_eq!(doc.nodes.get(a).unwrap().get_name(), Some("third"));
+        assert!(!doc.can_redo());
+    }
+
+    #[test]
+    fn removed_attribute_stays_removed() {
+        let mut doc = Document::default();
+        let a = doc.add_node("task", NodeId::ROOT_NODE);
+        doc.set_node_attribute_s(a, "status", "open");
+        let status = doc.get_or_define_attribute_id("status");
+        let mut changes = Changes::new();
+        changes.remove_attribute(a, status);
+        doc.add_and_apply_changes(changes).unwrap();
+        assert_eq!(doc.nodes.get(a).unwrap().get_attribute(status), None);
+
+        let mut buf = Vec::<u8>::new();
+        doc.write(&mut buf).unwrap();
+        let mut doc2 = Document::read(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(doc2.nodes.get(a).unwrap().get_attribute(status), None);
+
+        doc2.undo();
+        assert_eq!(
+            doc2.nodes.get(a).unwrap().get_string_attribute(status),
+            Some("open")
+        );
+    }
+
+    fn save_and_reopen(doc: &Document) -> Document {
+        let mut buf = Vec::<u8>::new();
+        doc.write(&mut buf).unwrap();
+        Document::read(&mut Cursor::new(buf)).unwrap()
+    }
+
+    #[test]
+    fn reopened_document_adds_new_ids() {
+        let mut doc = Document::default();
+        let a = doc.add_node("task", NodeId::ROOT_NODE);
+        let b = doc.add_node("task", a);
+        doc.set_node_name(a, "first");
+        doc.set_node_name(b, "second");
+
+        let mut doc2 = save_and_reopen(&doc);
+        let c = doc2.add_node("task", NodeId::ROOT_NODE);
+        assert_ne!(c, a);
+        assert_ne!(c, b);
+        assert_eq!(doc2.nodes.get(a).unwrap().get_name(), Some("first"));
+        assert_eq!(doc2.nodes.get(b).unwrap().get_name(), Some("second"));
+
+        doc2.set_node_name(c, "third");
+        let mut doc3 = save_and_reopen(&doc2);
+        assert_eq!(doc3.find_roots().len(), 2);
+        assert_eq!(doc3.nodes.get(a).unwrap().get_name(), Some("first"));
+        assert_eq!(doc3.nodes.get(c).unwrap().get_name(), Some("third"));
+        let d = doc3.add_node("task", NodeId::ROOT_NODE);
+        assert!(![a, b, c].contains(&d));
+    }
+
+    #[test]
+    fn ids_of_removed_nodes_are_not_reused() {
+        // The example removes the first node it adds, so only the second is left
+        let mut doc = Document::new(create_example_journal());
+        let id = doc.add_node("task", NodeId::ROOT_NODE);
+        assert_eq!(id, NodeId::new(3));
+
+        let mut doc2 = save_and_reopen(&doc);
+        assert_eq!(doc2.add_node("task", NodeId::ROOT_NODE), NodeId::new(4));
+    }
+
+    #[test]
+    fn ids_of_referenced_nodes_are_not_reused() {
+        let mut doc = Document::default();
+        let a = doc.add_node("task", NodeId::ROOT_NODE);
+        doc.set_node_attribute(
+            a,
+            "blocked_by",
+            AttributeValue::NodeReference(NodeId::new(10)),
+        );
+
+        let mut doc2 = save_and_reopen(&doc);
+        assert_eq!(doc2.add_node("task", NodeId::ROOT_NODE), NodeId::new(11));
+    }
+
+    #[test]
+    fn appended_operations_advance_ids() {
+        let mut doc = Document::default();
+        let mut changes = Changes::new();
+        changes.add_node(NodeId::new(5), NodeId::ROOT_NODE, 0);
+        doc.add_and_apply_changes(changes).unwrap();
+        assert_eq!(doc.next_id(), NodeId::new(6));
+
+        let mut data = vec![];
+        let mut remote = Changes::new();
+        remote.add_node(NodeId::new(8), NodeId::ROOT_NODE, 1);
+        for operation in &remote.operations {
+            operation.write(&mut data).unwrap();
+        }
+        doc.append_and_apply(&mut data.as_slice()).unwrap();
+        assert_eq!(doc.add_node("task", NodeId::ROOT_NODE), NodeId::new(9));
+        assert_eq!(doc.find_roots().len(), 3);
+    }
+
+    #[test]
+    fn clients_with_reserved_ids_dont_collide() {
+        let mut server = Document::default();
+        server.add_node("task", NodeId::ROOT_NODE);
+
+        // Ids are reserved the way a server does it, after every id the file uses
+        let reserve = |server: &mut Document, client: &str| {
+            let first = NodeIdGenerator::for_operations(&server.journal.operations).next_id();
+            server
+                .add_and_apply(Operation::ReserveNodeIds {
+                    first,
+                    count: 10,
+                    client: client.to_string(),
+                })
+                .unwrap();
+            first
+        };
+        let ann_block = reserve(&mut server, "ann");
+        let bob_block = reserve(&mut server, "bob");
+        assert_eq!(ann_block, NodeId::new(2));
+        assert_eq!(bob_block, NodeId::new(12));
+
+        let mut ann = save_and_reopen(&server);
+        let mut bob = save_and_reopen(&server);
+        ann.node_id_generator.use_reserved(ann_block, 10);
+        bob.node_id_generator.use_reserved(bob_block, 10);
+        let a = ann.add_node("task", NodeId::ROOT_NODE);
+        let b = bob.add_node("task", NodeId::ROOT_NODE);
+        assert_eq!(a, ann_block);
+        assert_eq!(b, bob_block);
+        assert_eq!(ann.node_id_generator.reserved_left(), 9);
+
+        // Someone without a reservation gets ids after both blocks
+        let mut other = save_and_reopen(&server);
+        assert_eq!(other.add_node("task", NodeId::ROOT_NODE), NodeId::new(22));
+
+        // Once its blocks are used up, a client gets no more ids until it reserves more, as
+        // the ids after them are the next ones the server hands out
+        for _ in 1..10 {
+            ann.add_node("task", NodeId::ROOT_NODE);
+        }
+        assert_eq!(ann.node_id_generator.reserved_left(), 0);
+        assert_eq!(ann.next_id(), NodeId::NO_NODE);
+
+        // Ids in a new block that nodes already have are not handed out again
+        let mut carl = save_and_reopen(&other);
+        carl.use_reserved_ids(NodeId::new(20), 10);
+        assert_eq!(carl.node_id_generator.reserved_left(), 7);
+        assert_eq!(carl.next_id(), NodeId::new(23));
+    }
+
+    #[test]
+    fn running_out_of_node_ids_gives_no_node() {
+        let last = NodeId::NO_NODE_ID - 1;
+        let mut generator = NodeIdGenerator::new();
+        generator.use_reserved(NodeId::new(last), 10);
+        assert_eq!(generator.next_id(), NodeId::new(last));
+        generator.skip_past(NodeId::new(last));
+        assert_eq!(generator.next_id(), NodeId::NO_NODE);
+        assert_eq!(generator.next_id(), NodeId::NO_NODE);
+        assert_eq!(generator.reserved_left(), 0);
+    }
+
+    #[test]
+    fn local_operations_are_rebased_on_remote_ones() {
+        let mut server = Document::default();
+        let a = server.add_node("task", NodeId::ROOT_NODE);
+        server.set_node_name(a, "shared");
+        let committed = server.num_operations();
+
+        // Both add a node with the same id while offline
+        let mut ann = save_and_reopen(&server);
+        let mut bob = save_and_reopen(&server);
+        let ann_node = ann.add_node("task", NodeId::ROOT_NODE);
+        ann.set_node_name(ann_node, "ann");
+        let bob_node = bob.add_node("note", NodeId::ROOT_NODE);
+        bob.set_node_name(bob_node, "bob");
+        assert_eq!(ann_node, bob_node);
+
+        let remote = bob.journal.operations[committed..].to_vec();
+        let conflicts = ann.rebase(committed, &remote);
+        assert!(conflicts.is_empty());
+        assert_eq!(ann.journal.operations[committed..][..remote.len()], remote);
+
+        let roots = ann.find_roots().clone();
+        assert_eq!(roots.len(), 3);
+        // The local node stays right after the node it followed
+        assert_eq!(roots[2], bob_node);
+        let moved = roots[1];
+        assert_ne!(moved, bob_node);
+        assert_eq!(ann.nodes.get(bob_node).unwrap().get_name(), Some("bob"));
+        assert_eq!(ann.nodes.get(moved).unwrap().get_name(), Some("ann"));
+        assert!(ann.next_id().index() > moved.index());
+
+        // Undo only steps back over local changes, which stay after the remote ones
+        ann.undo();
+        assert_eq!(ann.nodes.get(moved).unwrap().get_name(), None);
+        assert_eq!(ann.nodes.get(bob_node).unwrap().get_name(), Some("bob"));
+    }
+
+    #[test]
+    fn rebase_drops_undone_operations() {
+        let mut server = Document::default();
+        let a = server.add_node("task", NodeId::ROOT_NODE);
+        let committed = server.num_operations();
+
+        let mut local = save_and_reopen(&server);
+        local.set_node_name(a, "undone");
+        local.undo();
+        let mut remote = save_and_reopen(&server);
+        remote.set_node_name(a, "remote");
+
+        let operations = remote.journal.operations[committed..].to_vec();
+        local.rebase(committed, &operations);
+        assert!(!local.can_redo());
+        assert_eq!(local.num_operations(), remote.num_operations());
+        assert_eq!(local.nodes.get(a).unwrap().get_name(), Some("remote"));
+    }
+
+    #[test]
+    fn committed_operations_are_not_undone() {
+        let mut server = Document::default();
+        let a = server.add_node("task", NodeId::ROOT_NODE);
+        server.set_node_name(a, "shared");
+
+        // Commit, undo, edit and then fetch again
+        let mut local = save_and_reopen(&server);
+        let committed = local.num_operations();
+        local.set_committed(committed);
+        local.undo();
+        local.undo();
+        assert!(!local.can_undo());
+        assert_eq!(local.nodes.get(a).unwrap().get_name(), Some("shared"));
+
+        local.set_node_name(a, "local");
+        local.undo();
+        assert!(!local.can_undo());
+        local.set_node_name(a, "local");
+        let mut remote = save_and_reopen(&server);
+        let b = remote.add_node("note", NodeId::ROOT_NODE);
+
+        let operations = remote.journal.operations[committed..].to_vec();
+        let conflicts = local.rebase(committed, &operations);
+        assert!(conflicts.is_empty());
+        assert_eq!(local.num_operations(), remote.num_operations() + 1);
+        assert_eq!(local.nodes.get(a).unwrap().get_name(), Some("local"));
+        assert!(local.nodes.get(b).is_some());
+
+        // What is undone is not sent with the next commit
+        local.undo();
+        local.discard_redo();
+        assert!(!local.can_redo());
+        assert_eq!(local.num_operations(), remote.num_operations());
+    }
+
     fn read_file(path: &str) -> Vec<u8> {
         let mut file = File::open(path).unwrap();
         let mut buf = Vec::<u8>::new();
@@ -3,13 +3,17 @@ mod store;
 
 use crate::store::Store;
 use binc::client::Client;
+use binc::compaction::{compact, CompactOptions};
+use binc::compatibility::{extension_name, is_v1, to_v1, V1Conversion};
 use binc::document::Document;
 use binc::journal::Journal;
 use binc::network_protocol::{NetworkRequest, NetworkResponse};
 use binc::node_id::NodeId;
 use binc::node_store::Node;
+use binc::validation::check_journal;
 use clap::{Parser, Subcommand};
 use std::io;
+use std::io::Write;
 
 /// A simple command line tool for creating, manipulating, viewing and serving BINC documents
 #[derive(Parser, Debug)]
@@ -37,6 +41,35 @@ enum Commands {
     /// Print the document tree
     Tree { path: String },
 
+    /// Check the document for damage and operations that don't fit, and list the problems
+    Check { path: String },
+
+    /// Rewrite the document with as few operations as possible. The original is kept with a
+    /// .bak extension. The document gets a new id, so clients that have it open have to open it
+    /// again.
+    Compact {
+        path: String,
+        /// Keep snapshots and the operations between them
+        #[arg(long)]
+        keep_snapshots: bool,
+        /// Keep every comment edit and retraction
+        #[arg(long)]
+        keep_comments: bool,
+    },
+
+    /// List the extensions to version 1 of the spec that the document uses
+    Extensions { path: String },
+
+    /// Write a copy of the document with only version 1 operations, for other implementations
+    ExportV1 {
+        path: String,
+        output: String,
+        /// Convert attributes to strings and leave out snapshots and checksums, instead of
+        /// failing on them
+        #[arg(long)]
+        downgrade: bool,
+    },
+
     /// Serve the contents of the directory over HTTP
     Serve { path: String, port: u16 },
 }
@@ -69,25 +102,43 @@ fn main() -> io::Result<()> {
             }
             Commands::Tree { path } => {
                 println!("Printing document tree for {}", path);
-                if let Ok(repo) = client
+                match client
                     .request(NetworkRequest::GetFileData { from: 0, path })?
                     .as_journal()
                 {
-                    let document = Document::new(repo);
-                    print_tree(&document, NodeId::ROOT_NODE, 0, 0);
+                    Ok(repo) => {
+                        let document = Document::new(repo);
+                        print_tree(&document, NodeId::ROOT_NODE, 0, 0);
+                    }
+                    Err(e) => println!("Error: {}", e),
                 }
             }
             Commands::History { store: path } => {
                 println!("Listing revisions for {}", path);
-                if let Ok(repo) = client
+                match client
                     .request(NetworkRequest::GetFileData { from: 0, path })?
                     .as_journal()
                 {
-                    repo.operations.iter().for_each(|c| {
+                    Ok(repo) => repo.operations.iter().for_each(|c| {
                         println!(" * {}", c);
-                    });
+                    }),
+                    Err(e) => println!("Error: {}", e),
                 }
             }
+            Commands::Check { path } => {
+                println!("Checking {}", path);
+                let journal = client
+                    .request(NetworkRequest::GetFileData { from: 0, path })?
+                    .as_journal()?;
+                report_problems(&journal)?;
+            }
+            Commands::Extensions { path } => {
+                println!("Listing extensions for {}", path);
+                let journal = client
+                    .request(NetworkRequest::GetFileData { from: 0, path })?
+                    .as_journal()?;
+                list_extensions(&journal);
+            }
             _ => {
                 println!("Command not supported for remote server");
             }
@@ -139,6 +190,105 @@ fn main() -> io::Result<()> {
 
             Ok(())
         }
+        Commands::Check { path: store } => {
+            println!("Checking store {}", store);
+
+            let data = std::fs::read(store)?;
+            let (journal, damaged) = match Journal::read(&mut data.as_slice()) {
+                Ok(journal) => (journal, false),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    let (journal, report) = Journal::recover(&data);
+                    for range in &report.skipped {
+                        println!("Skipped damaged bytes {}..{}", range.start, range.end);
+                    }
+                    (journal, true)
+                }
+            };
+            report_problems(&journal)?;
+            if damaged {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "The file is damaged",
+                ));
+            }
+            Ok(())
+        }
+        Commands::Compact {
+            path: store,
+            keep_snapshots,
+            keep_comments,
+        } => {
+            println!("Compacting store {}", store);
+
+            let data = std::fs::read(&store)?;
+            let journal = Journal::read(&mut data.as_slice())?;
+            let options = CompactOptions {
+                keep_snapshots,
+                keep_comments,
+            };
+            let compacted = compact(&journal, options);
+            if compacted.version == 1 {
+                println!(
+                    "Warning: version 1 files have no document id, so clients that have it open \
+                     can't tell it was rewritten"
+                );
+            }
+
+            // Write next to the original first, so a failure can't leave a partial file
+            let backup = format!("{}.bak", store);
+            let temporary = format!("{}.tmp", store);
+            write_synced(&temporary, |file| compacted.write(file))?;
+            write_synced(&backup, |file| file.write_all(&data))?;
+
+            // A server may have appended to it since it was read, which would be lost
+            if std::fs::metadata(&store)?.len() != data.len() as u64 {
+                std::fs::remove_file(&temporary)?;
+                return Err(io::Error::other(
+                    "The file changed while it was compacted, try again",
+                ));
+            }
+            std::fs::rename(&temporary, &store)?;
+
+            println!(
+                "{} operations compacted to {}, original kept as {}",
+                journal.operations.len(),
+                compacted.operations.len(),
+                backup
+            );
+            Ok(())
+        }
+        Commands::Extensions { path: store } => {
+            println!("Listing extensions for store {}", store);
+
+            let journal = Journal::read(&mut std::fs::File::open(store)?)?;
+            list_extensions(&journal);
+
+            Ok(())
+        }
+        Commands::ExportV1 {
+            path: store,
+            output,
+            downgrade,
+        } => {
+            println!("Exporting store {} to {}", store, output);
+
+            let journal = Journal::read(&mut std::fs::File::open(store)?)?;
+            let conversion = if downgrade {
+                V1Conversion::Downgrade
+            } else {
+                V1Conversion::Strict
+            };
+            let converted = to_v1(&journal, conversion)?;
+            converted.write(&mut std::fs::File::create(output)?)?;
+
+            println!(
+                "{} of {} operations written",
+                converted.operations.len(),
+                journal.operations.len()
+            );
+            Ok(())
+        }
         Commands::Serve { path: store, port } => {
             println!("Serving store {} on port {}", store, port);
             server::server(store, port);
@@ -147,6 +297,61 @@ fn main() -> io::Result<()> {
     }
 }
 
+/// Write a file and wait until it is on disk, so it can replace another one
+fn write_synced(
+    path: &str,
+    write: impl FnOnce(&mut std::fs::File) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut file = std::fs::File::create(path)?;
+    write(&mut file)?;
+    file.sync_all()
+}
+
+/// Print every problem in the journal. Finding any is an error, so scripts can tell.
+fn report_problems(journal: &Journal) -> io::Result<()> {
+    let problems = check_journal(journal);
+    for problem in &problems {
+        println!("{}", problem);
+        println!("    {}", journal.operations[problem.index]);
+    }
+
+    if problems.is_empty() {
+        println!("{} operations, no problems found", journal.operations.len());
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} problems found", problems.len()),
+        ))
+    }
+}
+
+/// Print the container version, the features in the header, and how many operations use each
+/// extension to version 1
+fn list_extensions(journal: &Journal) {
+    println!("Container version {}", journal.version);
+    if !journal.header.features.is_empty() {
+        println!("Features in header: {}", journal.header.features.join(", "));
+    }
+
+    let mut counts: Vec<(String, usize)> = vec![];
+    for operation in journal.operations.iter().filter(|o| !is_v1(o)) {
+        let name = extension_name(operation);
+        match counts.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((name, 1)),
+        }
+    }
+    counts.sort();
+
+    if counts.is_empty() {
+        println!("Only version 1 operations are used");
+    }
+    for (name, count) in counts {
+        println!("{}: {} operations", name, count);
+    }
+}
+
 fn list_files(files: Vec<String>) {
     for file in files {
         println!("{}", file);
@@ -11,6 +11,7 @@ struct Connection {
 pub(crate) fn server(store: String, port: u16) {
     let addr = format!("0.0.0.0:{}", port);
     let listener = TcpListener::bind(addr).unwrap();
+    let store = Store::new(&store);
 
     for stream in listener.incoming() {
         match stream {
@@ -34,11 +35,8 @@ pub(crate) fn server(store: String, port: u16) {
 }
 
 impl Connection {
-    fn new(stream: TcpStream, root_dir: String) -> Connection {
-        Connection {
-            stream,
-            store: Store::new(&root_dir),
-        }
+    fn new(stream: TcpStream, store: Store) -> Connection {
+        Connection { stream, store }
     }
 
     pub fn handle_connection(&mut self) -> io::Result<()> {
@@ -69,35 +67,60 @@ impl Connection {
                     NetworkRequest::GetFileData {
                         from: from_revision,
                         path,
-                    } => {
-                        if let Ok((from_revision, to_revision, data)) =
-                            self.store.get_file_data(from_revision, path)
-                        {
+                    } => match self.store.get_file_data(from_revision, path) {
+                        Ok((from_revision, to_revision, document_id, data)) => {
                             NetworkResponse::GetFileData {
                                 from: from_revision,
                                 to: to_revision,
+                                document_id,
                                 data,
                             }
                             .write(&mut stream)?;
                         }
-                    }
+                        Err(e) => {
+                            NetworkResponse::Error {
+                                message: e.to_string(),
+                            }
+                            .write(&mut stream)?;
+                        }
+                    },
                     NetworkRequest::AppendFile {
                         from: from_revision,
                         to: to_revision,
                         path,
+                        document_id,
                         data,
                     } => {
                         NetworkResponse::AppendFile {
                             result: self
                                 .store
-                                .append_file(from_revision, to_revision, &path, data)
+                                .append_file(from_revision, to_revision, &path, document_id, data)
                                 .map_err(|e| e.to_string()),
                         }
                         .write(&mut stream)?;
                     }
+                    NetworkRequest::ReserveNodeIds {
+                        path,
+                        client,
+                        count,
+                    } => match self.store.reserve_node_ids(&path, client, count) {
+                        Ok(first) => {
+                            NetworkResponse::ReserveNodeIds { first, count }.write(&mut stream)?;
+                        }
+                        Err(e) => {
+                            NetworkResponse::Error {
+                                message: e.to_string(),
+                            }
+                            .write(&mut stream)?;
+                        }
+                    },
+                }
+            } else if let Err(e) = request {
+                // A client that goes away without saying goodbye is not an error
+                if e.is_end_of_stream() {
+                    return Ok(());
                 }
-            } else if let Err(request) = request {
-                return Err(request);
+                return Err(e.into());
             }
         }
     }
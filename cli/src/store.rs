@@ -1,19 +1,41 @@
-use binc::journal::Journal;
+use binc::document::Document;
+use binc::error::BincError;
+use binc::journal::{Journal, JournalHash};
+use binc::node_id::{NodeId, NodeIdGenerator};
+use binc::node_store::NodeStore;
+use binc::operation::Operation;
+use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::io::Write;
+use std::sync::{Arc, Mutex};
 use std::{fs, io};
+use uuid::Uuid;
 
+/// Clones share the locks, so one store can be cloned for every connection
+#[derive(Clone)]
 pub struct Store {
     root_dir: String,
+    /// A lock per file, held from reading the file to appending to it, so two connections
+    /// can't both append at the same revision or reserve the same node ids
+    locks: Arc<Mutex<HashMap<String, Arc<Mutex<()>>>>>,
 }
 
 impl Store {
+    /// Most node ids one client can reserve at a time
+    const MAX_RESERVED_IDS: u64 = NodeStore::MAX_RESERVED_IDS as u64;
+
     pub fn new(root: &str) -> Store {
         Store {
             root_dir: root.to_string(),
+            locks: Arc::default(),
         }
     }
 
+    fn file_lock(&self, fs_path: &str) -> Arc<Mutex<()>> {
+        let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+        locks.entry(fs_path.to_string()).or_default().clone()
+    }
+
     fn translate_path(&self, path: &str) -> String {
         self.root_dir.clone() + "/" + &path
     }
@@ -37,15 +59,19 @@ impl Store {
         }
     }
 
-    pub fn get_file_data(&self, from: u64, path: String) -> io::Result<(u64, u64, Vec<u8>)> {
+    /// The operations from revision `from` on, with the range of revisions and the document id
+    pub fn get_file_data(
+        &self,
+        from: u64,
+        path: String,
+    ) -> Result<(u64, u64, Uuid, Vec<u8>), BincError> {
         let repo = Journal::read(&mut fs::File::open(self.translate_path(&path))?)?;
         let to = repo.operations.len() as u64;
 
         if from > to {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Revision out of range",
-            ));
+            return Err(
+                io::Error::new(io::ErrorKind::InvalidInput, "Revision out of range").into(),
+            );
         }
 
         let mut data = vec![0; 0];
@@ -58,34 +84,137 @@ impl Store {
             index += 1;
         }
 
-        Ok((from, to, data))
+        Ok((from, to, Self::document_id(&repo), data))
     }
 
+    /// Append the operations `from..to` to the file, and return the revision it is at after.
+    /// That includes the checksum added after them.
     pub(crate) fn append_file(
         &self,
         from: u64,
         to: u64,
         path: &str,
+        document_id: Uuid,
         data: Vec<u8>,
-    ) -> io::Result<()> {
+    ) -> Result<u64, BincError> {
         if from >= to {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
                 format!("No changes to append. {}..{}", from, to),
-            ));
+            )
+            .into());
         }
 
         let fs_path = self.translate_path(path);
-        let repo = Journal::read(&mut fs::File::open(fs_path.clone())?)?;
+        let lock = self.file_lock(&fs_path);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        let (repo, hash) = Journal::read_hashed(&mut fs::File::open(fs_path.clone())?)?;
+        // The revisions the client has are not the ones in a rewritten file
+        if Self::document_id(&repo) != document_id {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The document was rewritten, open it again",
+            )
+            .into());
+        }
         if repo.operations.len() as u64 != from {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Revision mismatch").into());
+        }
+
+        // Don't let a client write operations the file can't be read back with, or that
+        // don't fit the document
+        let mut appended = Journal::new();
+        appended.append(&mut data.as_slice())?;
+        if appended.operations.len() as u64 != to - from {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
-                "Revision mismatch",
-            ));
+                format!(
+                    "Expected {} operations, got {}",
+                    to - from,
+                    appended.operations.len()
+                ),
+            )
+            .into());
         }
+        // Only the server hands out node ids, see `reserve_node_ids`
+        if appended
+            .operations
+            .iter()
+            .any(|o| matches!(o, Operation::ReserveNodeIds { .. }))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Node ids can only be reserved through the server",
+            )
+            .into());
+        }
+        Document::new(repo).validate(&appended.operations)?;
+
+        Self::write_operations(&fs_path, data, hash)?;
+        Ok(to + 1)
+    }
+
+    /// Reserve the next `count` node ids in the file for `client`, and return the first one.
+    /// The reservation is added to the file, so no one else gets the same ids.
+    pub(crate) fn reserve_node_ids(
+        &self,
+        path: &str,
+        client: String,
+        count: u64,
+    ) -> Result<u64, BincError> {
+        if count == 0 || count > Self::MAX_RESERVED_IDS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Can't reserve {} ids", count),
+            )
+            .into());
+        }
+
+        let fs_path = self.translate_path(path);
+        let lock = self.file_lock(&fs_path);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        let (repo, hash) = Journal::read_hashed(&mut fs::File::open(fs_path.clone())?)?;
+        let first = NodeIdGenerator::for_operations(&repo.operations).next_id();
+        if !first.exists() || first.index() as u64 + count > NodeId::NO_NODE_ID as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                format!("Not enough node ids left to reserve {}", count),
+            )
+            .into());
+        }
+
+        let mut data = vec![];
+        Operation::ReserveNodeIds {
+            first,
+            count: count as usize,
+            client,
+        }
+        .write(&mut data)?;
+        Self::write_operations(&fs_path, data, hash)?;
+
+        Ok(first.index() as u64)
+    }
+
+    /// A version 1 file has no document id, and reading it makes up a new one every time
+    fn document_id(journal: &Journal) -> Uuid {
+        match journal.version {
+            1 => Uuid::nil(),
+            _ => journal.header.document_id,
+        }
+    }
+
+    /// Append `data` and a checksum to a file that hashes to `hash` so far, so corruption is
+    /// found before the data is served again
+    fn write_operations(
+        fs_path: &str,
+        mut data: Vec<u8>,
+        mut hash: JournalHash,
+    ) -> Result<(), BincError> {
+        hash.update(&data);
+        hash.checksum().write(&mut data)?;
 
         let mut file = OpenOptions::new().append(true).open(fs_path)?;
-        file.write(&data)?;
+        file.write_all(&data)?;
 
         Ok(())
     }
@@ -1,8 +1,9 @@
-use crate::importer::{Import, Importer, IMPORTERS};
+use crate::importer::{import_file, reimport_file, Import, Importer, IMPORTERS};
 use crate::persistent_client::PersistentClient;
 use binc::changes::Changes;
-use binc::document::Document;
+use binc::document::{Coalescing, Document};
 use binc::journal::Journal;
+use binc::merge::Conflict;
 use binc::node_id::NodeId;
 use binc::node_store::Node;
 use binc::operation::Operation;
@@ -11,8 +12,8 @@ use eframe::egui::{Id, Modal, Sense, Ui, Widget};
 use std::collections::HashSet;
 use std::fs::File;
 use std::io;
-use std::path::PathBuf;
-use std::time::SystemTime;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 use crate::uiext::UiExt;
 
@@ -110,6 +111,7 @@ impl Application {
         if let Ok((client, document)) = result {
             self.client = Some(client);
             self.document = document;
+            self.document.coalescing = Self::EDIT_COALESCING;
         } else if let Err(error) = result {
             let text = format!("Failed to connect to host\n\n{}", error.to_string());
             rfd::MessageDialog::new()
@@ -131,12 +133,12 @@ impl Application {
         if let Some(client) = &mut self.client {
             let result = client.check_for_updates(&mut self.document);
 
-            if result.is_err() {
-                let text = format!(
-                    "Failed to check for updates\n\n{}",
-                    result.unwrap_err().to_string()
-                );
-                log::error!("{}", text);
+            match result {
+                Ok(conflicts) => show_conflicts(&conflicts),
+                Err(error) => {
+                    let text = format!("Failed to check for updates\n\n{}", error.to_string());
+                    log::error!("{}", text);
+                }
             }
         }
 
@@ -156,7 +158,7 @@ impl Application {
             } => self.move_node(&node, &new_parent, index_in_new_parent),
             GuiAction::RemoveNode { node } => self.remove_node(&node),
             GuiAction::Commit { message } => self.commit(&message),
-            GuiAction::WrappedChange { change } => self.document.add_and_apply(change),
+            GuiAction::WrappedChange { change } => self.apply_operation(change),
             GuiAction::Undo => self.document.undo(),
             GuiAction::Redo => self.document.redo(),
             GuiAction::SelectPreviousInTree => self.select_previous_in_tree(),
@@ -183,9 +185,14 @@ impl Application {
 }
 
 impl Application {
+    /// Typing in the inspector sets the name once per key, so edits close together are merged
+    const EDIT_COALESCING: Coalescing = Coalescing::Within(Duration::from_secs(2));
+
     pub fn new() -> Application {
+        let mut document = new_document();
+        document.coalescing = Self::EDIT_COALESCING;
         Application {
-            document: new_document(),
+            document,
             ui: UiState::default(),
             document_path: None,
             client: None,
@@ -195,6 +202,7 @@ impl Application {
 
     pub fn set_document(&mut self, document: Document) {
         self.document = document;
+        self.document.coalescing = Self::EDIT_COALESCING;
         self.ui.root = NodeId::ROOT_NODE;
         self.select_node(NodeId::NO_NODE);
     }
@@ -225,7 +233,7 @@ impl Application {
             parent: parent_id.clone(),
             index_in_parent: insertion_index,
         };
-        self.document.add_and_apply(c1);
+        self.apply_operation(c1);
     }
 
     pub fn move_node(&mut self, node_id: &NodeId, new_parent_id: &NodeId, insertion_index: usize) {
@@ -234,20 +242,28 @@ impl Application {
             new_parent: new_parent_id.clone(),
             index_in_new_parent: insertion_index,
         };
-        self.document.add_and_apply(c);
+        self.apply_operation(c);
     }
 
     pub fn remove_node(&mut self, node_id: &NodeId) {
         let c = Operation::RemoveNode {
             id: node_id.clone(),
         };
-        self.document.add_and_apply(c);
+        self.apply_operation(c);
         self.select_node(NodeId::NO_NODE);
         if !self.node_exists(self.ui.root) {
             self.ui.root = NodeId::ROOT_NODE;
         }
     }
 
+    /// Apply an edit made in the UI. The UI can be behind the document, like when dragging a
+    /// node that was just removed, so a rejected edit is logged and otherwise ignored.
+    fn apply_operation(&mut self, operation: Operation) {
+        if let Err(e) = self.document.add_and_apply(operation) {
+            log::error!("Edit rejected: {}", e);
+        }
+    }
+
     fn node_exists(&self, id: NodeId) -> bool {
         self.document.nodes.exists(id)
     }
@@ -258,26 +274,19 @@ impl Application {
 
     pub fn commit(&mut self, message: &str) {
         if !message.is_empty() {
-            self.document.add_and_apply(Operation::Snapshot {
+            self.apply_operation(Operation::Snapshot {
                 author: Self::get_author(),
                 message: message.to_string(),
             })
         }
-        /*self.document.pending_changes.message = message.to_string();
-        self.document.commit_changes();
 
         if let Some(client) = &mut self.client {
-            let result = client.commit_changes(self.document.as_ref());
-
-            if let Err(error) = result {
-                let text = format!("Failed to commit changes\n\n{}", error.to_string());
-                rfd::MessageDialog::new()
-                    .set_level(rfd::MessageLevel::Error)
-                    .set_title("Error")
-                    .set_description(text)
-                    .show();
+            let result = client.commit_changes(&mut self.document);
+            match result {
+                Ok(conflicts) => show_conflicts(&conflicts),
+                Err(_) => show_error(result, "Failed to commit changes"),
             }
-        }*/
+        }
     }
 
     pub fn get_previous_sibling(&self, node_id: NodeId) -> Option<NodeId> {
@@ -524,10 +533,41 @@ pub fn create_toolbar(app: &mut Application, ui: &mut Ui, extra: impl FnOnce(&mu
                     }
                 }
             });
+
+            let sources: Vec<_> = app
+                .document
+                .sources()
+                .iter()
+                .map(|(_, source)| (source.importer.clone(), source.path.clone()))
+                .collect();
+            ui.add_enabled_ui(!sources.is_empty(), |ui| {
+                ui.menu_button("Re-import", |ui| {
+                    for (name, path) in sources {
+                        let importer = IMPORTERS.into_iter().find(|i| i.get_name() == name);
+                        if let Some(importer) = importer {
+                            if ui.button(&path).clicked() {
+                                let result =
+                                    reimport_file(&importer, Path::new(&path), &mut app.document);
+                                if result.is_err() {
+                                    show_error(result, "Failed to re-import file");
+                                }
+                            }
+                        }
+                    }
+                });
+            });
         });
 
         ui.separator();
 
+        if ui
+            .button_with_enable("⏮", app.document.can_undo())
+            .on_hover_text("Undo to the last snapshot")
+            .clicked()
+        {
+            app.document.undo_to_snapshot();
+        }
+
         if ui
             .button_with_enable("↺", app.document.can_undo())
             .clicked()
@@ -578,6 +618,24 @@ pub fn create_toolbar(app: &mut Application, ui: &mut Ui, extra: impl FnOnce(&mu
     }
 }
 
+/// Tell the user which of their changes could not be kept as they were, after they were
+/// rebased on changes made by others
+pub fn show_conflicts(conflicts: &[Conflict]) {
+    if conflicts.is_empty() {
+        return;
+    }
+    let mut text = "Some of your changes conflicted with changes made by others:\n".to_string();
+    for conflict in conflicts {
+        log::warn!("Conflicting change: {}", conflict);
+        text += &format!("\n{}", conflict);
+    }
+    rfd::MessageDialog::new()
+        .set_level(rfd::MessageLevel::Warning)
+        .set_title("Conflicting changes")
+        .set_description(text)
+        .show();
+}
+
 pub fn show_error<T>(result: io::Result<T>, description: &str) {
     if let Err(error) = result {
         let text = format!("{}\n\n{}", description.to_string(), error.to_string());
@@ -610,10 +668,7 @@ pub fn import_document_from_file(importer: &Importer) -> io::Result<Option<Docum
         .pick_file();
 
     if let Some(path) = path {
-        let mut file = File::open(path)?;
-        let repo = importer.import(&mut file);
-        let document = Document::new(repo?);
-        return Ok(Some(document));
+        return import_file(importer, &path).map(Some);
     }
 
     Ok(None)
@@ -660,7 +715,9 @@ pub fn new_document() -> Document {
     changes
         .add_node(id, NodeId::ROOT_NODE, 2)
         .set_name(id, "Third");
-    document.add_and_apply_changes(changes);
+    document
+        .add_and_apply_changes(changes)
+        .expect("Nodes are added in order");
     document
 }
 
@@ -670,14 +727,14 @@ mod tests {
     use binc::node_id::NodeId;
 
     fn setup_app() -> Application {
-        let mut app = Application::new();
+        let mut app = Application::new_with_document(Document::default());
         let mut changes = Changes::new();
         changes
             .add_node(NodeId::new(1), NodeId::ROOT_NODE, 0)
             .add_node(NodeId::new(2), NodeId::ROOT_NODE, 1)
             .add_node(NodeId::new(3), NodeId::new(1), 0);
 
-        app.document.add_and_apply_changes(changes);
+        app.document.add_and_apply_changes(changes).unwrap();
         app
     }
 
@@ -1,8 +1,10 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 #![allow(rustdoc::missing_crate_level_docs)]
 
+use binc::attributes::{attribute_type, format_size, AttributeValue};
+use binc::comments::{CommentThread, Comments};
 use binc::node_id::NodeId;
-use binc::node_store::Node;
+use binc::node_store::{Node, NodeStore};
 use binc::operation::Operation;
 use bincgui::app::{create_toolbar, Application, GuiAction};
 use bincgui::column::Columns;
@@ -19,6 +21,17 @@ struct ExplorerApp {
     tree: NodeTree,
     columns: Columns,
     use_tree: bool,
+    comment_editor: CommentEditor,
+}
+
+/// Text being written in the comment section of the inspector
+#[derive(Default)]
+struct CommentEditor {
+    node: NodeId,
+    draft: String,
+    reply_to: usize,
+    editing: Option<usize>,
+    edit_text: String,
 }
 
 impl ExplorerApp {
@@ -29,6 +42,7 @@ impl ExplorerApp {
             tree: NodeTree::new(),
             columns: Columns::new(),
             use_tree: true,
+            comment_editor: CommentEditor::default(),
         }
     }
 
@@ -118,7 +132,7 @@ impl ExplorerApp {
         ui.vertical(|ui| {
             if let Some(node) = self.application.get_selected_node() {
                 egui::Grid::new("inspector_grid")
-                    .num_columns(2)
+                    .num_columns(3)
                     .show(ui, |ui| {
                         ui.label("Inspector");
                         if ui.button("Delete Node").clicked() {
@@ -156,10 +170,39 @@ impl ExplorerApp {
 
                         for at in node.attributes.iter() {
                             ui.label(self.application.document.attribute_name(at.key));
-                            ui.label(format!("{}", at.value));
+                            let references = at.value.node_references();
+                            if !references.is_empty() {
+                                Self::reference_inspector(
+                                    ui,
+                                    &self.application.document.nodes,
+                                    references,
+                                    on_action,
+                                );
+                            } else if let AttributeValue::Bytes(bytes) = &at.value {
+                                Self::bytes_inspector(ui, at.key, bytes);
+                            } else if let Some(elements) = at.value.array_elements() {
+                                Self::array_inspector(ui, at.key, &at.value, &elements);
+                            } else {
+                                ui.label(format!("{}", at.value));
+                            }
+                            if ui
+                                .small_button("🗑")
+                                .on_hover_text("Remove attribute")
+                                .clicked()
+                            {
+                                on_action(GuiAction::WrappedChange {
+                                    change: Operation::RemoveAttribute {
+                                        node: node.id,
+                                        attribute: at.key,
+                                    },
+                                });
+                            }
                             ui.end_row();
                         }
                     });
+
+                ui.separator();
+                Self::comment_section(ui, node, &mut self.comment_editor, on_action);
             } else {
                 ui.vertical_centered(|ui| {
                     ui.label("No node selected");
@@ -167,6 +210,203 @@ impl ExplorerApp {
             }
         });
     }
+
+    fn comment_section(
+        ui: &mut Ui,
+        node: &Node,
+        editor: &mut CommentEditor,
+        on_action: &mut impl FnMut(GuiAction),
+    ) {
+        if editor.node != node.id {
+            *editor = CommentEditor {
+                node: node.id,
+                ..Default::default()
+            };
+        }
+
+        ui.label(format!("Comments ({})", node.comments.len()));
+        for thread in node.comments.threads() {
+            Self::comment_thread(ui, node.id, &thread, editor, on_action);
+        }
+
+        if editor.reply_to != Comments::NO_COMMENT {
+            ui.horizontal(|ui| {
+                ui.weak(format!("Replying to #{}", editor.reply_to));
+                if ui.small_button("Cancel").clicked() {
+                    editor.reply_to = Comments::NO_COMMENT;
+                }
+            });
+        }
+        ui.text_edit_multiline(&mut editor.draft);
+        if ui.button("Post").clicked() && !editor.draft.trim().is_empty() {
+            on_action(GuiAction::WrappedChange {
+                change: Operation::AddComment {
+                    node: node.id,
+                    comment: std::mem::take(&mut editor.draft),
+                    author: Application::get_author(),
+                    response_to: editor.reply_to,
+                },
+            });
+            editor.reply_to = Comments::NO_COMMENT;
+        }
+    }
+
+    fn comment_thread(
+        ui: &mut Ui,
+        node: NodeId,
+        thread: &CommentThread,
+        editor: &mut CommentEditor,
+        on_action: &mut impl FnMut(GuiAction),
+    ) {
+        let comment = thread.comment;
+        ui.group(|ui| {
+            ui.horizontal(|ui| {
+                ui.strong(&comment.author);
+                ui.weak(format!("#{}", comment.id));
+                if comment.edited && !comment.retracted {
+                    ui.weak("(edited)");
+                }
+            });
+
+            if comment.retracted {
+                ui.weak("Comment retracted");
+            } else if editor.editing == Some(comment.id) {
+                ui.text_edit_multiline(&mut editor.edit_text);
+                ui.horizontal(|ui| {
+                    if ui.small_button("Save").clicked() {
+                        on_action(GuiAction::WrappedChange {
+                            change: Operation::EditComment {
+                                node,
+                                comment: comment.id,
+                                text: std::mem::take(&mut editor.edit_text),
+                            },
+                        });
+                        editor.editing = None;
+                    }
+                    if ui.small_button("Cancel").clicked() {
+                        editor.editing = None;
+                    }
+                });
+            } else {
+                ui.label(&comment.text);
+                ui.horizontal(|ui| {
+                    if ui.small_button("Reply").clicked() {
+                        editor.reply_to = comment.id;
+                    }
+                    if ui.small_button("Edit").clicked() {
+                        editor.editing = Some(comment.id);
+                        editor.edit_text = comment.text.clone();
+                    }
+                    if ui.small_button("Retract").clicked() {
+                        on_action(GuiAction::WrappedChange {
+                            change: Operation::RetractComment {
+                                node,
+                                comment: comment.id,
+                            },
+                        });
+                    }
+                });
+            }
+
+            if !thread.replies.is_empty() {
+                ui.indent(("replies", comment.id), |ui| {
+                    for reply in &thread.replies {
+                        Self::comment_thread(ui, node, reply, editor, on_action);
+                    }
+                });
+            }
+        });
+    }
+
+    fn reference_inspector(
+        ui: &mut Ui,
+        nodes: &NodeStore,
+        references: &[NodeId],
+        on_action: &mut impl FnMut(GuiAction),
+    ) {
+        ui.horizontal_wrapped(|ui| {
+            for target in references {
+                match nodes.resolve(*target) {
+                    Some(node) => {
+                        let label = match node.get_name() {
+                            Some(name) => format!("{} (#{})", name, target),
+                            None => format!("#{}", target),
+                        };
+                        if ui.link(label).clicked() {
+                            on_action(GuiAction::SelectNode { node: *target });
+                        }
+                    }
+                    None => {
+                        ui.colored_label(ui.visuals().error_fg_color, format!("#{}", target))
+                            .on_hover_text("This node has been removed");
+                    }
+                }
+            }
+        });
+    }
+
+    fn bytes_inspector(ui: &mut Ui, key: usize, bytes: &[u8]) {
+        const BYTES_PER_ROW: usize = 16;
+        const MAX_ROWS: usize = 256;
+
+        egui::CollapsingHeader::new(format_size(bytes.len()))
+            .id_salt(("bytes_attribute", key))
+            .show(ui, |ui| {
+                if let Ok(text) = std::str::from_utf8(bytes) {
+                    ui.label(text);
+                    ui.separator();
+                }
+                for (row, chunk) in bytes.chunks(BYTES_PER_ROW).take(MAX_ROWS).enumerate() {
+                    let hex = chunk
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let ascii: String = chunk
+                        .iter()
+                        .map(|&b| {
+                            if b.is_ascii_graphic() || b == b' ' {
+                                b as char
+                            } else {
+                                '.'
+                            }
+                        })
+                        .collect();
+                    ui.monospace(format!(
+                        "{:08x}  {:<47}  {}",
+                        row * BYTES_PER_ROW,
+                        hex,
+                        ascii
+                    ));
+                }
+                let shown = MAX_ROWS * BYTES_PER_ROW;
+                if bytes.len() > shown {
+                    ui.weak(format!("… {} more", format_size(bytes.len() - shown)));
+                }
+            });
+    }
+
+    fn array_inspector(ui: &mut Ui, key: usize, value: &AttributeValue, elements: &[String]) {
+        const MAX_ROWS: usize = 1000;
+
+        let title = format!("{} × {}", attribute_type(value), elements.len());
+        egui::CollapsingHeader::new(title)
+            .id_salt(("array_attribute", key))
+            .show(ui, |ui| {
+                egui::Grid::new(("array_grid", key))
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        for (index, element) in elements.iter().take(MAX_ROWS).enumerate() {
+                            ui.weak(index.to_string());
+                            ui.label(element);
+                            ui.end_row();
+                        }
+                    });
+                if elements.len() > MAX_ROWS {
+                    ui.weak(format!("… {} more", elements.len() - MAX_ROWS));
+                }
+            });
+    }
 }
 
 impl eframe::App for ExplorerApp {
@@ -27,7 +27,50 @@ fn main() -> eframe::Result {
 struct IssuesApp {
     application: Application,
     search_string: String,
+    sort_order: SortOrder,
     found_issues: Vec<NodeId>,
+    /// Number of issues found that don't have the date they are sorted by
+    missing_dates: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortOrder {
+    Newest,
+    Created,
+    Updated,
+    Due,
+}
+
+impl SortOrder {
+    const ALL: [SortOrder; 4] = [SortOrder::Newest, SortOrder::Created, SortOrder::Updated, SortOrder::Due];
+
+    fn label(&self) -> &'static str {
+        match self {
+            SortOrder::Newest => "Newest",
+            SortOrder::Created => "Created",
+            SortOrder::Updated => "Updated",
+            SortOrder::Due => "Due date",
+        }
+    }
+
+    fn attribute(&self) -> Option<&'static str> {
+        match self {
+            SortOrder::Newest => None,
+            SortOrder::Created => Some("created"),
+            SortOrder::Updated => Some("updated"),
+            SortOrder::Due => Some("due"),
+        }
+    }
+
+    /// Shown for issues that don't have the attribute, as a timestamp or date
+    fn missing_label(&self) -> &'static str {
+        match self {
+            SortOrder::Newest => "",
+            SortOrder::Created => "No creation date",
+            SortOrder::Updated => "No update date",
+            SortOrder::Due => "No due date",
+        }
+    }
 }
 
 impl IssuesApp {
@@ -52,12 +95,42 @@ impl IssuesApp {
         Self {
             application: Application::new(),
             search_string: String::new(),
+            sort_order: SortOrder::Newest,
             found_issues: vec![],
+            missing_dates: 0,
         }
     }
 
     fn update_search(&mut self) {
-        self.found_issues = self.get_issues_for_search(&self.search_string, 30);
+        const LIMIT: usize = 30;
+        if let Some(attribute) = self.sort_order.attribute() {
+            let mut issues = self.get_issues_for_search(&self.search_string, usize::MAX);
+            self.missing_dates = issues.iter().filter(|id| self.date_millis(**id, attribute).is_none()).count();
+            self.sort_issues(&mut issues, attribute);
+            issues.truncate(LIMIT);
+            self.found_issues = issues;
+        } else {
+            self.missing_dates = 0;
+            self.found_issues = self.get_issues_for_search(&self.search_string, LIMIT);
+        }
+    }
+
+    /// The date in the attribute, if the issue has it as a timestamp or date
+    fn date_millis(&self, id: NodeId, attribute: &str) -> Option<i64> {
+        let nodes = &self.application.document.nodes;
+        let key = nodes.attribute_names.get_index(attribute)?;
+        nodes.get(id)?.get_attribute(key)?.as_timestamp().map(|date| date.timestamp_millis())
+    }
+
+    /// Most recent first, except due dates where the closest comes first. Issues without the date go last.
+    fn sort_issues(&self, issues: &mut [NodeId], attribute: &str) {
+        issues.sort_by_cached_key(|id| {
+            match (self.date_millis(*id, attribute), self.sort_order) {
+                (Some(date), SortOrder::Due) => (false, date),
+                (Some(date), _) => (false, -date),
+                (None, _) => (true, 0),
+            }
+        });
     }
 
     fn get_issues_for_search(&self, search_string: &str, limit: usize) -> Vec<NodeId> {
@@ -110,7 +183,20 @@ impl eframe::App for IssuesApp {
         egui::TopBottomPanel::top("toolbar")
             .frame(frame)
             .show(ctx, |ui| {
-                create_toolbar(&mut self.application, ui, |ui| {});
+                let mut sort_order = self.sort_order;
+                create_toolbar(&mut self.application, ui, |ui| {
+                    egui::ComboBox::from_label("Sort by")
+                        .selected_text(sort_order.label())
+                        .show_ui(ui, |ui| {
+                            for order in SortOrder::ALL {
+                                ui.selectable_value(&mut sort_order, order, order.label());
+                            }
+                        });
+                });
+                if sort_order != self.sort_order {
+                    self.sort_order = sort_order;
+                    self.update_search();
+                }
             });
 
         egui::CentralPanel::default().show(ctx, |ui| {
@@ -122,6 +208,10 @@ impl eframe::App for IssuesApp {
 
                 ui.separator();
 
+                if self.missing_dates > 0 {
+                    ui.weak(format!("{} on {} of the issues found, listed last", self.sort_order.missing_label(), self.missing_dates));
+                }
+
                 let f = egui::Frame::default()
                     .inner_margin(4.0)
                     .corner_radius(4)
@@ -147,6 +237,15 @@ impl eframe::App for IssuesApp {
                             ui.hyperlink_to(key, url);
                             ui.label(label);
                             ui.label(assignee);
+                            if let Some(attribute) = self.sort_order.attribute() {
+                                let date = self.application.document.nodes.attribute_names.get_index(attribute)
+                                    .and_then(|key| node.get_attribute(key))
+                                    .filter(|value| value.as_timestamp().is_some());
+                                match date {
+                                    Some(date) => ui.label(date.to_string()),
+                                    None => ui.weak(self.sort_order.missing_label()),
+                                };
+                            }
                         })
                     });
                 }
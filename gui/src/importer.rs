@@ -1,8 +1,16 @@
+use binc::attributes::AttributeValue;
 use binc::changes::Changes;
+use binc::document::Document;
+use binc::error::InvalidOperation;
 use binc::journal::Journal;
 use binc::node_id::{NodeId, NodeIdGenerator};
+use binc::operation::Operation;
+use binc::sources::SourceStatus;
+use std::collections::HashMap;
+use std::fs;
 use std::io;
 use std::io::Read;
+use std::path::Path;
 use xml::reader::XmlEvent;
 use xml::EventReader;
 
@@ -38,6 +46,226 @@ impl Import for Importer {
     }
 }
 
+/// Attributes that tie an imported node to the source it came from and where it is in the
+/// file, so a re-import finds it again
+const SOURCE_ATTRIBUTE: &str = "source";
+const SOURCE_KEY_ATTRIBUTE: &str = "source key";
+
+/// Import a file into a new document, and register the file as the source of its data
+pub fn import_file(importer: &Importer, path: &Path) -> io::Result<Document> {
+    let mut document = Document::default();
+    reimport_file(importer, path, &mut document)?;
+    Ok(document)
+}
+
+/// Import a file into a document, which may have been imported from it before. If the file
+/// is the same as the last time, nothing changes. Otherwise the nodes that came from the file
+/// are made to match it, in one undo step. Nodes are found again by where they are in the
+/// file, see `source_keys`, and types and attributes are matched by name. Other nodes are left
+/// as they are, unless they are under a node that is no longer in the file.
+pub fn reimport_file(
+    importer: &Importer,
+    path: &Path,
+    document: &mut Document,
+) -> io::Result<SourceStatus> {
+    let data = fs::read(path)?;
+    let path = path.to_string_lossy();
+    if let Some(id) = document.sources().find(importer.get_name(), &path)
+        && document.sources().get(id).is_some_and(|s| s.matches(&data))
+    {
+        return Ok(SourceStatus::Unchanged(id));
+    }
+    let imported = Document::new(importer.import(&mut data.as_slice())?);
+
+    document.begin_transaction();
+    let status = document.register_source(importer.get_name(), &path, &data);
+    let source = match status {
+        SourceStatus::Added(id) | SourceStatus::Updated(id) | SourceStatus::Unchanged(id) => id,
+    };
+    match update_from_source(document, source, &imported) {
+        Ok(()) => {
+            document.commit_transaction();
+            Ok(status)
+        }
+        Err(e) => {
+            document.rollback_transaction();
+            Err(io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+    }
+}
+
+/// Make the nodes that came from `source` match the ones in `imported`
+fn update_from_source(
+    document: &mut Document,
+    source: usize,
+    imported: &Document,
+) -> Result<(), InvalidOperation> {
+    let source_attribute = document.get_or_define_attribute_id(SOURCE_ATTRIBUTE);
+    let key_attribute = document.get_or_define_attribute_id(SOURCE_KEY_ATTRIBUTE);
+    let source_value = AttributeValue::U64(source as u64);
+
+    let mut existing: HashMap<String, NodeId> = document
+        .nodes
+        .nodes()
+        .iter()
+        .filter(|n| n.id.exists() && n.get_attribute(source_attribute) == Some(&source_value))
+        .filter_map(|n| Some((n.get_string_attribute(key_attribute)?.to_string(), n.id)))
+        .collect();
+
+    let mut mapped = HashMap::from([(NodeId::ROOT_NODE, NodeId::ROOT_NODE)]);
+    // The last node from the file placed in each parent, so the next one goes after it
+    let mut previous = HashMap::<NodeId, NodeId>::new();
+    for (imported_id, key) in source_keys(imported) {
+        let node = imported
+            .nodes
+            .get(imported_id)
+            .expect("Keys are for existing nodes");
+        let parent = mapped[&node.parent];
+        let type_name = node
+            .get_type()
+            .and_then(|t| imported.nodes.type_names.get(t));
+        let node_type = match type_name {
+            Some(name) => Some(define_type(document, name)?),
+            None => None,
+        };
+        let index = match previous.get(&parent) {
+            Some(previous) => child_index(document, parent, *previous) + 1,
+            None => 0,
+        };
+
+        let id = match existing.remove(&key) {
+            Some(id) => {
+                let current = document.nodes.get(id).expect("Node exists");
+                let current_type = current.get_type();
+                let in_order = current.parent == parent
+                    && previous.get(&parent).is_none_or(|previous| {
+                        child_index(document, parent, id) > child_index(document, parent, *previous)
+                    });
+                if !in_order {
+                    document.add_and_apply(Operation::MoveNode {
+                        id,
+                        new_parent: parent,
+                        index_in_new_parent: index,
+                    })?;
+                }
+                if let Some(node_type) = node_type
+                    && current_type != Some(node_type)
+                {
+                    document.add_and_apply(Operation::SetType {
+                        node: id,
+                        type_id: node_type,
+                    })?;
+                }
+                id
+            }
+            None => {
+                let id = document.next_id();
+                document.add_and_apply(Operation::AddNode {
+                    id,
+                    node_type: node_type.unwrap_or(0),
+                    parent,
+                    index_in_parent: index,
+                })?;
+                document.add_and_apply(Operation::SetAttribute {
+                    node: id,
+                    attribute: source_attribute,
+                    value: source_value.clone(),
+                })?;
+                document.add_and_apply(Operation::SetAttribute {
+                    node: id,
+                    attribute: key_attribute,
+                    value: AttributeValue::String(key),
+                })?;
+                id
+            }
+        };
+
+        // The file has all attributes of the nodes from it, besides the ones tying them to it
+        let mut attributes = vec![source_attribute, key_attribute];
+        for entry in node.attributes.iter() {
+            let attribute =
+                document.get_or_define_attribute_id(&imported.attribute_name(entry.key));
+            attributes.push(attribute);
+            let current = document.nodes.get(id).expect("Node exists");
+            if current.get_attribute(attribute) != Some(&entry.value) {
+                document.add_and_apply(Operation::SetAttribute {
+                    node: id,
+                    attribute,
+                    value: entry.value.clone(),
+                })?;
+            }
+        }
+        let current = document.nodes.get(id).expect("Node exists");
+        let removed: Vec<usize> = current
+            .attributes
+            .iter()
+            .map(|entry| entry.key)
+            .filter(|key| !attributes.contains(key))
+            .collect();
+        for attribute in removed {
+            document.add_and_apply(Operation::RemoveAttribute {
+                node: id,
+                attribute,
+            })?;
+        }
+
+        mapped.insert(imported_id, id);
+        previous.insert(parent, id);
+    }
+
+    // Nodes that are no longer in the file. Ones under another of them are already gone.
+    for id in existing.into_values() {
+        if document.nodes.resolve(id).is_some() {
+            document.add_and_apply(Operation::RemoveNode { id })?;
+        }
+    }
+    Ok(())
+}
+
+/// Every node in `imported` with a key saying where it is in the file, parents before their
+/// children and children in order. A key is the type and the number of siblings of the same
+/// type before it, after the key of the parent, so the same node gets the same key when other
+/// nodes are added or removed elsewhere in the file.
+fn source_keys(imported: &Document) -> Vec<(NodeId, String)> {
+    let mut keys = vec![];
+    let mut pending = vec![(NodeId::ROOT_NODE, String::new())];
+    while let Some((parent, parent_key)) = pending.pop() {
+        let mut counts = HashMap::<Option<usize>, usize>::new();
+        let mut children = vec![];
+        for child in &imported.nodes.get(parent).expect("Parent exists").children {
+            let node_type = imported.nodes.get(*child).and_then(|n| n.get_type());
+            let count = counts.entry(node_type).or_default();
+            let type_name = node_type.and_then(|t| imported.nodes.type_names.get(t));
+            let key = format!("{}/{}[{}]", parent_key, type_name.unwrap_or(""), count);
+            *count += 1;
+            keys.push((*child, key.clone()));
+            children.push((*child, key));
+        }
+        pending.extend(children.into_iter().rev());
+    }
+    keys
+}
+
+fn child_index(document: &Document, parent: NodeId, child: NodeId) -> usize {
+    let children = &document.nodes.get(parent).expect("Parent exists").children;
+    children
+        .iter()
+        .position(|c| *c == child)
+        .expect("Child of parent")
+}
+
+fn define_type(document: &mut Document, name: &str) -> Result<usize, InvalidOperation> {
+    if let Some(id) = document.nodes.type_names.get_index(name) {
+        return Ok(id);
+    }
+    let id = document.nodes.type_names.len();
+    document.add_and_apply(Operation::DefineTypeName {
+        id,
+        name: name.to_string(),
+    })?;
+    Ok(id)
+}
+
 fn import_xml<R: Read>(reader: &mut R) -> io::Result<Journal> {
     let parser = EventReader::new(reader);
     let mut changes = Changes::new();
@@ -109,7 +337,7 @@ fn import_xml<R: Read>(reader: &mut R) -> io::Result<Journal> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use binc::document::Document;
+    use binc::builder::NodeBuilder;
     use std::io::{BufReader, Cursor};
 
     #[test]
@@ -126,6 +354,79 @@ mod tests {
         assert!(result.is_ok());
 
         let document = Document::new(result.unwrap());
-        // Add more assertions to verify the contents of the journal
+        let root = document.find_roots()[0];
+        let root_type = document.nodes.get(root).unwrap().get_type();
+        assert_eq!(document.type_name(root_type), "root");
+    }
+
+    #[test]
+    fn test_import_file_registers_source() {
+        let path = std::env::temp_dir().join("binc_test_import_file_registers_source.xml");
+        fs::write(&path, "<root><child/></root>").unwrap();
+
+        let mut document = import_file(&Importer::XML, &path).unwrap();
+        let (_, source) = document.sources().iter().next().unwrap();
+        assert_eq!(source.importer, "XML");
+        assert_eq!(source.path, path.to_string_lossy());
+
+        let path_string = path.to_string_lossy().to_string();
+        let data = fs::read(&path).unwrap();
+        assert_eq!(
+            document.register_source("XML", &path_string, &data),
+            SourceStatus::Unchanged(0)
+        );
+        assert_eq!(
+            document.register_source("XML", &path_string, b"<root/>"),
+            SourceStatus::Updated(0)
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_reimport_file() {
+        let path = std::env::temp_dir().join("binc_test_reimport_file.xml");
+        fs::write(&path, r#"<root><child a="1"/><gone/></root>"#).unwrap();
+
+        // The document has a node and an attribute of its own before the import
+        let mut document = Document::default();
+        let own = document.add_node("note", NodeId::ROOT_NODE);
+        document.set_node_attribute(own, "priority", AttributeValue::U8(1));
+        let priority = document.get_or_define_attribute_id("priority");
+        assert_eq!(
+            reimport_file(&Importer::XML, &path, &mut document).unwrap(),
+            SourceStatus::Added(0)
+        );
+        let root = document.find_roots()[0];
+        let child = document.nodes.get(root).unwrap().children[0];
+        let revision = document.num_operations();
+        assert_eq!(
+            reimport_file(&Importer::XML, &path, &mut document).unwrap(),
+            SourceStatus::Unchanged(0)
+        );
+        assert_eq!(document.num_operations(), revision);
+
+        fs::write(&path, r#"<root><new/><child a="2"/></root>"#).unwrap();
+        assert_eq!(
+            reimport_file(&Importer::XML, &path, &mut document).unwrap(),
+            SourceStatus::Updated(0)
+        );
+        assert_eq!(document.find_roots(), &vec![root, own]);
+        assert_eq!(document.attribute_name(priority), "priority");
+        let children = &document.nodes.get(root).unwrap().children;
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[1], child);
+        let a = document.nodes.attribute_names.get_index("a").unwrap();
+        assert_eq!(
+            document.nodes.get(child).unwrap().get_string_attribute(a),
+            Some("2")
+        );
+        assert_eq!(document.sources().iter().count(), 1);
+
+        // The whole re-import is one undo step
+        document.undo();
+        let children = &document.nodes.get(root).unwrap().children;
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0], child);
+        fs::remove_file(path).unwrap();
     }
 }
@@ -1,39 +1,48 @@
 use binc::client::Client;
 use binc::document::Document;
+use binc::journal::Journal;
+use binc::merge::Conflict;
 use binc::network_protocol::{NetworkRequest, NetworkResponse};
+use binc::node_id::NodeId;
 use std::io;
 
 pub struct PersistentClient {
     pub client: Client,
     current_pos: u64,
     path: String,
+    /// Who node ids are reserved for
+    client_name: String,
 }
 
 impl PersistentClient {
+    /// Number of node ids to reserve at a time. More are reserved when half of them are used.
+    const ID_BLOCK_SIZE: u64 = 1024;
+    /// How many times the local operations are rebased and sent again before giving up
+    const MAX_COMMIT_ATTEMPTS: usize = 5;
+
     pub fn connect_to_document(url: &str) -> io::Result<(PersistentClient, Document)> {
         if let Some((host, path)) = url.split_once('/') {
             if let Ok(mut client) = Client::new(host) {
-                if let Ok(repo) = client
+                match client
                     .request(NetworkRequest::GetFileData {
                         from: 0,
                         path: path.to_string(),
                     })?
                     .as_journal()
                 {
-                    let document = Document::new(repo);
-                    Ok((
-                        PersistentClient {
+                    Ok(repo) => {
+                        let mut document = Document::new(repo);
+                        document.set_committed(document.num_operations());
+                        let mut persistent_client = PersistentClient {
                             client,
                             current_pos: document.num_operations() as u64,
                             path: path.to_string(),
-                        },
-                        document,
-                    ))
-                } else {
-                    Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        "Failed to get file data",
-                    ))
+                            client_name: whoami::username(),
+                        };
+                        persistent_client.check_for_updates(&mut document)?;
+                        Ok((persistent_client, document))
+                    }
+                    Err(e) => Err(io::Error::other(format!("Failed to get file data: {}", e))),
                 }
             } else {
                 Err(io::Error::new(
@@ -46,23 +55,85 @@ impl PersistentClient {
         }
     }
 
-    pub fn check_for_updates(&mut self, document: &mut Document) -> io::Result<()> {
+    /// Get the operations added by others, and reserve more node ids if this client is running
+    /// out of them, so the nodes it adds never have the same ids as nodes added by others.
+    /// Local operations that are not committed yet are rebased on top of the new ones, and
+    /// whatever conflicted with them is returned.
+    pub fn check_for_updates(&mut self, document: &mut Document) -> io::Result<Vec<Conflict>> {
+        let mut conflicts = self.fetch_updates(document)?;
+
+        let reserved_left = document.node_id_generator.reserved_left() as u64;
+        if reserved_left < Self::ID_BLOCK_SIZE / 2 {
+            self.reserve_node_ids(document)?;
+            conflicts.extend(self.fetch_updates(document)?);
+        }
+        Ok(conflicts)
+    }
+
+    fn reserve_node_ids(&mut self, document: &mut Document) -> io::Result<()> {
+        let response = self.client.request(NetworkRequest::ReserveNodeIds {
+            path: self.path.clone(),
+            client: self.client_name.clone(),
+            count: Self::ID_BLOCK_SIZE,
+        })?;
+        match response {
+            NetworkResponse::ReserveNodeIds { first, count } => {
+                if first == 0 || first.saturating_add(count) > NodeId::NO_NODE_ID as u64 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid node id block {}..{}", first, first + count),
+                    ));
+                }
+                document.use_reserved_ids(NodeId::new(first as usize), count as usize);
+                Ok(())
+            }
+            NetworkResponse::Error { message } => Err(io::Error::other(message)),
+            _ => Err(io::Error::new(io::ErrorKind::Other, "Invalid response")),
+        }
+    }
+
+    /// Get the operations added after `current_pos`. The local operations after it are pending,
+    /// and are rebased on top of the ones fetched.
+    fn fetch_updates(&mut self, document: &mut Document) -> io::Result<Vec<Conflict>> {
+        self.check_revision(document)?;
         if let Ok(response) = self.client.request(NetworkRequest::GetFileData {
             path: self.path.clone(),
-            from: self.current_pos as u64,
+            from: self.current_pos,
         }) {
             match response {
-                NetworkResponse::GetFileData { from, to, data } => {
+                NetworkResponse::GetFileData {
+                    from,
+                    to,
+                    document_id,
+                    data,
+                } => {
+                    // Compacting renumbers the operations, and gives the document a new id
+                    if document_id != document.journal.header.document_id {
+                        return Err(io::Error::new(
+                            io::ErrorKind::Other,
+                            "The document was rewritten on the server, open it again",
+                        ));
+                    }
                     if from != self.current_pos {
                         return Err(io::Error::new(io::ErrorKind::Other, "Revision mismatch"));
                     }
+                    let mut conflicts = vec![];
                     if to > from {
-                        document.append_and_apply(&mut data.as_slice())?;
+                        let pending = self.current_pos as usize;
+                        if pending == document.num_operations() {
+                            document.append_and_apply(&mut data.as_slice())?;
+                        } else {
+                            let mut remote = Journal::new();
+                            remote.append(&mut data.as_slice())?;
+                            conflicts = document.rebase(pending, &remote.operations);
+                        }
                         self.current_pos = to;
+                        document.set_committed(to as usize);
                     }
 
-                    Ok(())
+                    Ok(conflicts)
                 }
+                NetworkResponse::Error { message } => Err(io::Error::other(message)),
                 _ => Err(io::Error::new(io::ErrorKind::Other, "Invalid response")),
             }
         } else {
@@ -73,36 +144,80 @@ impl PersistentClient {
         }
     }
 
-    pub fn commit_changes(&mut self, document: &Document) -> io::Result<()> {
-        let from = self.current_pos;
-        let to = document.journal.operations.len() as u64;
+    /// The operations up to `current_pos` are the ones the server has, so the document must
+    /// still have all of them
+    fn check_revision(&self, document: &Document) -> io::Result<()> {
+        if self.current_pos > document.num_operations() as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The document has {} operations, but {} are committed",
+                    document.num_operations(),
+                    self.current_pos
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Send the local operations to the server. If others added operations first, the local
+    /// ones are rebased on top of theirs and sent again. Returns whatever conflicted.
+    pub fn commit_changes(&mut self, document: &mut Document) -> io::Result<Vec<Conflict>> {
+        let mut conflicts = vec![];
+        // Undone operations are not part of the document as it is shown, so they are not sent
+        document.discard_redo();
+        for _ in 0..Self::MAX_COMMIT_ATTEMPTS {
+            self.check_revision(document)?;
+            let from = self.current_pos;
+            let to = document.num_operations() as u64;
+            if to <= from {
+                break;
+            }
 
-        if to > from {
             let mut data = vec![];
-            let mut index = 0;
-            for change in &document.journal.operations {
-                if index >= from as usize {
-                    change.write(&mut data)?;
-                }
-                index += 1;
+            for operation in &document.journal.operations[from as usize..] {
+                operation.write(&mut data)?;
             }
             let response = self.client.request(NetworkRequest::AppendFile {
                 from,
                 to,
                 path: self.path.clone(),
+                document_id: document.journal.header.document_id,
                 data,
             })?;
             match response {
                 NetworkResponse::AppendFile { result } => match result {
-                    Ok(()) => {
+                    Ok(revision) => {
+                        // The server has these operations now, so they can't be merged into
+                        document.end_coalescing();
                         self.current_pos = to;
+                        document.set_committed(to as usize);
+                        // Get what the server added after them, like its checksum, so the
+                        // next commit starts at the revision the file is at
+                        if revision > to {
+                            conflicts.extend(self.fetch_updates(document)?);
+                        }
+                        return Ok(conflicts);
+                    }
+                    Err(e) => {
+                        // Usually someone else appended first. If nothing new was fetched,
+                        // sending the same operations again won't help.
+                        conflicts.extend(self.fetch_updates(document)?);
+                        if self.current_pos == from {
+                            return Err(io::Error::new(io::ErrorKind::Other, e));
+                        }
                     }
-                    Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e)),
                 },
                 _ => return Err(io::Error::new(io::ErrorKind::Other, "Invalid response")),
             }
         }
 
-        Ok(())
+        if self.current_pos < document.num_operations() as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Others kept changing the document, try again later",
+            ));
+        }
+        Ok(conflicts)
     }
 }